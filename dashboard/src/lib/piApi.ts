@@ -1,11 +1,50 @@
 const baseUrl = "http://pi-grow.local:3000"
 
-export type Sensors = {
-    temperature: number
-    humidity: number
+export type Quantity =
+    | "temperature"
+    | "humidity"
+    | "pressure"
+    | "soil_moisture"
+    | "co2"
+    | "illuminance"
+    | "ppfd"
+    | "vpd"
+    | "leaf_vpd"
+    | "dew_point"
+    | "absolute_humidity"
+    | "heat_index"
+
+export type Value = {
+    quantity: Quantity
+    value: number
+    unit: string
+}
+
+export type Units = {
+    temperature: "celsius" | "fahrenheit"
+    pressure: "hpa" | "kpa" | "inhg"
+}
+
+export type SensorData = {
+    values: Value[]
+}
+
+export type Sensor = {
+    id: string
+    type: string
+    connection:
+        | { pin: number }
+        | { i2c: { bus: number, address: number } }
+        | { iio: { device: string } }
+        | { one_wire: { root: string, device: string } }
+        | { serial: { port: string } }
+    interval_secs: number
+    latest: SensorData | null
+    dli?: number
 }
 
-export async function sensorState(): Promise<Sensors> {
-    const response = await fetch(`${baseUrl}/sensors`)
-    return response.json() as Promise<Sensors>
-}
\ No newline at end of file
+export async function sensorState(units?: Partial<Units>): Promise<Sensor[]> {
+    const query = new URLSearchParams(units ?? {})
+    const response = await fetch(`${baseUrl}/sensors?${query}`)
+    return response.json() as Promise<Sensor[]>
+}
@@ -31,9 +31,9 @@ impl<T, const N: usize> Circular<T, N> {
         &self.data[self.current]
     }
 
-    pub fn iter<'a>(&'a self) -> impl Iterator<Item = &'a T> {
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
         CircularIterator {
-            circular: &self,
+            circular: self,
             current: 0,
         }
     }
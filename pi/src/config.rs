@@ -0,0 +1,338 @@
+//! Settings of the grow controller that are loaded from a JSON file.
+
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::humidity::{
+    AdcSettings, Connection, Corrections, ReadMode, RetryPolicy, SensorType, ShtSettings,
+    SoilCalibration, Spectrum, ValidationPolicy,
+};
+
+/// The I2C bus on pins 3 and 5 of the Raspberry Pi header
+const DEFAULT_I2C_BUS: u8 = 1;
+/// Where the kernel lists its Industrial I/O devices
+const IIO_ROOT: &str = "/sys/bus/iio/devices";
+/// Where the kernel lists the devices found by its 1-Wire bus masters
+const DEFAULT_W1_ROOT: &str = "/sys/bus/w1/devices";
+/// The primary UART on pins 8 and 10 of the Raspberry Pi header
+const DEFAULT_SERIAL_PORT: &str = "/dev/serial0";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// The sensors to track, in the order they are listed by the API.
+    pub sensors: Vec<SensorConfig>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            sensors: vec![SensorConfig {
+                id: "humidity".to_owned(),
+                sensor_type: SensorType::Dht22,
+                pin: Some(23),
+                bus: None,
+                address: None,
+                iio_device: None,
+                device: None,
+                w1_root: None,
+                serial_port: None,
+                interval_secs: 2,
+                read_mode: None,
+                retry: None,
+                validation: None,
+                sht: None,
+                condensation_humidity: None,
+                adc: None,
+                soil: None,
+                self_calibration: None,
+                spectrum: None,
+                leaf_temperature_offset: None,
+                calibration: Corrections::new(),
+            }],
+        }
+    }
+}
+
+impl Config {
+    /// Reads a config from a JSON file, missing fields use the defaults.
+    pub fn load(path: &Path) -> Result<Self> {
+        Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    /// Writes the config as JSON, replacing the file only once it is written completely.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let temporary = path.with_extension("json.tmp");
+        fs::write(&temporary, serde_json::to_string_pretty(self)? + "\n")
+            .with_context(|| format!("Could not write {}", temporary.display()))?;
+        fs::rename(&temporary, path)
+            .with_context(|| format!("Could not replace {}", path.display()))?;
+        Ok(())
+    }
+}
+
+/// A sensor and how it is read.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SensorConfig {
+    /// Stable name used in the API, e.g. `canopy` or `intake`.
+    pub id: String,
+    #[serde(rename = "type")]
+    pub sensor_type: SensorType,
+    /// The GPIO pin the data line of a single-wire sensor is connected to.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pin: Option<u8>,
+    /// The I2C bus of an I2C sensor, defaults to bus 1.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bus: Option<u8>,
+    /// The address of an I2C sensor, defaults to the sensor type's usual address.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub address: Option<u8>,
+    /// Reads a DHT sensor through the kernel's `dht11` driver instead of its `pin`, e.g.
+    /// `iio:device0` or a full path to a directory with the same files.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub iio_device: Option<PathBuf>,
+    /// The id of a 1-Wire sensor, e.g. `28-0316a2799fff`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device: Option<String>,
+    /// The sysfs directory listing the 1-Wire devices, defaults to `/sys/bus/w1/devices`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub w1_root: Option<PathBuf>,
+    /// The serial port of a UART sensor, defaults to `/dev/serial0`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub serial_port: Option<PathBuf>,
+    /// Seconds between readings.
+    #[serde(default = "default_interval_secs")]
+    pub interval_secs: u64,
+    /// Overrides `--dht-read-mode` for this sensor.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub read_mode: Option<ReadMode>,
+    /// Overrides the `--retry-*` options for this sensor.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry: Option<RetryPolicy>,
+    /// Overrides the `--validation-*` options for this sensor.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub validation: Option<ValidationPolicy>,
+    /// Measurement mode and precision of an SHT sensor.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sht: Option<ShtSettings>,
+    /// Humidity in percent at which the heater of an SHT sensor is pulsed to drive off condensation.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub condensation_humidity: Option<f32>,
+    /// Channel, gain and data rate of the ADS1115 a soil moisture probe is read through.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub adc: Option<AdcSettings>,
+    /// Voltages a soil moisture probe outputs in dry air and in water.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub soil: Option<SoilCalibration>,
+    /// Turns the automatic baseline correction of a CO2 sensor on or off, otherwise the
+    /// sensor keeps its own setting.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub self_calibration: Option<bool>,
+    /// The light source a light sensor sees, which sets how its lux convert to PPFD, defaults
+    /// to sunlight.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub spectrum: Option<Spectrum>,
+    /// How much warmer than the air the leaves are for the leaf VPD, in degrees Celsius,
+    /// defaults to -2 as leaves transpiring under lights are usually cooler.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub leaf_temperature_offset: Option<f32>,
+    /// Gain and offset correcting each measured quantity, e.g. `{"humidity": {"gain": 1.0,
+    /// "offset": -2.5}}`, updated when calibration points are captured through the API.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub calibration: Corrections,
+}
+
+impl SensorConfig {
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs(self.interval_secs)
+    }
+
+    /// How the sensor is wired, filling in the defaults for I2C, 1-Wire and UART sensors.
+    pub fn connection(&self) -> Result<Connection> {
+        if let Some(device) = &self.iio_device {
+            // Joining an absolute path replaces the root
+            return Ok(Connection::Iio {
+                device: Path::new(IIO_ROOT).join(device),
+            });
+        }
+        if self.sensor_type == SensorType::Ds18b20 {
+            let device = self.device.clone().with_context(|| {
+                format!(
+                    "Sensor {} needs a 1-Wire device id, e.g. 28-0316a2799fff",
+                    self.id
+                )
+            })?;
+            return Ok(Connection::OneWire {
+                root: self
+                    .w1_root
+                    .clone()
+                    .unwrap_or_else(|| DEFAULT_W1_ROOT.into()),
+                device,
+            });
+        }
+        if self.sensor_type == SensorType::Mhz19b {
+            return Ok(Connection::Serial {
+                port: self
+                    .serial_port
+                    .clone()
+                    .unwrap_or_else(|| DEFAULT_SERIAL_PORT.into()),
+            });
+        }
+
+        match self.sensor_type.default_address() {
+            Some(address) => Ok(Connection::I2c {
+                bus: self.bus.unwrap_or(DEFAULT_I2C_BUS),
+                address: self.address.unwrap_or(address),
+            }),
+            None => {
+                let pin = self
+                    .pin
+                    .with_context(|| format!("Sensor {} needs a pin", self.id))?;
+                Ok(Connection::Pin(pin))
+            }
+        }
+    }
+}
+
+fn default_interval_secs() -> u64 {
+    2
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(json: &str) -> SensorConfig {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn parses_the_example_config() {
+        let readme = include_str!("../../readme.md");
+        let start = readme.find("```json").unwrap() + "```json".len();
+        let end = start + readme[start..].find("```").unwrap();
+
+        let config: Config = serde_json::from_str(&readme[start..end]).unwrap();
+        assert_eq!(config.sensors.len(), 11);
+        for sensor in &config.sensors {
+            sensor.connection().unwrap();
+        }
+        assert_eq!(config.sensors[1].interval(), Duration::from_secs(10));
+        assert_eq!(config.sensors[1].read_mode, Some(ReadMode::Edges));
+        assert_eq!(config.sensors[0].leaf_temperature_offset, Some(-1.5));
+    }
+
+    #[test]
+    fn fills_in_defaults() {
+        let sensor = parse(r#"{ "id": "canopy", "type": "dht22", "pin": 4 }"#);
+        assert_eq!(sensor.interval(), Duration::from_secs(2));
+        assert_eq!(sensor.read_mode, None);
+        assert_eq!(sensor.retry, None);
+        assert_eq!(sensor.validation, None);
+        assert!(sensor.calibration.is_empty());
+
+        // A config without sensors reads the DHT22 on pin 23
+        let config: Config = serde_json::from_str("{}").unwrap();
+        assert_eq!(config.sensors.len(), 1);
+        assert_eq!(config.sensors[0].connection().unwrap(), Connection::Pin(23));
+    }
+
+    #[test]
+    fn rejects_unknown_sensor_types() {
+        let result = serde_json::from_str::<SensorConfig>(r#"{ "id": "a", "type": "dht99" }"#);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn prefers_an_iio_device_over_any_other_connection() {
+        let sensor = parse(
+            r#"{ "id": "a", "type": "dht22", "pin": 4, "bus": 1, "address": 68,
+                 "iio_device": "iio:device0", "device": "28-1", "serial_port": "/dev/ttyS0" }"#,
+        );
+        assert_eq!(
+            sensor.connection().unwrap(),
+            Connection::Iio {
+                device: "/sys/bus/iio/devices/iio:device0".into()
+            }
+        );
+
+        let sensor = parse(r#"{ "id": "a", "type": "dht22", "iio_device": "/tmp/iio" }"#);
+        assert_eq!(
+            sensor.connection().unwrap(),
+            Connection::Iio {
+                device: "/tmp/iio".into()
+            }
+        );
+    }
+
+    #[test]
+    fn reads_1_wire_sensors_from_their_device() {
+        let sensor = parse(r#"{ "id": "a", "type": "ds18b20", "pin": 4, "device": "28-1" }"#);
+        assert_eq!(
+            sensor.connection().unwrap(),
+            Connection::OneWire {
+                root: DEFAULT_W1_ROOT.into(),
+                device: "28-1".to_owned()
+            }
+        );
+
+        let sensor = parse(r#"{ "id": "a", "type": "ds18b20", "pin": 4 }"#);
+        assert!(sensor.connection().is_err());
+    }
+
+    #[test]
+    fn reads_uart_sensors_from_their_serial_port() {
+        let sensor = parse(r#"{ "id": "a", "type": "mhz19b", "pin": 4, "address": 68 }"#);
+        assert_eq!(
+            sensor.connection().unwrap(),
+            Connection::Serial {
+                port: DEFAULT_SERIAL_PORT.into()
+            }
+        );
+
+        let sensor = parse(r#"{ "id": "a", "type": "mhz19b", "serial_port": "/dev/ttyUSB0" }"#);
+        assert_eq!(
+            sensor.connection().unwrap(),
+            Connection::Serial {
+                port: "/dev/ttyUSB0".into()
+            }
+        );
+    }
+
+    #[test]
+    fn reads_i2c_sensors_at_their_address() {
+        let sensor = parse(r#"{ "id": "a", "type": "sht3x", "pin": 4 }"#);
+        assert_eq!(
+            sensor.connection().unwrap(),
+            Connection::I2c {
+                bus: DEFAULT_I2C_BUS,
+                address: 0x44
+            }
+        );
+
+        let sensor = parse(r#"{ "id": "a", "type": "sht3x", "bus": 3, "address": 69 }"#);
+        assert_eq!(
+            sensor.connection().unwrap(),
+            Connection::I2c {
+                bus: 3,
+                address: 69
+            }
+        );
+    }
+
+    #[test]
+    fn reads_single_wire_sensors_from_their_pin() {
+        let sensor = parse(r#"{ "id": "a", "type": "dht11", "pin": 4, "address": 68 }"#);
+        assert_eq!(sensor.connection().unwrap(), Connection::Pin(4));
+
+        let sensor = parse(r#"{ "id": "a", "type": "dht11" }"#);
+        assert!(sensor.connection().is_err());
+    }
+}
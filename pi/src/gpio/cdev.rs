@@ -0,0 +1,440 @@
+//! GPIO through the Linux character device line API (`/dev/gpiochipN`), I2C through the bus
+//! devices (`/dev/i2c-N`) and serial ports through termios.
+//!
+//! Works on any board with kernel GPIO and I2C drivers and only needs access to the devices,
+//! usually granted through the `gpio`, `i2c` and `dialout` groups.
+
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Read, Write},
+    os::unix::{fs::OpenOptionsExt, io::AsRawFd},
+    path::Path,
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+use embedded_hal::blocking::delay::{DelayMs, DelayUs};
+use gpio_cdev::{
+    Chip as CdevChip, EventRequestFlags, EventType, Line, LineEventHandle, LineHandle,
+    LineRequestFlags,
+};
+use i2cdev::{
+    core::{I2CMessage, I2CTransfer},
+    linux::{LinuxI2CBus, LinuxI2CMessage},
+};
+use nix::{
+    fcntl::OFlag,
+    poll::{poll, PollFd, PollFlags},
+    sys::termios::{
+        self, BaudRate, ControlFlags, FlushArg, SetArg, SpecialCharacterIndices, Termios,
+    },
+};
+
+use super::{Result, SERIAL_TIMEOUT};
+use crate::humidity::Edge;
+
+/// Label shown for the requested lines in `gpioinfo`
+const CONSUMER: &str = "grow";
+
+/// A GPIO chip shared between all pins claimed from it.
+#[derive(Clone, Debug)]
+pub struct Chip(Arc<Mutex<CdevChip>>);
+
+impl Chip {
+    pub fn open(path: &Path) -> Result<Self> {
+        Ok(Chip(Arc::new(Mutex::new(CdevChip::new(path)?))))
+    }
+
+    fn line(&self, offset: u8) -> Result<Line> {
+        Ok(self.0.lock().unwrap().get_line(u32::from(offset))?)
+    }
+
+    /// Requests line `offset` as an output, initially low.
+    pub fn output(&self, offset: u8) -> Result<OutputPin> {
+        let handle = self
+            .line(offset)?
+            .request(LineRequestFlags::OUTPUT, 0, CONSUMER)?;
+        Ok(OutputPin {
+            handle,
+            high: false,
+        })
+    }
+
+    /// Requests line `offset` as an input that can be switched to output later.
+    pub fn io(&self, offset: u8) -> Result<IoPin> {
+        let line = self.line(offset)?;
+        let handle = line.request(LineRequestFlags::INPUT, 0, CONSUMER)?;
+        Ok(IoPin {
+            line,
+            handle: Some(handle),
+            events: None,
+            high: true,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct OutputPin {
+    handle: LineHandle,
+    high: bool,
+}
+
+impl OutputPin {
+    pub fn set(&mut self, high: bool) -> Result<()> {
+        self.handle.set_value(u8::from(high))?;
+        self.high = high;
+        Ok(())
+    }
+
+    pub fn is_set_high(&self) -> bool {
+        self.high
+    }
+}
+
+/// A line whose direction is changed by releasing and re-requesting it from the kernel.
+#[derive(Debug)]
+pub struct IoPin {
+    line: Line,
+    handle: Option<LineHandle>,
+    /// Edge events requested while capturing.
+    events: Option<LineEventHandle>,
+    /// The level driven while in output mode.
+    high: bool,
+}
+
+impl IoPin {
+    fn request(&mut self, flags: LineRequestFlags) -> Result<()> {
+        // The previous request has to be released before the line can be requested again
+        self.handle = None;
+        self.events = None;
+        self.handle = Some(self.line.request(flags, u8::from(self.high), CONSUMER)?);
+        Ok(())
+    }
+
+    pub fn set_input(&mut self) -> Result<()> {
+        self.request(LineRequestFlags::INPUT)
+    }
+
+    pub fn set_output(&mut self) -> Result<()> {
+        self.request(LineRequestFlags::OUTPUT)
+    }
+
+    /// Re-requests the line as an input reporting both edges, timestamped by the kernel.
+    pub fn start_capture(&mut self) -> Result<()> {
+        self.handle = None;
+        self.events = Some(self.line.events(
+            LineRequestFlags::INPUT,
+            EventRequestFlags::BOTH_EDGES,
+            CONSUMER,
+        )?);
+        Ok(())
+    }
+
+    /// Collects up to `count` edges, waiting at most `timeout`, then returns the line to input.
+    pub fn finish_capture(&mut self, count: usize, timeout: Duration) -> Result<Vec<Edge>> {
+        let mut edges = Vec::with_capacity(count);
+        if let Some(mut events) = self.events.take() {
+            let deadline = Instant::now() + timeout;
+            while edges.len() < count {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                let mut fds = [PollFd::new(events.as_raw_fd(), PollFlags::POLLIN)];
+                let ready = poll(&mut fds, poll_timeout_ms(remaining))
+                    .map_err(|e| gpio_cdev::Error::from(io::Error::from(e)))?;
+                if ready == 0 {
+                    break;
+                }
+
+                let event = events.get_event()?;
+                edges.push(Edge {
+                    time_us: event.timestamp() / 1_000,
+                    rising: event.event_type() == EventType::RisingEdge,
+                });
+            }
+        }
+
+        self.set_input()?;
+        Ok(edges)
+    }
+
+    pub fn is_high(&self) -> Result<bool> {
+        match &self.handle {
+            Some(handle) => Ok(handle.get_value()? == 1),
+            None => Ok(false),
+        }
+    }
+
+    pub fn set(&mut self, high: bool) -> Result<()> {
+        self.high = high;
+        if let Some(handle) = &self.handle {
+            if handle.flags().contains(LineRequestFlags::OUTPUT) {
+                handle.set_value(u8::from(high))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The timeout of a `poll` waiting at least `remaining`, rounded up so the tail of a
+/// response isn't cut off once less than a millisecond is left.
+fn poll_timeout_ms(remaining: Duration) -> i32 {
+    remaining.as_micros().div_ceil(1_000).min(i32::MAX as u128) as i32
+}
+
+/// An I2C bus whose transfers are issued as combined messages, so reads use a repeated start.
+pub struct I2c(LinuxI2CBus);
+
+impl I2c {
+    /// Opens `/dev/i2c-<bus>`.
+    pub fn open(bus: u8) -> Result<Self> {
+        Ok(I2c(LinuxI2CBus::new(format!("/dev/i2c-{bus}"))?))
+    }
+
+    pub fn read(&mut self, address: u8, buffer: &mut [u8]) -> Result<()> {
+        let mut messages = [LinuxI2CMessage::read(buffer).with_address(u16::from(address))];
+        self.0.transfer(&mut messages)?;
+        Ok(())
+    }
+
+    pub fn write(&mut self, address: u8, bytes: &[u8]) -> Result<()> {
+        let mut messages = [LinuxI2CMessage::write(bytes).with_address(u16::from(address))];
+        self.0.transfer(&mut messages)?;
+        Ok(())
+    }
+
+    pub fn write_read(&mut self, address: u8, bytes: &[u8], buffer: &mut [u8]) -> Result<()> {
+        let mut messages = [
+            LinuxI2CMessage::write(bytes).with_address(u16::from(address)),
+            LinuxI2CMessage::read(buffer).with_address(u16::from(address)),
+        ];
+        self.0.transfer(&mut messages)?;
+        Ok(())
+    }
+}
+
+/// A serial port in raw mode, whose reads give up after [`SERIAL_TIMEOUT`] of silence.
+pub struct Serial(File);
+
+impl Serial {
+    /// Opens `port`, e.g. `/dev/serial0`, at 9600 baud with 8 data bits, no parity and 1 stop bit.
+    pub fn open(port: &Path) -> Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            // Keeps the port from becoming the controlling terminal of the process
+            .custom_flags(OFlag::O_NOCTTY.bits())
+            .open(port)
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {e}", port.display())))?;
+
+        let mut settings = termios::tcgetattr(file.as_raw_fd()).map_err(io::Error::from)?;
+        raw_mode(&mut settings)?;
+        termios::tcsetattr(file.as_raw_fd(), SetArg::TCSANOW, &settings)
+            .map_err(io::Error::from)?;
+        Ok(Serial(file))
+    }
+
+    pub fn write_all(&mut self, bytes: &[u8]) -> Result<()> {
+        self.0.write_all(bytes)?;
+        Ok(())
+    }
+
+    /// Reads until `buffer` is full or the line goes silent, returning how many bytes arrived.
+    pub fn read(&mut self, buffer: &mut [u8]) -> Result<usize> {
+        let mut filled = 0;
+        while filled < buffer.len() {
+            match self.0.read(&mut buffer[filled..])? {
+                0 => break,
+                read => filled += read,
+            }
+        }
+        Ok(filled)
+    }
+
+    pub fn discard_input(&mut self) -> Result<()> {
+        termios::tcflush(self.0.as_raw_fd(), FlushArg::TCIFLUSH).map_err(io::Error::from)?;
+        Ok(())
+    }
+}
+
+/// Sets up `settings` for binary frames at 9600 baud, 8N1, timing reads out after [`SERIAL_TIMEOUT`].
+fn raw_mode(settings: &mut Termios) -> Result<()> {
+    termios::cfmakeraw(settings);
+    termios::cfsetspeed(settings, BaudRate::B9600).map_err(io::Error::from)?;
+    settings.control_flags |= ControlFlags::CLOCAL | ControlFlags::CREAD;
+    settings.control_flags &= !(ControlFlags::CSTOPB | ControlFlags::CRTSCTS);
+    // Returns whatever arrived once the line is silent for VTIME tenths of a second
+    settings.control_chars[SpecialCharacterIndices::VMIN as usize] = 0;
+    settings.control_chars[SpecialCharacterIndices::VTIME as usize] =
+        (SERIAL_TIMEOUT.as_millis() / 100) as u8;
+    Ok(())
+}
+
+/// Delay provider using the monotonic clock, spinning for microsecond delays.
+#[derive(Debug, Default)]
+pub struct Delay;
+
+impl DelayUs<u16> for Delay {
+    fn delay_us(&mut self, us: u16) {
+        let until = Instant::now() + Duration::from_micros(u64::from(us));
+        while Instant::now() < until {
+            std::hint::spin_loop();
+        }
+    }
+}
+
+impl DelayMs<u16> for Delay {
+    fn delay_ms(&mut self, ms: u16) {
+        thread::sleep(Duration::from_millis(u64::from(ms)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::PathBuf};
+
+    use super::*;
+
+    #[test]
+    fn rounds_poll_timeouts_up() {
+        assert_eq!(poll_timeout_ms(Duration::ZERO), 0);
+        assert_eq!(poll_timeout_ms(Duration::from_micros(1)), 1);
+        assert_eq!(poll_timeout_ms(Duration::from_micros(999)), 1);
+        assert_eq!(poll_timeout_ms(Duration::from_millis(10)), 10);
+        assert_eq!(poll_timeout_ms(Duration::from_micros(10_001)), 11);
+        assert_eq!(poll_timeout_ms(Duration::MAX), i32::MAX);
+    }
+
+    /// A chip of the kernel's `gpio-sim` module, set up through configfs and removed on drop.
+    ///
+    /// Needs root and `modprobe gpio-sim`, so the tests using it only run when asked for with
+    /// `cargo test -p pi --features gpio-cdev -- --ignored`.
+    struct GpioSim {
+        /// The chip's directory in configfs.
+        config: PathBuf,
+        /// The device node, e.g. `/dev/gpiochip2`.
+        device: PathBuf,
+        /// The sysfs directory holding the `sim_gpio<offset>` attributes of the lines.
+        lines: PathBuf,
+    }
+
+    impl GpioSim {
+        fn new(name: &str, lines: u8) -> Self {
+            let config = PathBuf::from("/sys/kernel/config/gpio-sim")
+                .join(format!("{name}-{}", std::process::id()));
+            let bank = config.join("bank0");
+            fs::create_dir(&config).expect("gpio-sim is not loaded");
+            fs::create_dir(&bank).unwrap();
+            fs::write(bank.join("num_lines"), lines.to_string()).unwrap();
+            fs::write(config.join("live"), "1").unwrap();
+
+            let read = |path: PathBuf| fs::read_to_string(path).unwrap().trim().to_owned();
+            let chip = read(bank.join("chip_name"));
+            let platform = read(config.join("dev_name"));
+            GpioSim {
+                device: Path::new("/dev").join(&chip),
+                lines: Path::new("/sys/devices/platform").join(platform).join(chip),
+                config,
+            }
+        }
+
+        fn attribute(&self, offset: u8, name: &str) -> PathBuf {
+            self.lines.join(format!("sim_gpio{offset}")).join(name)
+        }
+
+        /// The level of line `offset` as seen by the simulated device.
+        fn value(&self, offset: u8) -> bool {
+            fs::read_to_string(self.attribute(offset, "value"))
+                .unwrap()
+                .trim()
+                == "1"
+        }
+
+        /// Pulls line `offset` up or down, as a device driving an input would.
+        fn pull(&self, offset: u8, high: bool) {
+            let pull = if high { "pull-up" } else { "pull-down" };
+            fs::write(self.attribute(offset, "pull"), pull).unwrap();
+        }
+    }
+
+    impl Drop for GpioSim {
+        fn drop(&mut self) {
+            let _ = fs::write(self.config.join("live"), "0");
+            let _ = fs::remove_dir(self.config.join("bank0"));
+            let _ = fs::remove_dir(&self.config);
+        }
+    }
+
+    #[test]
+    #[ignore = "needs root and the gpio-sim kernel module"]
+    fn drives_outputs() {
+        let sim = GpioSim::new("grow-output", 1);
+        let mut pin = Chip::open(&sim.device).unwrap().output(0).unwrap();
+        assert!(!sim.value(0));
+
+        pin.set(true).unwrap();
+        assert!(sim.value(0));
+        assert!(pin.is_set_high());
+        pin.set(false).unwrap();
+        assert!(!sim.value(0));
+    }
+
+    #[test]
+    #[ignore = "needs root and the gpio-sim kernel module"]
+    fn switches_data_lines_between_input_and_output() {
+        let sim = GpioSim::new("grow-io", 1);
+        let mut pin = Chip::open(&sim.device).unwrap().io(0).unwrap();
+
+        sim.pull(0, true);
+        assert!(pin.is_high().unwrap());
+        sim.pull(0, false);
+        assert!(!pin.is_high().unwrap());
+
+        pin.set_output().unwrap();
+        pin.set(true).unwrap();
+        assert!(sim.value(0));
+        pin.set(false).unwrap();
+        assert!(!sim.value(0));
+
+        // Released, the line follows the pull again
+        sim.pull(0, true);
+        pin.set_input().unwrap();
+        assert!(pin.is_high().unwrap());
+    }
+
+    #[test]
+    #[ignore = "needs root and the gpio-sim kernel module"]
+    fn captures_timestamped_edges() {
+        let sim = GpioSim::new("grow-edges", 1);
+        sim.pull(0, true);
+        let mut pin = Chip::open(&sim.device).unwrap().io(0).unwrap();
+
+        pin.start_capture().unwrap();
+        for high in [false, true, false, true] {
+            sim.pull(0, high);
+        }
+        let edges = pin.finish_capture(4, Duration::from_millis(100)).unwrap();
+
+        let rising: Vec<_> = edges.iter().map(|edge| edge.rising).collect();
+        assert_eq!(rising, [false, true, false, true]);
+        assert!(edges
+            .windows(2)
+            .all(|pair| pair[0].time_us <= pair[1].time_us));
+        // The line is back to a plain input
+        assert!(pin.is_high().unwrap());
+    }
+
+    #[test]
+    #[ignore = "needs root and the gpio-sim kernel module"]
+    fn stops_capturing_at_the_timeout() {
+        let sim = GpioSim::new("grow-timeout", 1);
+        let mut pin = Chip::open(&sim.device).unwrap().io(0).unwrap();
+
+        pin.start_capture().unwrap();
+        sim.pull(0, true);
+        let started = Instant::now();
+        let edges = pin.finish_capture(4, Duration::from_millis(20)).unwrap();
+
+        assert_eq!(edges.len(), 1);
+        assert!(started.elapsed() >= Duration::from_millis(20));
+    }
+}
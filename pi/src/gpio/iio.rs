@@ -0,0 +1,29 @@
+use std::{fs, io, path::PathBuf};
+
+use crate::humidity::IioDevice;
+
+/// An IIO device as listed in sysfs, e.g. `/sys/bus/iio/devices/iio:device0`.
+#[derive(Debug, Clone)]
+pub struct Sysfs {
+    device: PathBuf,
+}
+
+impl Sysfs {
+    /// Reads the channels in `device`, which can also be a copy of the sysfs tree.
+    pub fn new(device: impl Into<PathBuf>) -> Self {
+        Sysfs {
+            device: device.into(),
+        }
+    }
+}
+
+impl IioDevice for Sysfs {
+    fn read_channel(&mut self, channel: &str) -> io::Result<String> {
+        let path = self.device.join(channel);
+        fs::read_to_string(&path).map_err(|e| match e.kind() {
+            io::ErrorKind::NotFound => io::Error::new(e.kind(), format!("{}: {e}", path.display())),
+            // Keeps the errno the driver failed the read with
+            _ => e,
+        })
+    }
+}
@@ -0,0 +1,694 @@
+//! GPIO backends selected through cargo features.
+//!
+//! Relays, humidity sensors and the status LED only use the pins, buses, serial ports and
+//! kernel drivers handed out by a [`Backend`], so the hardware specific code lives here:
+//! - `rppal`: Raspberry Pi GPIO through memory-mapped BCM registers
+//! - `gpio-cdev`: Linux GPIO and I2C character devices and termios serial ports, for other
+//!   boards or unprivileged access
+//! - `sim`: in-memory pins connected to the simulated [`Environment`]
+
+#[cfg(feature = "gpio-cdev")]
+mod cdev;
+#[cfg(any(feature = "rppal", feature = "gpio-cdev"))]
+pub(crate) mod iio;
+#[cfg(any(feature = "rppal", feature = "gpio-cdev"))]
+mod w1;
+
+use core::convert::Infallible;
+
+use embedded_hal::{
+    blocking::{
+        delay::{DelayMs, DelayUs},
+        i2c::{Read, Write, WriteRead},
+    },
+    digital::v2::{InputPin, OutputPin as HalOutputPin, StatefulOutputPin},
+};
+
+use std::{
+    collections::BTreeMap,
+    io,
+    path::Path,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+#[cfg(any(feature = "rppal", feature = "gpio-cdev"))]
+use std::{sync::OnceLock, time::Instant};
+
+#[cfg(feature = "sim")]
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::humidity::{
+    Clock, DeviceLock, Edge, EdgeCapture, IioDevice, IoMode, SerialPort, SharedDevices, W1Bus,
+};
+#[cfg(feature = "sim")]
+use crate::{
+    humidity::{
+        sim::{SimDelay, SimLine},
+        sim_i2c::{Nack, SimBus},
+        sim_iio::SimIio,
+        sim_uart::SimMhz19,
+        sim_w1::SimW1,
+    },
+    sim::Environment,
+};
+
+/// How long reads from a serial port wait for the line to speak
+#[cfg(any(feature = "rppal", feature = "gpio-cdev"))]
+const SERIAL_TIMEOUT: Duration = Duration::from_secs(1);
+
+#[cfg(not(any(feature = "rppal", feature = "gpio-cdev", feature = "sim")))]
+compile_error!("at least one GPIO backend feature must be enabled: `rppal`, `gpio-cdev` or `sim`");
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[cfg(not(any(feature = "rppal", feature = "gpio-cdev")))]
+    #[error("No hardware GPIO backend enabled, rebuild with `rppal` or `gpio-cdev` or run with --simulate")]
+    NoHardware,
+    #[cfg(feature = "rppal")]
+    #[error(transparent)]
+    Rppal(#[from] rppal::gpio::Error),
+    #[cfg(feature = "rppal")]
+    #[error("Edge capture is not supported by the rppal backend, use gpio-cdev or polling")]
+    EdgeCaptureUnsupported,
+    #[cfg(feature = "rppal")]
+    #[error(transparent)]
+    RppalI2c(#[from] rppal::i2c::Error),
+    #[cfg(feature = "rppal")]
+    #[error(transparent)]
+    RppalUart(#[from] rppal::uart::Error),
+    #[cfg(feature = "gpio-cdev")]
+    #[error(transparent)]
+    Cdev(#[from] gpio_cdev::Error),
+    #[cfg(feature = "gpio-cdev")]
+    #[error(transparent)]
+    CdevI2c(#[from] i2cdev::linux::LinuxI2CError),
+    #[cfg(feature = "sim")]
+    #[error(transparent)]
+    SimI2c(#[from] Nack),
+    #[cfg(any(feature = "rppal", feature = "gpio-cdev"))]
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+impl From<Infallible> for Error {
+    fn from(e: Infallible) -> Self {
+        match e {}
+    }
+}
+
+pub type Result<T> = core::result::Result<T, Error>;
+
+/// The locks of I2C devices, by bus and address.
+type DeviceLocks = Arc<Mutex<BTreeMap<(u8, u8), DeviceLock>>>;
+
+/// Hands out pins from one of the compiled-in GPIO implementations.
+#[derive(Clone)]
+pub struct Backend {
+    gpio: Gpio,
+    /// The locks of the I2C devices, shared by every handle to their bus.
+    i2c_devices: DeviceLocks,
+}
+
+#[derive(Clone)]
+enum Gpio {
+    #[cfg(feature = "rppal")]
+    Rppal(rppal::gpio::Gpio),
+    #[cfg(feature = "gpio-cdev")]
+    Cdev(cdev::Chip),
+    #[cfg(feature = "sim")]
+    Simulated(Environment),
+}
+
+impl Backend {
+    fn new(gpio: Gpio) -> Self {
+        Backend {
+            gpio,
+            i2c_devices: DeviceLocks::default(),
+        }
+    }
+
+    /// Opens the hardware GPIO backend compiled into this binary, preferring `rppal`.
+    pub fn hardware() -> Result<Self> {
+        #[cfg(feature = "rppal")]
+        {
+            Ok(Backend::new(Gpio::Rppal(rppal::gpio::Gpio::new()?)))
+        }
+        #[cfg(all(not(feature = "rppal"), feature = "gpio-cdev"))]
+        {
+            Self::cdev(std::path::Path::new("/dev/gpiochip0"))
+        }
+        #[cfg(not(any(feature = "rppal", feature = "gpio-cdev")))]
+        {
+            Err(Error::NoHardware)
+        }
+    }
+
+    /// Opens the GPIO character device at `chip`, e.g. `/dev/gpiochip0`.
+    #[cfg(feature = "gpio-cdev")]
+    pub fn cdev(chip: &std::path::Path) -> Result<Self> {
+        Ok(Backend::new(Gpio::Cdev(cdev::Chip::open(chip)?)))
+    }
+
+    /// Creates a backend whose pins are wired into the simulated environment.
+    #[cfg(feature = "sim")]
+    pub fn simulated(environment: Environment) -> Self {
+        Backend::new(Gpio::Simulated(environment))
+    }
+
+    /// Claims `pin` as an output.
+    pub fn output(&self, pin: u8) -> Result<OutputPin> {
+        match &self.gpio {
+            #[cfg(feature = "rppal")]
+            Gpio::Rppal(gpio) => Ok(OutputPin::Rppal(gpio.get(pin)?.into_output())),
+            #[cfg(feature = "gpio-cdev")]
+            Gpio::Cdev(chip) => Ok(OutputPin::Cdev(chip.output(pin)?)),
+            #[cfg(feature = "sim")]
+            Gpio::Simulated(environment) => Ok(OutputPin::Simulated(environment.output(pin))),
+        }
+    }
+
+    /// Claims `pin` as a bidirectional data line, starting in input mode.
+    pub fn io(&self, pin: u8) -> Result<IoPin> {
+        match &self.gpio {
+            #[cfg(feature = "rppal")]
+            Gpio::Rppal(gpio) => Ok(IoPin::Rppal(
+                gpio.get(pin)?.into_io(rppal::gpio::Mode::Input),
+            )),
+            #[cfg(feature = "gpio-cdev")]
+            Gpio::Cdev(chip) => Ok(IoPin::Cdev(chip.io(pin)?)),
+            #[cfg(feature = "sim")]
+            Gpio::Simulated(environment) => Ok(IoPin::Simulated(environment.line(pin))),
+        }
+    }
+
+    /// Opens I2C `bus`, e.g. 1 for the bus on pins 3 and 5 of the Raspberry Pi header.
+    pub fn i2c(&self, bus: u8) -> Result<I2c> {
+        let handle = match &self.gpio {
+            #[cfg(feature = "rppal")]
+            Gpio::Rppal(_) => I2cHandle::Rppal(rppal::i2c::I2c::with_bus(bus)?),
+            #[cfg(feature = "gpio-cdev")]
+            Gpio::Cdev(_) => I2cHandle::Cdev(cdev::I2c::open(bus)?),
+            #[cfg(feature = "sim")]
+            Gpio::Simulated(environment) => I2cHandle::Simulated(environment.i2c(bus)),
+        };
+        Ok(I2c {
+            handle,
+            number: bus,
+            devices: self.i2c_devices.clone(),
+        })
+    }
+
+    /// Opens serial `port` at 9600 baud with 8 data bits, no parity and 1 stop bit.
+    #[cfg_attr(
+        not(any(feature = "rppal", feature = "gpio-cdev")),
+        allow(unused_variables)
+    )]
+    pub fn serial(&self, port: &Path) -> Result<Serial> {
+        match &self.gpio {
+            #[cfg(feature = "rppal")]
+            Gpio::Rppal(_) => {
+                let mut uart =
+                    rppal::uart::Uart::with_path(port, 9600, rppal::uart::Parity::None, 8, 1)?;
+                uart.set_read_mode(0, SERIAL_TIMEOUT)?;
+                uart.set_write_mode(true)?;
+                Ok(Serial::Rppal(uart))
+            }
+            #[cfg(feature = "gpio-cdev")]
+            Gpio::Cdev(_) => Ok(Serial::Cdev(cdev::Serial::open(port)?)),
+            #[cfg(feature = "sim")]
+            Gpio::Simulated(environment) => Ok(Serial::Simulated(environment.serial())),
+        }
+    }
+
+    /// Lists the kernel's 1-Wire devices in `root`, e.g. `/sys/bus/w1/devices`.
+    #[cfg_attr(
+        not(any(feature = "rppal", feature = "gpio-cdev")),
+        allow(unused_variables)
+    )]
+    pub fn one_wire(&self, root: &Path) -> OneWire {
+        match &self.gpio {
+            #[cfg(feature = "rppal")]
+            Gpio::Rppal(_) => OneWire::Sysfs(w1::Sysfs::new(root)),
+            #[cfg(feature = "gpio-cdev")]
+            Gpio::Cdev(_) => OneWire::Sysfs(w1::Sysfs::new(root)),
+            #[cfg(feature = "sim")]
+            Gpio::Simulated(environment) => OneWire::Simulated(environment.one_wire()),
+        }
+    }
+
+    /// Reads the IIO device in sysfs directory `device`, e.g. `/sys/bus/iio/devices/iio:device0`.
+    pub fn iio(&self, device: &Path) -> Iio {
+        match &self.gpio {
+            #[cfg(feature = "rppal")]
+            Gpio::Rppal(_) => Iio::Sysfs(iio::Sysfs::new(device)),
+            #[cfg(feature = "gpio-cdev")]
+            Gpio::Cdev(_) => Iio::Sysfs(iio::Sysfs::new(device)),
+            #[cfg(feature = "sim")]
+            Gpio::Simulated(environment) => Iio::Simulated(environment.iio(device)),
+        }
+    }
+
+    /// A delay provider matching the timing of this backend's pins.
+    pub fn delay(&self) -> Delay {
+        match &self.gpio {
+            #[cfg(feature = "rppal")]
+            Gpio::Rppal(_) => Delay::Rppal(rppal::hal::Delay::new()),
+            #[cfg(feature = "gpio-cdev")]
+            Gpio::Cdev(_) => Delay::Cdev(cdev::Delay),
+            #[cfg(feature = "sim")]
+            Gpio::Simulated(environment) => Delay::Simulated(environment.delay()),
+        }
+    }
+
+    /// A delay provider timing the data line claimed with [`Backend::io`] on `pin`.
+    #[cfg_attr(not(feature = "sim"), allow(unused_variables))]
+    pub fn line_delay(&self, pin: u8) -> Delay {
+        match &self.gpio {
+            #[cfg(feature = "sim")]
+            Gpio::Simulated(environment) => Delay::Simulated(environment.line_delay(pin)),
+            #[cfg(any(feature = "rppal", feature = "gpio-cdev"))]
+            _ => self.delay(),
+        }
+    }
+}
+
+/// A pin claimed as an output.
+#[derive(Debug)]
+pub enum OutputPin {
+    #[cfg(feature = "rppal")]
+    Rppal(rppal::gpio::OutputPin),
+    #[cfg(feature = "gpio-cdev")]
+    Cdev(cdev::OutputPin),
+    #[cfg(feature = "sim")]
+    Simulated(Arc<AtomicBool>),
+}
+
+impl HalOutputPin for OutputPin {
+    type Error = Error;
+
+    fn set_low(&mut self) -> Result<()> {
+        match self {
+            #[cfg(feature = "rppal")]
+            OutputPin::Rppal(pin) => pin.set_low(),
+            #[cfg(feature = "gpio-cdev")]
+            OutputPin::Cdev(pin) => pin.set(false)?,
+            #[cfg(feature = "sim")]
+            OutputPin::Simulated(state) => state.store(false, Ordering::SeqCst),
+        }
+        Ok(())
+    }
+
+    fn set_high(&mut self) -> Result<()> {
+        match self {
+            #[cfg(feature = "rppal")]
+            OutputPin::Rppal(pin) => pin.set_high(),
+            #[cfg(feature = "gpio-cdev")]
+            OutputPin::Cdev(pin) => pin.set(true)?,
+            #[cfg(feature = "sim")]
+            OutputPin::Simulated(state) => state.store(true, Ordering::SeqCst),
+        }
+        Ok(())
+    }
+}
+
+impl StatefulOutputPin for OutputPin {
+    fn is_set_high(&self) -> Result<bool> {
+        match self {
+            #[cfg(feature = "rppal")]
+            OutputPin::Rppal(pin) => Ok(pin.is_set_high()),
+            #[cfg(feature = "gpio-cdev")]
+            OutputPin::Cdev(pin) => Ok(pin.is_set_high()),
+            #[cfg(feature = "sim")]
+            OutputPin::Simulated(state) => Ok(state.load(Ordering::SeqCst)),
+        }
+    }
+
+    fn is_set_low(&self) -> Result<bool> {
+        Ok(!self.is_set_high()?)
+    }
+}
+
+/// A pin claimed as a bidirectional data line.
+pub enum IoPin {
+    #[cfg(feature = "rppal")]
+    Rppal(rppal::gpio::IoPin),
+    #[cfg(feature = "gpio-cdev")]
+    Cdev(cdev::IoPin),
+    #[cfg(feature = "sim")]
+    Simulated(SimLine),
+}
+
+impl InputPin for IoPin {
+    type Error = Error;
+
+    fn is_high(&self) -> Result<bool> {
+        match self {
+            #[cfg(feature = "rppal")]
+            IoPin::Rppal(pin) => Ok(pin.is_high()),
+            #[cfg(feature = "gpio-cdev")]
+            IoPin::Cdev(pin) => pin.is_high(),
+            #[cfg(feature = "sim")]
+            IoPin::Simulated(line) => Ok(line.is_high()?),
+        }
+    }
+
+    fn is_low(&self) -> Result<bool> {
+        Ok(!self.is_high()?)
+    }
+}
+
+impl HalOutputPin for IoPin {
+    type Error = Error;
+
+    fn set_low(&mut self) -> Result<()> {
+        match self {
+            #[cfg(feature = "rppal")]
+            IoPin::Rppal(pin) => pin.set_low(),
+            #[cfg(feature = "gpio-cdev")]
+            IoPin::Cdev(pin) => pin.set(false)?,
+            #[cfg(feature = "sim")]
+            IoPin::Simulated(line) => line.set_low()?,
+        }
+        Ok(())
+    }
+
+    fn set_high(&mut self) -> Result<()> {
+        match self {
+            #[cfg(feature = "rppal")]
+            IoPin::Rppal(pin) => pin.set_high(),
+            #[cfg(feature = "gpio-cdev")]
+            IoPin::Cdev(pin) => pin.set(true)?,
+            #[cfg(feature = "sim")]
+            IoPin::Simulated(line) => line.set_high()?,
+        }
+        Ok(())
+    }
+}
+
+impl IoMode for IoPin {
+    type Error = Error;
+
+    fn set_input(&mut self) -> Result<()> {
+        match self {
+            #[cfg(feature = "rppal")]
+            IoPin::Rppal(pin) => pin.set_mode(rppal::gpio::Mode::Input),
+            #[cfg(feature = "gpio-cdev")]
+            IoPin::Cdev(pin) => pin.set_input()?,
+            #[cfg(feature = "sim")]
+            IoPin::Simulated(line) => line.set_input()?,
+        }
+        Ok(())
+    }
+
+    fn set_output(&mut self) -> Result<()> {
+        match self {
+            #[cfg(feature = "rppal")]
+            IoPin::Rppal(pin) => pin.set_mode(rppal::gpio::Mode::Output),
+            #[cfg(feature = "gpio-cdev")]
+            IoPin::Cdev(pin) => pin.set_output()?,
+            #[cfg(feature = "sim")]
+            IoPin::Simulated(line) => line.set_output()?,
+        }
+        Ok(())
+    }
+}
+
+impl EdgeCapture for IoPin {
+    type Error = Error;
+
+    fn start_capture(&mut self) -> Result<()> {
+        match self {
+            // rppal only offers interrupts on input pins and without timestamps
+            #[cfg(feature = "rppal")]
+            IoPin::Rppal(_) => Err(Error::EdgeCaptureUnsupported),
+            #[cfg(feature = "gpio-cdev")]
+            IoPin::Cdev(pin) => pin.start_capture(),
+            #[cfg(feature = "sim")]
+            IoPin::Simulated(line) => Ok(line.start_capture()?),
+        }
+    }
+
+    #[cfg_attr(
+        not(any(feature = "gpio-cdev", feature = "sim")),
+        allow(unused_variables)
+    )]
+    fn finish_capture(&mut self, count: usize, timeout: Duration) -> Result<Vec<Edge>> {
+        match self {
+            #[cfg(feature = "rppal")]
+            IoPin::Rppal(_) => Err(Error::EdgeCaptureUnsupported),
+            #[cfg(feature = "gpio-cdev")]
+            IoPin::Cdev(pin) => pin.finish_capture(count, timeout),
+            #[cfg(feature = "sim")]
+            IoPin::Simulated(line) => Ok(line.finish_capture(count, timeout)?),
+        }
+    }
+}
+
+/// An I2C bus opened by a [`Backend`].
+pub struct I2c {
+    handle: I2cHandle,
+    /// The number of the bus, e.g. 1 for `/dev/i2c-1`.
+    number: u8,
+    devices: DeviceLocks,
+}
+
+enum I2cHandle {
+    #[cfg(feature = "rppal")]
+    Rppal(rppal::i2c::I2c),
+    #[cfg(feature = "gpio-cdev")]
+    Cdev(cdev::I2c),
+    #[cfg(feature = "sim")]
+    Simulated(SimBus),
+}
+
+impl Read for I2c {
+    type Error = Error;
+
+    fn read(&mut self, address: u8, buffer: &mut [u8]) -> Result<()> {
+        match &mut self.handle {
+            #[cfg(feature = "rppal")]
+            I2cHandle::Rppal(bus) => Read::read(bus, address, buffer)?,
+            #[cfg(feature = "gpio-cdev")]
+            I2cHandle::Cdev(bus) => bus.read(address, buffer)?,
+            #[cfg(feature = "sim")]
+            I2cHandle::Simulated(bus) => bus.read(address, buffer)?,
+        }
+        Ok(())
+    }
+}
+
+impl Write for I2c {
+    type Error = Error;
+
+    fn write(&mut self, address: u8, bytes: &[u8]) -> Result<()> {
+        match &mut self.handle {
+            #[cfg(feature = "rppal")]
+            I2cHandle::Rppal(bus) => Write::write(bus, address, bytes)?,
+            #[cfg(feature = "gpio-cdev")]
+            I2cHandle::Cdev(bus) => bus.write(address, bytes)?,
+            #[cfg(feature = "sim")]
+            I2cHandle::Simulated(bus) => bus.write(address, bytes)?,
+        }
+        Ok(())
+    }
+}
+
+impl WriteRead for I2c {
+    type Error = Error;
+
+    fn write_read(&mut self, address: u8, bytes: &[u8], buffer: &mut [u8]) -> Result<()> {
+        match &mut self.handle {
+            #[cfg(feature = "rppal")]
+            I2cHandle::Rppal(bus) => WriteRead::write_read(bus, address, bytes, buffer)?,
+            #[cfg(feature = "gpio-cdev")]
+            I2cHandle::Cdev(bus) => bus.write_read(address, bytes, buffer)?,
+            #[cfg(feature = "sim")]
+            I2cHandle::Simulated(bus) => bus.write_read(address, bytes, buffer)?,
+        }
+        Ok(())
+    }
+}
+
+impl SharedDevices for I2c {
+    fn device_lock(&self, address: u8) -> DeviceLock {
+        // A poisoned lock only means another sensor's thread panicked
+        let mut devices = self.devices.lock().unwrap_or_else(|e| e.into_inner());
+        devices.entry((self.number, address)).or_default().clone()
+    }
+}
+
+/// A 1-Wire bus opened by a [`Backend`].
+pub enum OneWire {
+    #[cfg(any(feature = "rppal", feature = "gpio-cdev"))]
+    Sysfs(w1::Sysfs),
+    #[cfg(feature = "sim")]
+    Simulated(SimW1),
+}
+
+impl W1Bus for OneWire {
+    type Error = Error;
+
+    fn devices(&mut self) -> Result<Vec<String>> {
+        match self {
+            #[cfg(any(feature = "rppal", feature = "gpio-cdev"))]
+            OneWire::Sysfs(bus) => Ok(bus.devices()?),
+            #[cfg(feature = "sim")]
+            OneWire::Simulated(bus) => Ok(bus.devices()?),
+        }
+    }
+
+    fn read_slave(&mut self, device: &str) -> Result<String> {
+        match self {
+            #[cfg(any(feature = "rppal", feature = "gpio-cdev"))]
+            OneWire::Sysfs(bus) => Ok(bus.read_slave(device)?),
+            #[cfg(feature = "sim")]
+            OneWire::Simulated(bus) => Ok(bus.read_slave(device)?),
+        }
+    }
+}
+
+/// An IIO device opened by a [`Backend`].
+pub enum Iio {
+    #[cfg(any(feature = "rppal", feature = "gpio-cdev"))]
+    Sysfs(iio::Sysfs),
+    #[cfg(feature = "sim")]
+    Simulated(SimIio),
+}
+
+impl IioDevice for Iio {
+    fn read_channel(&mut self, channel: &str) -> io::Result<String> {
+        match self {
+            #[cfg(any(feature = "rppal", feature = "gpio-cdev"))]
+            Iio::Sysfs(device) => device.read_channel(channel),
+            #[cfg(feature = "sim")]
+            Iio::Simulated(device) => device.read_channel(channel),
+        }
+    }
+}
+
+/// A serial port opened by a [`Backend`].
+pub enum Serial {
+    #[cfg(feature = "rppal")]
+    Rppal(rppal::uart::Uart),
+    #[cfg(feature = "gpio-cdev")]
+    Cdev(cdev::Serial),
+    #[cfg(feature = "sim")]
+    Simulated(SimMhz19),
+}
+
+impl Serial {
+    /// Opens `port` through termios, e.g. a pseudo-terminal standing in for a sensor.
+    #[cfg(all(test, feature = "gpio-cdev"))]
+    pub fn termios(port: &Path) -> Result<Self> {
+        Ok(Serial::Cdev(cdev::Serial::open(port)?))
+    }
+}
+
+impl SerialPort for Serial {
+    type Error = Error;
+
+    fn write_all(&mut self, bytes: &[u8]) -> Result<()> {
+        match self {
+            #[cfg(feature = "rppal")]
+            Serial::Rppal(uart) => {
+                let mut written = 0;
+                while written < bytes.len() {
+                    written += uart.write(&bytes[written..])?;
+                }
+            }
+            #[cfg(feature = "gpio-cdev")]
+            Serial::Cdev(port) => port.write_all(bytes)?,
+            #[cfg(feature = "sim")]
+            Serial::Simulated(port) => port.write_all(bytes)?,
+        }
+        Ok(())
+    }
+
+    fn read(&mut self, buffer: &mut [u8]) -> Result<usize> {
+        match self {
+            #[cfg(feature = "rppal")]
+            Serial::Rppal(uart) => {
+                let mut filled = 0;
+                while filled < buffer.len() {
+                    match uart.read(&mut buffer[filled..])? {
+                        0 => break,
+                        read => filled += read,
+                    }
+                }
+                Ok(filled)
+            }
+            #[cfg(feature = "gpio-cdev")]
+            Serial::Cdev(port) => Ok(port.read(buffer)?),
+            #[cfg(feature = "sim")]
+            Serial::Simulated(port) => Ok(port.read(buffer)?),
+        }
+    }
+
+    fn discard_input(&mut self) -> Result<()> {
+        match self {
+            #[cfg(feature = "rppal")]
+            Serial::Rppal(uart) => uart.flush(rppal::uart::Queue::Input)?,
+            #[cfg(feature = "gpio-cdev")]
+            Serial::Cdev(port) => port.discard_input()?,
+            #[cfg(feature = "sim")]
+            Serial::Simulated(port) => port.discard_input()?,
+        }
+        Ok(())
+    }
+}
+
+/// A delay provider for the pins of a [`Backend`].
+pub enum Delay {
+    #[cfg(feature = "rppal")]
+    Rppal(rppal::hal::Delay),
+    #[cfg(feature = "gpio-cdev")]
+    Cdev(cdev::Delay),
+    #[cfg(feature = "sim")]
+    Simulated(SimDelay),
+}
+
+impl DelayUs<u16> for Delay {
+    fn delay_us(&mut self, us: u16) {
+        match self {
+            #[cfg(feature = "rppal")]
+            Delay::Rppal(delay) => delay.delay_us(us),
+            #[cfg(feature = "gpio-cdev")]
+            Delay::Cdev(delay) => delay.delay_us(us),
+            #[cfg(feature = "sim")]
+            Delay::Simulated(delay) => delay.delay_us(us),
+        }
+    }
+}
+
+impl DelayMs<u16> for Delay {
+    fn delay_ms(&mut self, ms: u16) {
+        match self {
+            #[cfg(feature = "rppal")]
+            Delay::Rppal(delay) => delay.delay_ms(ms),
+            #[cfg(feature = "gpio-cdev")]
+            Delay::Cdev(delay) => delay.delay_ms(ms),
+            #[cfg(feature = "sim")]
+            Delay::Simulated(delay) => delay.delay_ms(ms),
+        }
+    }
+}
+
+impl Clock for Delay {
+    fn now_us(&self) -> u64 {
+        match self {
+            #[cfg(feature = "rppal")]
+            Delay::Rppal(_) => monotonic_us(),
+            #[cfg(feature = "gpio-cdev")]
+            Delay::Cdev(_) => monotonic_us(),
+            #[cfg(feature = "sim")]
+            Delay::Simulated(delay) => delay.now_us(),
+        }
+    }
+}
+
+/// Microseconds elapsed on the monotonic clock since it was first read.
+#[cfg(any(feature = "rppal", feature = "gpio-cdev"))]
+fn monotonic_us() -> u64 {
+    static START: OnceLock<Instant> = OnceLock::new();
+    START.get_or_init(Instant::now).elapsed().as_micros() as u64
+}
@@ -0,0 +1,35 @@
+use std::{fs, io, path::PathBuf};
+
+use crate::humidity::W1Bus;
+
+/// The kernel's 1-Wire devices as listed in sysfs, usually under `/sys/bus/w1/devices`.
+#[derive(Debug, Clone)]
+pub struct Sysfs {
+    root: PathBuf,
+}
+
+impl Sysfs {
+    /// Lists the devices in `root`, which can also be a copy of the sysfs tree.
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Sysfs { root: root.into() }
+    }
+}
+
+impl W1Bus for Sysfs {
+    type Error = io::Error;
+
+    fn devices(&mut self) -> io::Result<Vec<String>> {
+        // Without the w1-gpio overlay loaded the directory doesn't exist
+        let entries = fs::read_dir(&self.root)
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {e}", self.root.display())))?;
+        let mut devices = Vec::new();
+        for entry in entries {
+            devices.push(entry?.file_name().to_string_lossy().into_owned());
+        }
+        Ok(devices)
+    }
+
+    fn read_slave(&mut self, device: &str) -> io::Result<String> {
+        fs::read_to_string(self.root.join(device).join("w1_slave"))
+    }
+}
@@ -0,0 +1,349 @@
+//! Rust driver for the TI ADS1115 16 bit analog-to-digital converter, generic over `embedded-hal` I2C buses.
+//!
+//! Every reading starts a single-shot conversion of one channel against ground, after which the
+//! converter powers down. Probes sharing a converter are read from their own threads, so
+//! conversions hold the bus's lock of the converter to keep one probe's channel from being read
+//! as another's.
+//!
+#![deny(unsafe_code)]
+
+use embedded_hal::blocking::delay::DelayMs;
+use serde::{Deserialize, Serialize};
+
+use super::{
+    bus::{BusError, DeviceLock, I2cBus, SharedDevices},
+    Error, Result,
+};
+
+/// The address with ADDR tied to ground, tying it to VDD, SDA or SCL selects `0x49` to `0x4b`
+pub const DEFAULT_ADDRESS: u8 = 0x48;
+
+pub(super) const REG_CONVERSION: u8 = 0x00;
+pub(super) const REG_CONFIG: u8 = 0x01;
+
+/// Config bit starting a conversion when written, it reads as set while the converter is idle
+pub(super) const START: u16 = 1 << 15;
+/// Multiplexer setting measuring the channel selected by the low bits against ground
+const MUX_SINGLE_ENDED: u16 = 0b100;
+const MODE_SINGLE_SHOT: u16 = 1 << 8;
+const COMPARATOR_DISABLED: u16 = 0b11;
+/// Full scale voltages selected by the gain bits, the last ones repeat the smallest range
+pub(super) const FULL_SCALE: [f32; 8] = [6.144, 4.096, 2.048, 1.024, 0.512, 0.256, 0.256, 0.256];
+
+/// How often the config is polled after the expected conversion time has passed
+const CONVERSION_POLLS: u16 = 10;
+
+/// A single-ended input channel of the converter, measured against ground.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "u8", into = "u8")]
+pub struct Channel(u8);
+
+impl TryFrom<u8> for Channel {
+    type Error = String;
+
+    fn try_from(channel: u8) -> core::result::Result<Self, Self::Error> {
+        match channel {
+            0..=3 => Ok(Channel(channel)),
+            channel => Err(format!("The ADS1115 has channels 0 to 3, not {channel}")),
+        }
+    }
+}
+
+impl From<Channel> for u8 {
+    fn from(channel: Channel) -> Self {
+        channel.0
+    }
+}
+
+/// The range of the programmable gain amplifier, named by its full scale voltage.
+///
+/// Defaults to the range covering a 3.3V supply, which the chip's own default of 2.048V doesn't.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Gain {
+    #[serde(rename = "6.144")]
+    Fsr6v144,
+    #[default]
+    #[serde(rename = "4.096")]
+    Fsr4v096,
+    #[serde(rename = "2.048")]
+    Fsr2v048,
+    #[serde(rename = "1.024")]
+    Fsr1v024,
+    #[serde(rename = "0.512")]
+    Fsr0v512,
+    #[serde(rename = "0.256")]
+    Fsr0v256,
+}
+
+impl Gain {
+    fn bits(self) -> u16 {
+        self as u16
+    }
+
+    /// The voltage at which readings saturate, inputs can't exceed the supply voltage either way.
+    pub fn full_scale(self) -> f32 {
+        FULL_SCALE[usize::from(self.bits())]
+    }
+}
+
+/// Samples per second, slower rates average out more noise.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "u16", into = "u16")]
+pub enum DataRate {
+    Sps8,
+    Sps16,
+    Sps32,
+    Sps64,
+    #[default]
+    Sps128,
+    Sps250,
+    Sps475,
+    Sps860,
+}
+
+const DATA_RATES: [(DataRate, u16); 8] = [
+    (DataRate::Sps8, 8),
+    (DataRate::Sps16, 16),
+    (DataRate::Sps32, 32),
+    (DataRate::Sps64, 64),
+    (DataRate::Sps128, 128),
+    (DataRate::Sps250, 250),
+    (DataRate::Sps475, 475),
+    (DataRate::Sps860, 860),
+];
+
+impl DataRate {
+    fn bits(self) -> u16 {
+        self as u16
+    }
+
+    /// How long a conversion takes (in milliseconds), rounded up.
+    fn conversion_ms(self) -> u16 {
+        1000_u16.div_ceil(u16::from(self))
+    }
+}
+
+impl TryFrom<u16> for DataRate {
+    type Error = String;
+
+    fn try_from(samples: u16) -> core::result::Result<Self, Self::Error> {
+        DATA_RATES
+            .iter()
+            .find(|(_, rate)| *rate == samples)
+            .map(|(data_rate, _)| *data_rate)
+            .ok_or_else(|| {
+                format!("The ADS1115 samples 8, 16, 32, 64, 128, 250, 475 or 860 times per second, not {samples}")
+            })
+    }
+}
+
+impl From<DataRate> for u16 {
+    fn from(data_rate: DataRate) -> Self {
+        DATA_RATES[usize::from(data_rate.bits())].1
+    }
+}
+
+/// What is converted and how.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    /// The channel the probe is connected to.
+    pub channel: Channel,
+    pub gain: Gain,
+    pub data_rate: DataRate,
+}
+
+impl Settings {
+    /// The config register starting a conversion with these settings.
+    fn config(self) -> u16 {
+        START
+            | (MUX_SINGLE_ENDED | u16::from(self.channel.0)) << 12
+            | self.gain.bits() << 9
+            | MODE_SINGLE_SHOT
+            | self.data_rate.bits() << 5
+            | COMPARATOR_DISABLED
+    }
+}
+
+/// An ADS1115 device.
+pub struct Ads1115<B> {
+    /// The bus the converter is connected to.
+    bus: B,
+    /// The address of the converter on the bus.
+    address: u8,
+    /// Held from starting a conversion until its result is read, shared with the other devices
+    /// for the same converter.
+    conversion: DeviceLock,
+}
+
+impl<B: SharedDevices> Ads1115<B> {
+    /// Creates a device at `address` on the given bus.
+    pub fn new(bus: B, address: u8) -> Self {
+        Ads1115 {
+            conversion: bus.device_lock(address),
+            bus,
+            address,
+        }
+    }
+}
+
+impl<B> Ads1115<B>
+where
+    B: I2cBus,
+    Error: From<BusError<B>>,
+{
+    /// Converts the voltage at the channel selected by `settings`, in volts.
+    pub fn read_voltage<D: DelayMs<u16>>(
+        &mut self,
+        settings: Settings,
+        delay: &mut D,
+    ) -> Result<f32> {
+        let conversion = self.conversion.clone();
+        // A poisoned lock only means another probe's read panicked
+        let _conversion = conversion.lock().unwrap_or_else(|e| e.into_inner());
+
+        let [msb, lsb] = settings.config().to_be_bytes();
+        self.bus.write(self.address, &[REG_CONFIG, msb, lsb])?;
+        delay.delay_ms(settings.data_rate.conversion_ms());
+        self.wait_for_conversion(delay)?;
+
+        let mut data = [0; 2];
+        self.bus
+            .write_read(self.address, &[REG_CONVERSION], &mut data)?;
+        let raw = i16::from_be_bytes(data);
+        Ok(f32::from(raw) * settings.gain.full_scale() / 32768.0)
+    }
+
+    fn wait_for_conversion<D: DelayMs<u16>>(&mut self, delay: &mut D) -> Result<()> {
+        for _ in 0..CONVERSION_POLLS {
+            let mut config = [0; 2];
+            self.bus
+                .write_read(self.address, &[REG_CONFIG], &mut config)?;
+            if u16::from_be_bytes(config) & START != 0 {
+                return Ok(());
+            }
+            delay.delay_ms(1);
+        }
+        Err(Error::NotReady)
+    }
+}
+
+#[cfg(all(test, feature = "sim"))]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{
+        gpio::Backend,
+        humidity::{
+            sim::SimClock,
+            sim_i2c::{SimAds1115, SimBus, Spy},
+        },
+        sim::{Environment, Model},
+    };
+
+    const GAINS: [Gain; 6] = [
+        Gain::Fsr6v144,
+        Gain::Fsr4v096,
+        Gain::Fsr2v048,
+        Gain::Fsr1v024,
+        Gain::Fsr0v512,
+        Gain::Fsr0v256,
+    ];
+
+    fn converter(volts: f32) -> SimAds1115<impl FnMut(u8) -> f32 + Send> {
+        // Each channel reads a tenth of a volt more than the one before
+        SimAds1115::new(move |channel| volts + f32::from(channel) / 10.0)
+    }
+
+    #[test]
+    fn starts_single_shot_conversions_against_ground() {
+        // OS 1, MUX 100 (AIN0), PGA 001 (4.096V), MODE 1, DR 100 (128 SPS), comparator off
+        assert_eq!(Settings::default().config(), 0xc383);
+        let settings = Settings {
+            channel: Channel(3),
+            gain: Gain::Fsr0v256,
+            data_rate: DataRate::Sps860,
+        };
+        // OS 1, MUX 111 (AIN3), PGA 101 (0.256V), MODE 1, DR 111 (860 SPS), comparator off
+        assert_eq!(settings.config(), 0xfbe3);
+    }
+
+    #[test]
+    fn writes_the_config_of_each_channel() {
+        let mut delay = SimClock::new().delay();
+        for channel in 0..4 {
+            let (spy, writes) = Spy::new(converter(1.0));
+            let bus = SimBus::new().with_device(DEFAULT_ADDRESS, spy);
+            let mut adc = Ads1115::new(bus, DEFAULT_ADDRESS);
+            let settings = Settings {
+                channel: Channel(channel),
+                gain: Gain::Fsr2v048,
+                data_rate: DataRate::Sps8,
+            };
+
+            let volts = adc.read_voltage(settings, &mut delay).unwrap();
+            assert!(
+                (volts - (1.0 + f32::from(channel) / 10.0)).abs() < 1e-3,
+                "{volts}"
+            );
+            let [msb, lsb] = settings.config().to_be_bytes();
+            assert_eq!(writes.lock().unwrap()[0], [REG_CONFIG, msb, lsb]);
+            assert_eq!(u16::from(msb) >> 4, 0b1100 | u16::from(channel));
+        }
+    }
+
+    #[test]
+    fn scales_conversions_to_the_full_scale_of_the_gain() {
+        let mut delay = SimClock::new().delay();
+        for gain in GAINS {
+            let bus = SimBus::new().with_device(DEFAULT_ADDRESS, converter(0.2));
+            let mut adc = Ads1115::new(bus, DEFAULT_ADDRESS);
+            let settings = Settings {
+                gain,
+                ..Settings::default()
+            };
+
+            let volts = adc.read_voltage(settings, &mut delay).unwrap();
+            // Within one step of the 16 bit range
+            let step = gain.full_scale() / 32768.0;
+            assert!((volts - 0.2).abs() <= step, "{gain:?}: {volts}");
+        }
+    }
+
+    #[test]
+    fn saturates_at_the_full_scale() {
+        let mut delay = SimClock::new().delay();
+        let bus = SimBus::new().with_device(DEFAULT_ADDRESS, converter(3.0));
+        let mut adc = Ads1115::new(bus, DEFAULT_ADDRESS);
+        let settings = Settings {
+            gain: Gain::Fsr2v048,
+            ..Settings::default()
+        };
+
+        let volts = adc.read_voltage(settings, &mut delay).unwrap();
+        assert_eq!(volts, 2.048 * 32767.0 / 32768.0);
+    }
+
+    #[test]
+    fn rejects_unknown_channels_and_data_rates() {
+        assert!(Channel::try_from(4).is_err());
+        assert_eq!(DataRate::try_from(475), Ok(DataRate::Sps475));
+        assert!(DataRate::try_from(100).is_err());
+        assert_eq!(DataRate::Sps8.conversion_ms(), 125);
+        assert_eq!(DataRate::Sps860.conversion_ms(), 2);
+    }
+
+    #[test]
+    fn converters_on_the_same_bus_share_a_lock() {
+        let backend = Backend::simulated(Environment::new(Model::default()).unwrap());
+        let first = Ads1115::new(backend.i2c(1).unwrap(), DEFAULT_ADDRESS);
+        let same = Ads1115::new(backend.i2c(1).unwrap(), DEFAULT_ADDRESS);
+        let other_address = Ads1115::new(backend.i2c(1).unwrap(), 0x49);
+        let other_bus = Ads1115::new(backend.i2c(3).unwrap(), DEFAULT_ADDRESS);
+
+        assert!(Arc::ptr_eq(&first.conversion, &same.conversion));
+        assert!(!Arc::ptr_eq(&first.conversion, &other_address.conversion));
+        assert!(!Arc::ptr_eq(&first.conversion, &other_bus.conversion));
+    }
+}
@@ -0,0 +1,120 @@
+//! Rust driver for the Rohm BH1750 ambient light sensor, generic over `embedded-hal` I2C buses.
+//!
+//! Every reading starts a one-time measurement at 1 lux resolution, after which the sensor
+//! powers down on its own. The sensor has no registers to read back, results are read as a
+//! single 16 bit count that is converted with the datasheet's typical sensitivity.
+//!
+#![deny(unsafe_code)]
+
+use embedded_hal::blocking::delay::{DelayMs, DelayUs};
+
+use super::{
+    bus::{BusError, I2cBus},
+    device::{Device, Measurement},
+    pin::Clock,
+    quantity::Quantity,
+    Error, Result,
+};
+
+/// The address with ADDR tied to ground, tying it high selects `0x5c`
+pub const DEFAULT_ADDRESS: u8 = 0x23;
+
+/// Counts per lux at the default measurement time
+pub(super) const COUNTS_PER_LUX: f32 = 1.2;
+/// Measurement range in lux, the full count at the default measurement time
+pub const ILLUMINANCE_RANGE: (f32, f32) = (0.0, u16::MAX as f32 / COUNTS_PER_LUX);
+
+pub(super) const POWER_ON: u8 = 0x01;
+/// Measures once at 1 lux resolution, then powers down
+pub(super) const ONE_TIME_HIGH_RES: u8 = 0x20;
+/// Longest time a high resolution measurement takes (in milliseconds)
+const MEASUREMENT_MS: u16 = 180;
+
+/// A BH1750 device.
+pub struct Bh1750<B> {
+    /// The bus the sensor is connected to.
+    bus: B,
+    /// The address of the sensor on the bus.
+    address: u8,
+}
+
+impl<B> Bh1750<B> {
+    /// Creates a device at `address` on the given bus.
+    pub fn new(bus: B, address: u8) -> Self {
+        Bh1750 { bus, address }
+    }
+}
+
+impl<B> Bh1750<B>
+where
+    B: I2cBus,
+    Error: From<BusError<B>>,
+{
+    /// Triggers a measurement and waits for it to complete.
+    pub fn perform_measurement<D: DelayMs<u16>>(&mut self, delay: &mut D) -> Result<Measurement> {
+        self.bus.write(self.address, &[POWER_ON])?;
+        self.bus.write(self.address, &[ONE_TIME_HIGH_RES])?;
+        delay.delay_ms(MEASUREMENT_MS);
+
+        let mut data = [0; 2];
+        self.bus.read(self.address, &mut data)?;
+        let counts = u16::from_be_bytes(data);
+        if counts == u16::MAX {
+            return Err(Error::Saturated);
+        }
+
+        Ok(Measurement::new().with(Quantity::Illuminance, f32::from(counts) / COUNTS_PER_LUX))
+    }
+}
+
+impl<B> Device for Bh1750<B>
+where
+    B: I2cBus,
+    Error: From<BusError<B>>,
+{
+    fn perform_measurement<D: DelayUs<u16> + DelayMs<u16> + Clock>(
+        &mut self,
+        delay: &mut D,
+    ) -> Result<Measurement> {
+        self.perform_measurement(delay)
+    }
+}
+
+#[cfg(all(test, feature = "sim"))]
+mod tests {
+    use super::*;
+    use crate::humidity::{
+        sim::SimClock,
+        sim_i2c::{SimBh1750, SimBus, Spy},
+    };
+
+    fn read(lux: f32) -> (Result<Measurement>, Vec<Vec<u8>>) {
+        let sample = move || Measurement::new().with(Quantity::Illuminance, lux);
+        let (spy, writes) = Spy::new(SimBh1750::new(sample));
+        let bus = SimBus::new().with_device(DEFAULT_ADDRESS, spy);
+        let result =
+            Bh1750::new(bus, DEFAULT_ADDRESS).perform_measurement(&mut SimClock::new().delay());
+        let writes = writes.lock().unwrap().clone();
+        (result, writes)
+    }
+
+    #[test]
+    fn converts_counts_to_lux() {
+        for lux in [0.0, 1.0, 350.0, 25_000.0, 54_000.0] {
+            let (result, writes) = read(lux);
+            let illuminance = result.unwrap().get(Quantity::Illuminance).unwrap();
+            // Within one count
+            assert!(
+                (illuminance - lux).abs() <= 1.0 / COUNTS_PER_LUX,
+                "{illuminance} != {lux}"
+            );
+            assert_eq!(writes, [[POWER_ON], [ONE_TIME_HIGH_RES]]);
+        }
+    }
+
+    #[test]
+    fn reports_saturation() {
+        let (result, _) = read(60_000.0);
+        assert!(matches!(result, Err(Error::Saturated)), "{result:?}");
+    }
+}
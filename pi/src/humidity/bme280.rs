@@ -0,0 +1,385 @@
+//! Rust driver for the Bosch BME280 temperature, humidity and pressure sensor, generic over `embedded-hal` I2C buses.
+//! The BMP280 shares the register map without the humidity part and is read through the same driver.
+//!
+//! Every reading triggers a measurement in forced mode, after which the sensor returns to sleep.
+//! The raw values are compensated with the factory calibration stored on the chip, using the
+//! floating point formulas from section 8.1 of the datasheet.
+//!
+#![deny(unsafe_code)]
+
+use embedded_hal::blocking::delay::{DelayMs, DelayUs};
+
+use super::{
+    bus::{BusError, I2cBus},
+    device::{Device, Measurement},
+    pin::Clock,
+    quantity::Quantity,
+    Error, Result,
+};
+
+/// The address with SDO tied to ground, tying it to VDDIO selects `0x77`
+pub const DEFAULT_ADDRESS: u8 = 0x76;
+
+/// Operating range of the temperature measurement in degrees Celsius
+pub const TEMPERATURE_RANGE: (f32, f32) = (-40.0, 85.0);
+/// Operating range of the humidity measurement in percent
+pub const HUMIDITY_RANGE: (f32, f32) = (0.0, 100.0);
+/// Operating range of the pressure measurement in hectopascal
+pub const PRESSURE_RANGE: (f32, f32) = (300.0, 1100.0);
+
+pub(super) const REG_CALIBRATION_TP: u8 = 0x88;
+pub(super) const REG_CHIP_ID: u8 = 0xd0;
+pub(super) const REG_CALIBRATION_H: u8 = 0xe1;
+pub(super) const REG_CTRL_HUM: u8 = 0xf2;
+pub(super) const REG_STATUS: u8 = 0xf3;
+pub(super) const REG_CTRL_MEAS: u8 = 0xf4;
+pub(super) const REG_DATA: u8 = 0xf7;
+
+pub(super) const CHIP_ID_BME280: u8 = 0x60;
+/// Chip ids of the BMP280 samples and mass production parts
+const CHIP_IDS_BMP280: [u8; 3] = [0x56, 0x57, 0x58];
+
+/// Oversampling setting taking a single sample of a quantity
+const OVERSAMPLING_X1: u8 = 0b001;
+/// Mode bits of `ctrl_meas` starting a single measurement
+pub(super) const MODE_FORCED: u8 = 0b01;
+/// Bit of the status register set while a measurement is running
+pub(super) const STATUS_MEASURING: u8 = 1 << 3;
+
+/// Longest measurement time with single samples of all three quantities (in microseconds)
+const MEASUREMENT_US: u16 = 9_300;
+/// How often the status is polled after the expected measurement time has passed
+const STATUS_POLLS: u16 = 10;
+
+/// Value of the temperature and pressure registers when the measurement was skipped
+const SKIPPED: u32 = 0x80000;
+
+/// Which variant of the chip answered.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Chip {
+    /// Temperature, humidity and pressure.
+    Bme280,
+    /// Temperature and pressure only.
+    Bmp280,
+}
+
+impl Chip {
+    /// Identifies the chip from the contents of its id register.
+    pub fn from_id(id: u8) -> Result<Self> {
+        match id {
+            CHIP_ID_BME280 => Ok(Chip::Bme280),
+            id if CHIP_IDS_BMP280.contains(&id) => Ok(Chip::Bmp280),
+            id => Err(Error::UnknownChip { id }),
+        }
+    }
+}
+
+/// The factory calibration coefficients, named as in the datasheet.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Calibration {
+    t1: f64,
+    t2: f64,
+    t3: f64,
+    p: [f64; 9],
+    h1: f64,
+    h2: f64,
+    h3: f64,
+    h4: f64,
+    h5: f64,
+    h6: f64,
+}
+
+impl Calibration {
+    /// Parses the 26 bytes starting at `0x88` and the 7 bytes starting at `0xe1`.
+    pub fn from_registers(tp: &[u8; 26], h: &[u8; 7]) -> Self {
+        let unsigned = |i: usize| f64::from(u16::from_le_bytes([tp[i], tp[i + 1]]));
+        let signed = |i: usize| f64::from(i16::from_le_bytes([tp[i], tp[i + 1]]));
+
+        let mut p = [unsigned(6), 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0];
+        for (n, coefficient) in p.iter_mut().enumerate().skip(1) {
+            *coefficient = signed(6 + n * 2);
+        }
+
+        Calibration {
+            t1: unsigned(0),
+            t2: signed(2),
+            t3: signed(4),
+            p,
+            h1: f64::from(tp[25]),
+            h2: f64::from(i16::from_le_bytes([h[0], h[1]])),
+            h3: f64::from(h[2]),
+            // Two 12 bit values sharing the nibbles of 0xe5
+            h4: f64::from(i16::from(h[3] as i8) << 4 | i16::from(h[4] & 0x0f)),
+            h5: f64::from(i16::from(h[5] as i8) << 4 | i16::from(h[4] >> 4)),
+            h6: f64::from(h[6] as i8),
+        }
+    }
+
+    /// The fine temperature the pressure and humidity compensation depend on.
+    pub fn t_fine(&self, adc_t: u32) -> f64 {
+        let adc_t = f64::from(adc_t);
+        let var1 = (adc_t / 16384.0 - self.t1 / 1024.0) * self.t2;
+        let var2 = (adc_t / 131072.0 - self.t1 / 8192.0).powi(2) * self.t3;
+        var1 + var2
+    }
+
+    /// Temperature in degrees Celsius.
+    pub fn temperature(&self, t_fine: f64) -> f64 {
+        t_fine / 5120.0
+    }
+
+    /// Pressure in pascal.
+    pub fn pressure(&self, t_fine: f64, adc_p: u32) -> f64 {
+        let [p1, p2, p3, p4, p5, p6, p7, p8, p9] = self.p;
+        let mut var1 = t_fine / 2.0 - 64000.0;
+        let mut var2 = var1 * var1 * p6 / 32768.0;
+        var2 += var1 * p5 * 2.0;
+        var2 = var2 / 4.0 + p4 * 65536.0;
+        var1 = (p3 * var1 * var1 / 524288.0 + p2 * var1) / 524288.0;
+        var1 = (1.0 + var1 / 32768.0) * p1;
+        if var1 == 0.0 {
+            // Avoids dividing by zero on a blank calibration
+            return 0.0;
+        }
+
+        let mut pressure = 1048576.0 - f64::from(adc_p);
+        pressure = (pressure - var2 / 4096.0) * 6250.0 / var1;
+        var1 = p9 * pressure * pressure / 2147483648.0;
+        var2 = pressure * p8 / 32768.0;
+        pressure + (var1 + var2 + p7) / 16.0
+    }
+
+    /// Relative humidity in percent.
+    pub fn humidity(&self, t_fine: f64, adc_h: u16) -> f64 {
+        let mut humidity = t_fine - 76800.0;
+        humidity = (f64::from(adc_h) - (self.h4 * 64.0 + self.h5 / 16384.0 * humidity))
+            * (self.h2 / 65536.0
+                * (1.0
+                    + self.h6 / 67108864.0 * humidity * (1.0 + self.h3 / 67108864.0 * humidity)));
+        humidity *= 1.0 - self.h1 * humidity / 524288.0;
+        humidity.clamp(0.0, 100.0)
+    }
+}
+
+/// A BME280 or BMP280 device.
+pub struct Bme280<B> {
+    /// The bus the sensor is connected to.
+    bus: B,
+    /// The address of the sensor on the bus.
+    address: u8,
+    /// The chip and its calibration, read before the first measurement.
+    chip: Option<(Chip, Calibration)>,
+}
+
+impl<B> Bme280<B> {
+    /// Creates a device at `address` on the given bus.
+    pub fn new(bus: B, address: u8) -> Self {
+        Bme280 {
+            bus,
+            address,
+            chip: None,
+        }
+    }
+}
+
+impl<B> Bme280<B>
+where
+    B: I2cBus,
+    Error: From<BusError<B>>,
+{
+    /// Triggers a measurement and waits for it to complete.
+    pub fn perform_measurement<D>(&mut self, delay: &mut D) -> Result<Measurement>
+    where
+        D: DelayUs<u16> + DelayMs<u16>,
+    {
+        let (chip, calibration) = self.identify()?;
+
+        if chip == Chip::Bme280 {
+            // Only takes effect once ctrl_meas is written
+            self.write(REG_CTRL_HUM, OVERSAMPLING_X1)?;
+        }
+        self.write(
+            REG_CTRL_MEAS,
+            OVERSAMPLING_X1 << 5 | OVERSAMPLING_X1 << 2 | MODE_FORCED,
+        )?;
+        delay.delay_us(MEASUREMENT_US);
+        self.wait_for_measurement(delay)?;
+
+        let mut data = [0; 8];
+        self.bus.write_read(self.address, &[REG_DATA], &mut data)?;
+        let adc_p = u32::from(data[0]) << 12 | u32::from(data[1]) << 4 | u32::from(data[2]) >> 4;
+        let adc_t = u32::from(data[3]) << 12 | u32::from(data[4]) << 4 | u32::from(data[5]) >> 4;
+        let adc_h = u16::from_be_bytes([data[6], data[7]]);
+        if adc_t == SKIPPED || adc_p == SKIPPED {
+            return Err(Error::NotReady);
+        }
+
+        let t_fine = calibration.t_fine(adc_t);
+        let mut measurement = Measurement::new()
+            .with(
+                Quantity::Temperature,
+                calibration.temperature(t_fine) as f32,
+            )
+            .with(
+                Quantity::Pressure,
+                (calibration.pressure(t_fine, adc_p) / 100.0) as f32,
+            );
+        // The BMP280 has no humidity sensor
+        if chip == Chip::Bme280 {
+            measurement.set(
+                Quantity::Humidity,
+                Some(calibration.humidity(t_fine, adc_h) as f32),
+            );
+        }
+        Ok(measurement)
+    }
+
+    /// Reads the chip id and calibration on first use.
+    fn identify(&mut self) -> Result<(Chip, Calibration)> {
+        if let Some(chip) = self.chip {
+            return Ok(chip);
+        }
+
+        let chip = Chip::from_id(self.read::<1>(REG_CHIP_ID)?[0])?;
+        let tp = self.read::<26>(REG_CALIBRATION_TP)?;
+        let h = match chip {
+            Chip::Bme280 => self.read::<7>(REG_CALIBRATION_H)?,
+            Chip::Bmp280 => [0; 7],
+        };
+        let identified = (chip, Calibration::from_registers(&tp, &h));
+        self.chip = Some(identified);
+        Ok(identified)
+    }
+
+    fn wait_for_measurement<D: DelayMs<u16>>(&mut self, delay: &mut D) -> Result<()> {
+        for _ in 0..STATUS_POLLS {
+            if self.read::<1>(REG_STATUS)?[0] & STATUS_MEASURING == 0 {
+                return Ok(());
+            }
+            delay.delay_ms(1);
+        }
+        Err(Error::NotReady)
+    }
+
+    fn read<const N: usize>(&mut self, register: u8) -> Result<[u8; N]> {
+        let mut buffer = [0; N];
+        self.bus
+            .write_read(self.address, &[register], &mut buffer)?;
+        Ok(buffer)
+    }
+
+    fn write(&mut self, register: u8, value: u8) -> Result<()> {
+        self.bus.write(self.address, &[register, value])?;
+        Ok(())
+    }
+}
+
+impl<B> Device for Bme280<B>
+where
+    B: I2cBus,
+    Error: From<BusError<B>>,
+{
+    fn perform_measurement<D: DelayUs<u16> + DelayMs<u16> + Clock>(
+        &mut self,
+        delay: &mut D,
+    ) -> Result<Measurement> {
+        self.perform_measurement(delay)
+    }
+}
+
+#[cfg(all(test, feature = "sim"))]
+mod tests {
+    use super::*;
+    use crate::humidity::{
+        sim::SimClock,
+        sim_i2c::{Registers, SimBus},
+    };
+
+    /// A BME280 holding the calibration and raw temperature and pressure of the compensation
+    /// example in section 8.2 of the BMP280 datasheet, with a raw humidity of 0x7000.
+    const DUMP: &str = "     0  1  2  3  4  5  6  7  8  9  a  b  c  d  e  f    0123456789abcdef
+80: 00 00 00 00 00 00 00 00 70 6b 43 67 18 fc 7d 8e    ........pkCg??}?
+90: 43 d6 d0 0b 27 0b 8c 00 f9 ff 8c 3c f8 c6 70 17    C???'??.?.?<??p?
+a0: 00 4b 00 00 00 00 00 00 00 00 00 00 00 00 00 00    .K..............
+d0: 60 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00    `...............
+e0: 00 6a 01 00 14 24 03 1e 00 00 00 00 00 00 00 00    .j?.?$??........
+f0: 00 00 00 00 00 00 00 65 5a c0 7e ed 00 70 00 00    .......eZ?~?.p..
+";
+
+    fn read(registers: Registers) -> Measurement {
+        let bus = SimBus::new().with_device(DEFAULT_ADDRESS, registers);
+        let mut bme280 = Bme280::new(bus, DEFAULT_ADDRESS);
+        bme280
+            .perform_measurement(&mut SimClock::new().delay())
+            .unwrap()
+    }
+
+    fn assert_close(actual: Option<f32>, expected: f32) {
+        let actual = actual.unwrap();
+        assert!((actual - expected).abs() < 0.01, "{actual} != {expected}");
+    }
+
+    #[test]
+    fn parses_the_calibration() {
+        let registers = Registers::from_dump(DUMP).unwrap();
+        let mut tp = [0; 26];
+        tp.copy_from_slice(registers.get(REG_CALIBRATION_TP, 26));
+        let mut h = [0; 7];
+        h.copy_from_slice(registers.get(REG_CALIBRATION_H, 7));
+        let calibration = Calibration::from_registers(&tp, &h);
+
+        assert_eq!(
+            (calibration.t1, calibration.t2, calibration.t3),
+            (27504.0, 26435.0, -1000.0)
+        );
+        assert_eq!(
+            calibration.p,
+            [36477.0, -10685.0, 3024.0, 2855.0, 140.0, -7.0, 15500.0, -14600.0, 6000.0]
+        );
+        assert_eq!(
+            [
+                calibration.h1,
+                calibration.h2,
+                calibration.h3,
+                calibration.h4,
+                calibration.h5,
+                calibration.h6
+            ],
+            [75.0, 362.0, 0.0, 324.0, 50.0, 30.0]
+        );
+    }
+
+    #[test]
+    fn compensates_like_the_reference() {
+        let measurement = read(Registers::from_dump(DUMP).unwrap());
+
+        // 25.08°C and 100653.27Pa in the datasheet, the humidity from Bosch's BME280 driver
+        assert_close(measurement.get(Quantity::Temperature), 25.08);
+        assert_close(measurement.get(Quantity::Pressure), 1006.5327);
+        assert_close(measurement.get(Quantity::Humidity), 43.68);
+    }
+
+    #[test]
+    fn reads_a_bmp280_without_humidity() {
+        let mut registers = Registers::from_dump(DUMP).unwrap();
+        registers.set(REG_CHIP_ID, &[0x58]);
+        let measurement = read(registers);
+
+        assert_close(measurement.get(Quantity::Temperature), 25.08);
+        assert_close(measurement.get(Quantity::Pressure), 1006.5327);
+        assert_eq!(measurement.get(Quantity::Humidity), None);
+    }
+
+    #[test]
+    fn rejects_unknown_chips() {
+        let mut registers = Registers::from_dump(DUMP).unwrap();
+        registers.set(REG_CHIP_ID, &[0x61]);
+        let bus = SimBus::new().with_device(DEFAULT_ADDRESS, registers);
+        let result =
+            Bme280::new(bus, DEFAULT_ADDRESS).perform_measurement(&mut SimClock::new().delay());
+        assert!(
+            matches!(result, Err(Error::UnknownChip { id: 0x61 })),
+            "{result:?}"
+        );
+    }
+}
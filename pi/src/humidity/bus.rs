@@ -0,0 +1,52 @@
+use std::{
+    io,
+    sync::{Arc, Mutex},
+};
+
+use embedded_hal::blocking::i2c::{Read, Write, WriteRead};
+
+/// An I2C bus that sensors can be addressed on.
+pub trait I2cBus: Read<Error = BusError<Self>> + Write<Error = BusError<Self>> + WriteRead {}
+
+impl<B> I2cBus for B where B: Read<Error = BusError<B>> + Write<Error = BusError<B>> + WriteRead {}
+
+/// The error type shared by all operations of an [`I2cBus`].
+pub type BusError<B> = <B as WriteRead>::Error;
+
+/// Held while a device is in the middle of a sequence of transfers, e.g. from starting a
+/// conversion until reading its result.
+pub type DeviceLock = Arc<Mutex<()>>;
+
+/// A bus whose devices may be read by several sensors, e.g. the channels of an ADC.
+pub trait SharedDevices {
+    /// The lock of the device at `address`, the same for every handle to the bus.
+    fn device_lock(&self, address: u8) -> DeviceLock;
+}
+
+/// The kernel's 1-Wire bus master, which reads the devices on the bus on our behalf.
+pub trait W1Bus {
+    type Error;
+
+    /// The ids of the devices on the bus, e.g. `28-0316a2799fff`.
+    fn devices(&mut self) -> Result<Vec<String>, Self::Error>;
+    /// The contents of the `w1_slave` file of `device`.
+    fn read_slave(&mut self, device: &str) -> Result<String, Self::Error>;
+}
+
+/// A device of the kernel's Industrial I/O subsystem, whose channels read as text.
+pub trait IioDevice {
+    /// The contents of a channel file of the device, e.g. `in_temp_input`.
+    fn read_channel(&mut self, channel: &str) -> io::Result<String>;
+}
+
+/// A serial port whose reads give up once the line stays silent for a while.
+pub trait SerialPort {
+    type Error;
+
+    /// Sends all of `bytes`.
+    fn write_all(&mut self, bytes: &[u8]) -> Result<(), Self::Error>;
+    /// Fills `buffer`, returning how many bytes arrived before the line went silent.
+    fn read(&mut self, buffer: &mut [u8]) -> Result<usize, Self::Error>;
+    /// Drops bytes that were received but not read, e.g. the rest of a garbled frame.
+    fn discard_input(&mut self) -> Result<(), Self::Error>;
+}
@@ -0,0 +1,186 @@
+//! Climate metrics derived from the temperature and relative humidity of a measurement.
+//!
+//! Vapour pressures use the Tetens equation and the dew point the Magnus formula with the
+//! coefficients of Sonntag (1990), both accurate to a fraction of a percent in the range
+//! plants grow in. The heat index follows the regression of the US National Weather Service.
+//!
+#![deny(unsafe_code)]
+
+use super::{device::Measurement, quantity::Quantity};
+
+/// Leaves transpiring under lights are usually this much cooler than the air (in degrees Celsius)
+pub const DEFAULT_LEAF_TEMPERATURE_OFFSET: f32 = -2.0;
+
+/// Specific gas constant of water vapour in J/(kg·K)
+const WATER_VAPOUR_GAS_CONSTANT: f32 = 461.5;
+const MAGNUS_A: f32 = 17.62;
+const MAGNUS_B: f32 = 243.12;
+
+/// The pressure of saturated water vapour over water at `temperature` (in °C), in kPa.
+pub fn saturation_vapour_pressure(temperature: f32) -> f32 {
+    0.61078 * (17.27 * temperature / (temperature + 237.3)).exp()
+}
+
+/// The temperature (in °C) at which the air would be saturated, `None` for completely dry air.
+pub fn dew_point(temperature: f32, humidity: f32) -> Option<f32> {
+    if humidity <= 0.0 {
+        return None;
+    }
+    let gamma = (humidity / 100.0).ln() + MAGNUS_A * temperature / (MAGNUS_B + temperature);
+    Some(MAGNUS_B * gamma / (MAGNUS_A - gamma))
+}
+
+/// The mass of water vapour in a cubic metre of air, in g/m³.
+pub fn absolute_humidity(temperature: f32, humidity: f32) -> f32 {
+    let vapour_pressure = saturation_vapour_pressure(temperature) * 1000.0 * humidity / 100.0;
+    vapour_pressure / (WATER_VAPOUR_GAS_CONSTANT * (temperature + 273.15)) * 1000.0
+}
+
+/// How hot the air feels (in °C), taking the humidity into account.
+pub fn heat_index(temperature: f32, humidity: f32) -> f32 {
+    // The regression is fitted in degrees Fahrenheit
+    let t = temperature * 9.0 / 5.0 + 32.0;
+    let rh = humidity;
+    let simple = 0.5 * (t + 61.0 + (t - 68.0) * 1.2 + rh * 0.094);
+    let index = if (simple + t) / 2.0 < 80.0 {
+        simple
+    } else {
+        let mut index = -42.379 + 2.049_015_3 * t + 10.143_332 * rh
+            - 0.224_755_4 * t * rh
+            - 0.006_837_83 * t * t
+            - 0.054_817_17 * rh * rh
+            + 0.001_228_74 * t * t * rh
+            + 0.000_852_82 * t * rh * rh
+            - 0.000_001_99 * t * t * rh * rh;
+        if rh < 13.0 && (80.0..=112.0).contains(&t) {
+            index -= (13.0 - rh) / 4.0 * ((17.0 - (t - 95.0).abs()) / 17.0).sqrt();
+        } else if rh > 85.0 && (80.0..=87.0).contains(&t) {
+            index += (rh - 85.0) / 10.0 * (87.0 - t) / 5.0;
+        }
+        index
+    };
+    (index - 32.0) * 5.0 / 9.0
+}
+
+/// Adds the derived metrics to a measurement with both temperature and humidity, leaving others
+/// as they are. Leaves are assumed to be `leaf_temperature_offset` warmer than the air.
+pub fn derive(measurement: &mut Measurement, leaf_temperature_offset: f32) {
+    let (Some(temperature), Some(humidity)) = (
+        measurement.get(Quantity::Temperature),
+        measurement.get(Quantity::Humidity),
+    ) else {
+        return;
+    };
+
+    let saturated = saturation_vapour_pressure(temperature);
+    let vapour_pressure = saturated * humidity / 100.0;
+    let leaf_saturated = saturation_vapour_pressure(temperature + leaf_temperature_offset);
+    measurement.set(Quantity::Vpd, Some(saturated - vapour_pressure));
+    measurement.set(Quantity::LeafVpd, Some(leaf_saturated - vapour_pressure));
+    measurement.set(Quantity::DewPoint, dew_point(temperature, humidity));
+    measurement.set(
+        Quantity::AbsoluteHumidity,
+        Some(absolute_humidity(temperature, humidity)),
+    );
+    measurement.set(Quantity::HeatIndex, Some(heat_index(temperature, humidity)));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: f32, expected: f32, tolerance: f32) {
+        assert!(
+            (actual - expected).abs() <= tolerance,
+            "{actual} is not within {tolerance} of {expected}"
+        );
+    }
+
+    /// The heat index in °F for a temperature in °F, the unit the NWS tables use
+    fn heat_index_f(temperature: f32, humidity: f32) -> f32 {
+        heat_index((temperature - 32.0) * 5.0 / 9.0, humidity) * 9.0 / 5.0 + 32.0
+    }
+
+    #[test]
+    fn computes_the_saturation_vapour_pressure() {
+        assert_close(saturation_vapour_pressure(0.0), 0.6108, 0.0005);
+        assert_close(saturation_vapour_pressure(25.0), 3.168, 0.002);
+        assert_close(saturation_vapour_pressure(100.0), 101.3, 1.0);
+    }
+
+    #[test]
+    fn computes_the_dew_point() {
+        assert_close(dew_point(25.0, 60.0).unwrap(), 16.69, 0.02);
+        assert_close(dew_point(20.0, 100.0).unwrap(), 20.0, 0.001);
+        assert_close(dew_point(-10.0, 80.0).unwrap(), -12.80, 0.02);
+        assert_eq!(dew_point(25.0, 0.0), None);
+    }
+
+    #[test]
+    fn computes_the_absolute_humidity() {
+        assert_close(absolute_humidity(25.0, 60.0), 13.81, 0.02);
+        assert_close(absolute_humidity(30.0, 100.0), 30.3, 0.1);
+        assert_eq!(absolute_humidity(25.0, 0.0), 0.0);
+    }
+
+    #[test]
+    fn uses_the_simple_formula_in_mild_weather() {
+        assert_close(heat_index_f(68.0, 50.0), 66.85, 0.01);
+        // Just below the switch to the regression, which only holds in the heat
+        assert_close(heat_index_f(80.0, 48.0), 79.96, 0.01);
+    }
+
+    #[test]
+    fn matches_the_nws_heat_index_table() {
+        assert_close(heat_index_f(80.0, 50.0), 80.80, 0.02);
+        assert_close(heat_index_f(90.0, 70.0), 105.92, 0.05);
+        assert_close(heat_index_f(96.0, 40.0), 100.89, 0.05);
+        assert_close(heat_index(32.0, 70.0), 40.41, 0.05);
+    }
+
+    #[test]
+    fn adjusts_the_heat_index_for_dry_heat() {
+        // 94.75 °F before the adjustment
+        assert_close(heat_index_f(100.0, 10.0), 94.12, 0.05);
+        // Only below 13 % is the air dry enough for the adjustment
+        assert_close(heat_index_f(100.0, 13.0), 95.35, 0.05);
+        assert!(heat_index_f(100.0, 12.9) < heat_index_f(100.0, 13.0));
+    }
+
+    #[test]
+    fn adjusts_the_heat_index_for_humid_heat() {
+        // 101.58 °F before the adjustment
+        assert_close(heat_index_f(85.0, 90.0), 101.78, 0.05);
+        // The adjustment fades out at 87 °F
+        assert_close(heat_index_f(87.0, 90.0), 109.18, 0.05);
+    }
+
+    #[test]
+    fn derives_metrics_from_temperature_and_humidity() {
+        let mut measurement = Measurement::new()
+            .with(Quantity::Temperature, 25.0)
+            .with(Quantity::Humidity, 60.0);
+        derive(&mut measurement, DEFAULT_LEAF_TEMPERATURE_OFFSET);
+
+        assert_close(measurement.get(Quantity::Vpd).unwrap(), 1.267, 0.002);
+        // The leaves at 23 °C hold less vapour than the air at 25 °C
+        assert_close(measurement.get(Quantity::LeafVpd).unwrap(), 0.909, 0.002);
+        assert_close(measurement.get(Quantity::DewPoint).unwrap(), 16.69, 0.02);
+        assert_close(
+            measurement.get(Quantity::AbsoluteHumidity).unwrap(),
+            13.81,
+            0.02,
+        );
+        assert_close(measurement.get(Quantity::HeatIndex).unwrap(), 25.12, 0.02);
+    }
+
+    #[test]
+    fn leaves_measurements_without_humidity_alone() {
+        let mut measurement = Measurement::new().with(Quantity::Temperature, 25.0);
+        derive(&mut measurement, DEFAULT_LEAF_TEMPERATURE_OFFSET);
+        assert_eq!(
+            measurement,
+            Measurement::new().with(Quantity::Temperature, 25.0)
+        );
+    }
+}
@@ -0,0 +1,252 @@
+//! Linear corrections of measured values, fitted to readings taken against reference values.
+//!
+//! A single reference point shifts the values onto it, keeping the slope. Two points, e.g.
+//! humidity in jars over magnesium chloride (33%) and sodium chloride (75%) solutions, also
+//! correct the slope. Corrections apply to raw measurements, before anything is derived from them.
+//!
+#![deny(unsafe_code)]
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use super::{device::Measurement, quantity::Quantity};
+
+/// Smallest difference between the raw values of two points that still gives a usable slope
+const MIN_RAW_SPREAD: f32 = 1.0;
+
+/// The corrections of a sensor, by quantity.
+pub type Corrections = BTreeMap<Quantity, Correction>;
+
+#[derive(thiserror::Error, Debug, Clone, Copy, PartialEq)]
+pub enum FitError {
+    #[error("{quantity} is derived from other quantities, calibrate those instead")]
+    Derived { quantity: Quantity },
+    #[error("A gain of {gain} would not map values back to the sensor's, it has to be positive")]
+    Gain { gain: f32 },
+    #[error(
+        "The points measured {first} and {second}, they need to be at least {MIN_RAW_SPREAD} apart"
+    )]
+    TooClose { first: f32, second: f32 },
+}
+
+/// Maps a raw value to `raw * gain + offset`.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Correction {
+    pub gain: f32,
+    pub offset: f32,
+}
+
+impl Default for Correction {
+    fn default() -> Self {
+        Correction {
+            gain: 1.0,
+            offset: 0.0,
+        }
+    }
+}
+
+impl Correction {
+    /// Fits the correction through the last two points, or keeps its gain and moves the
+    /// offset onto a single one, so a gain fitted earlier or configured by hand isn't lost.
+    pub fn fit(self, points: &[Point]) -> Result<Self, FitError> {
+        let correction = match points {
+            [] => self,
+            [point] => Correction {
+                gain: self.gain,
+                offset: point.reference - self.gain * point.raw,
+            },
+            [.., first, second] => {
+                if (second.raw - first.raw).abs() < MIN_RAW_SPREAD {
+                    return Err(FitError::TooClose {
+                        first: first.raw,
+                        second: second.raw,
+                    });
+                }
+                let gain = (second.reference - first.reference) / (second.raw - first.raw);
+                Correction {
+                    gain,
+                    offset: first.reference - gain * first.raw,
+                }
+            }
+        };
+        correction.check()?;
+        Ok(correction)
+    }
+
+    /// Checks that the correction can be undone, which capturing further points relies on.
+    pub fn check(&self) -> Result<(), FitError> {
+        if self.gain.is_finite() && self.gain > 0.0 {
+            Ok(())
+        } else {
+            Err(FitError::Gain { gain: self.gain })
+        }
+    }
+
+    pub fn apply(&self, raw: f32) -> f32 {
+        raw * self.gain + self.offset
+    }
+
+    /// The raw value that the correction maps to `corrected`.
+    pub fn invert(&self, corrected: f32) -> f32 {
+        (corrected - self.offset) / self.gain
+    }
+}
+
+/// A raw value measured while the sensor was exposed to a known reference value.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Point {
+    pub raw: f32,
+    pub reference: f32,
+}
+
+/// Checks that the corrections only cover quantities that are measured rather than derived.
+pub fn check(corrections: &Corrections) -> Result<(), FitError> {
+    for (&quantity, correction) in corrections {
+        if quantity.is_derived() {
+            return Err(FitError::Derived { quantity });
+        }
+        correction.check()?;
+    }
+    Ok(())
+}
+
+/// Corrects the values of a raw measurement that there are corrections for.
+pub fn apply(corrections: &Corrections, measurement: &mut Measurement) {
+    for (&quantity, correction) in corrections {
+        let corrected = measurement.get(quantity).map(|raw| correction.apply(raw));
+        measurement.set(quantity, corrected);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point(raw: f32, reference: f32) -> Point {
+        Point { raw, reference }
+    }
+
+    fn assert_close(actual: f32, expected: f32) {
+        assert!(
+            (actual - expected).abs() < 1e-4,
+            "{actual} is not close to {expected}"
+        );
+    }
+
+    #[test]
+    fn keeps_the_correction_without_points() {
+        let correction = Correction {
+            gain: 1.1,
+            offset: -3.0,
+        };
+        assert_eq!(correction.fit(&[]), Ok(correction));
+    }
+
+    #[test]
+    fn keeps_the_gain_with_a_single_point() {
+        let correction = Correction {
+            gain: 1.1,
+            offset: 4.0,
+        };
+        let fitted = correction.fit(&[point(70.0, 75.0)]).unwrap();
+        assert_eq!(fitted.gain, 1.1);
+        assert_close(fitted.offset, -2.0);
+        assert_close(fitted.apply(70.0), 75.0);
+    }
+
+    #[test]
+    fn fits_the_slope_through_two_points() {
+        let fitted = Correction::default()
+            .fit(&[point(35.0, 33.0), point(72.0, 75.0)])
+            .unwrap();
+        assert_close(fitted.gain, 42.0 / 37.0);
+        assert_close(fitted.apply(35.0), 33.0);
+        assert_close(fitted.apply(72.0), 75.0);
+    }
+
+    #[test]
+    fn fits_the_last_two_points() {
+        let fitted = Correction::default()
+            .fit(&[point(10.0, 0.0), point(35.0, 33.0), point(72.0, 75.0)])
+            .unwrap();
+        assert_close(fitted.apply(35.0), 33.0);
+        assert_close(fitted.apply(72.0), 75.0);
+    }
+
+    #[test]
+    fn rejects_points_with_equal_raw_values() {
+        let points = [point(50.0, 33.0), point(50.0, 75.0)];
+        assert_eq!(
+            Correction::default().fit(&points),
+            Err(FitError::TooClose {
+                first: 50.0,
+                second: 50.0,
+            })
+        );
+        let points = [point(50.0, 33.0), point(50.5, 75.0)];
+        assert!(matches!(
+            Correction::default().fit(&points),
+            Err(FitError::TooClose { .. })
+        ));
+    }
+
+    #[test]
+    fn rejects_a_falling_slope() {
+        let points = [point(30.0, 75.0), point(70.0, 33.0)];
+        assert!(matches!(
+            Correction::default().fit(&points),
+            Err(FitError::Gain { .. })
+        ));
+    }
+
+    #[test]
+    fn inverts_what_it_applies() {
+        let correction = Correction {
+            gain: 0.93,
+            offset: 2.5,
+        };
+        for raw in [-20.0, 0.0, 33.3, 75.0, 1013.25] {
+            assert_close(correction.invert(correction.apply(raw)), raw);
+        }
+    }
+
+    #[test]
+    fn rejects_corrections_of_derived_quantities() {
+        let mut corrections = Corrections::new();
+        corrections.insert(Quantity::Humidity, Correction::default());
+        assert_eq!(check(&corrections), Ok(()));
+
+        corrections.insert(Quantity::DewPoint, Correction::default());
+        assert_eq!(
+            check(&corrections),
+            Err(FitError::Derived {
+                quantity: Quantity::DewPoint
+            })
+        );
+    }
+
+    #[test]
+    fn corrects_the_quantities_it_has_corrections_for() {
+        let mut corrections = Corrections::new();
+        corrections.insert(
+            Quantity::Humidity,
+            Correction {
+                gain: 1.0,
+                offset: 3.0,
+            },
+        );
+        corrections.insert(Quantity::Co2, Correction::default());
+        let mut measurement = Measurement::new()
+            .with(Quantity::Temperature, 21.0)
+            .with(Quantity::Humidity, 50.0);
+        apply(&corrections, &mut measurement);
+        assert_eq!(
+            measurement,
+            Measurement::new()
+                .with(Quantity::Temperature, 21.0)
+                .with(Quantity::Humidity, 53.0)
+        );
+    }
+}
@@ -1,23 +1,133 @@
-use super::Result;
+use core::{fmt, time::Duration};
+
+use super::{
+    pin::Clock,
+    quantity::{Quantity, Value},
+    Result,
+};
 use embedded_hal::blocking::delay::{DelayMs, DelayUs};
 use serde::{Deserialize, Serialize};
 
-#[derive(Copy, Clone, Default, Debug, Serialize, Deserialize)]
+/// The values a sensor measured in a single sample, at most one per quantity.
+///
+/// Serialized as the list of values, each with its quantity and unit.
+#[derive(Copy, Clone, Default, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(into = "Vec<Value>", try_from = "Vec<Value>")]
 pub struct Measurement {
-    /// The measured temperature in tenths of degrees Celsius.
-    pub temperature: f32,
-    /// The measured humidity in tenths of a percent.
-    pub humidity: f32,
+    values: [Option<f32>; Quantity::ALL.len()],
+}
+
+impl Measurement {
+    /// A measurement without any values.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the value of `quantity`, replacing any previous one.
+    pub fn with(mut self, quantity: Quantity, value: f32) -> Self {
+        self.set(quantity, Some(value));
+        self
+    }
+
+    /// The value of `quantity`, if the sensor measured it.
+    pub fn get(&self, quantity: Quantity) -> Option<f32> {
+        self.values[quantity.index()]
+    }
+
+    /// Replaces or removes the value of `quantity`.
+    pub fn set(&mut self, quantity: Quantity, value: Option<f32>) {
+        self.values[quantity.index()] = value;
+    }
+
+    /// The measured values, in the order of [`Quantity::ALL`].
+    pub fn values(&self) -> impl Iterator<Item = Value> + '_ {
+        Quantity::ALL
+            .into_iter()
+            .filter_map(|quantity| Some(Value::new(quantity, self.get(quantity)?)))
+    }
+}
+
+impl From<Measurement> for Vec<Value> {
+    fn from(measurement: Measurement) -> Self {
+        measurement.values().collect()
+    }
+}
+
+impl TryFrom<Vec<Value>> for Measurement {
+    type Error = String;
+
+    fn try_from(values: Vec<Value>) -> core::result::Result<Self, Self::Error> {
+        let mut measurement = Measurement::new();
+        for value in values {
+            if value.unit != value.quantity.unit() {
+                return Err(format!(
+                    "{} is recorded in {}, not {}",
+                    value.quantity,
+                    value.quantity.unit(),
+                    value.unit
+                ));
+            }
+            measurement.set(value.quantity, Some(value.value));
+        }
+        Ok(measurement)
+    }
+}
+
+impl fmt::Display for Measurement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let values: Vec<_> = self.values().map(|value| value.to_string()).collect();
+        write!(f, "{}", values.join(", "))
+    }
 }
 
 pub trait Device {
-    fn perform_measurement<D: DelayUs<u16> + DelayMs<u16>>(
-        &mut self,
-        delay: &mut D,
-    ) -> Result<Measurement>;
-    fn perform_measurement_with_retries<D: DelayUs<u16> + DelayMs<u16>>(
+    fn perform_measurement<D: DelayUs<u16> + DelayMs<u16> + Clock>(
         &mut self,
         delay: &mut D,
-        retries: u16,
     ) -> Result<Measurement>;
+
+    /// How long until the sensor can take another measurement.
+    fn ready_in<C: Clock>(&self, _clock: &C) -> Duration {
+        Duration::ZERO
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_through_json() {
+        let measurement = Measurement::new()
+            .with(Quantity::Temperature, 21.5)
+            .with(Quantity::Humidity, 48.0)
+            .with(Quantity::Co2, 812.0);
+        let json = serde_json::to_string(&measurement).unwrap();
+        assert_eq!(
+            json,
+            r#"[{"quantity":"temperature","value":21.5,"unit":"°C"},{"quantity":"humidity","value":48.0,"unit":"%"},{"quantity":"co2","value":812.0,"unit":"ppm"}]"#
+        );
+        assert_eq!(
+            serde_json::from_str::<Measurement>(&json).unwrap(),
+            measurement
+        );
+        assert_eq!(
+            serde_json::from_str::<Measurement>("[]").unwrap(),
+            Measurement::new()
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_quantities_and_units() {
+        for json in [
+            r#"[{"quantity":"ozone","value":0.1,"unit":"ppm"}]"#,
+            r#"[{"quantity":"pressure","value":760.0,"unit":"mmHg"}]"#,
+            // Values are recorded in the quantity's unit, converted ones can't be read back
+            r#"[{"quantity":"temperature","value":70.7,"unit":"°F"}]"#,
+            r#"[{"quantity":"pressure","value":101.3,"unit":"kPa"}]"#,
+            r#"[{"quantity":"humidity","value":48.0}]"#,
+        ] {
+            assert!(serde_json::from_str::<Measurement>(json).is_err(), "{json}");
+        }
+    }
 }
@@ -0,0 +1,854 @@
+//! Rust driver for the DHT family of single-wire temperature and humidity sensors, generic over `embedded-hal` digital pins.
+//! Any pin implementing [`DataPin`] can be used, e.g. the [rppal](https://docs.golemparts.com/rppal/0.16.1/rppal/gpio/struct.IoPin.html#) GPIO library `IoPin` type.
+//!
+//! All models share the same handshake and 40 bit frame, a [`Model`] describes the parts that differ.
+//!
+#![deny(unsafe_code)]
+#![deny(missing_docs)]
+
+use core::time::Duration;
+
+use embedded_hal::blocking::delay::{DelayMs, DelayUs};
+use serde::{Deserialize, Serialize};
+
+use super::{
+    device::{Device, Measurement},
+    pin::{Clock, DataPin, Edge, EdgeCapture, PinError},
+    quantity::Quantity,
+    Error, Result,
+};
+
+/// How long to wait for a pulse on the data line (in microseconds)
+const TIMEOUT_US: u64 = 1_000;
+
+/// Number of edges in a complete response: the handshake, 40 bits, the closing low pulse and release
+const RESPONSE_EDGES: usize = 2 + 40 * 2 + 2;
+
+/// How long to record edges for when reading through edge capture
+const CAPTURE_TIMEOUT: Duration = Duration::from_millis(10);
+
+/// Longest high pulse decoded as a `0` bit (in microseconds).
+/// The datasheets specify 26-28µs for a `0` and 70µs for a `1`.
+const ZERO_MAX_US: u32 = 48;
+
+/// The measured widths of one low/high pulse pair on the data line (in microseconds)
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct Pulse {
+    /// How long the line was held low.
+    pub low_us: u32,
+    /// How long the line was held high.
+    pub high_us: u32,
+}
+
+impl Pulse {
+    /// The bit encoded by the length of the high pulse.
+    pub fn bit(&self) -> bool {
+        self.high_us > ZERO_MAX_US
+    }
+}
+
+/// A frame as received from the sensor, before the checksum is verified.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Frame {
+    /// The 4 data bytes followed by the checksum.
+    pub bytes: [u8; 5],
+    /// The sensor's response to the start signal, if it was seen.
+    pub handshake: Option<Pulse>,
+    /// The pulses of each of the 40 bits.
+    pub bits: [Pulse; 40],
+    /// How many bits were received before the sensor stopped responding.
+    pub received: usize,
+}
+
+impl Default for Frame {
+    fn default() -> Self {
+        Frame {
+            bytes: [0; 5],
+            handshake: None,
+            bits: [Pulse::default(); 40],
+            received: 0,
+        }
+    }
+}
+
+/// The part of a read that failed.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Phase {
+    /// The sensor did not answer the start signal.
+    Handshake,
+    /// The sensor stopped responding before sending the bit at this index.
+    Bit(usize),
+    /// The whole frame was received but the checksum did not match.
+    Checksum,
+}
+
+/// Everything recorded during a single read, for telling wiring and timing problems apart.
+#[derive(Clone, Debug, Serialize)]
+pub struct Diagnostics {
+    /// The name of the sensor model.
+    pub model: &'static str,
+    /// How the response was sampled.
+    pub read_mode: ReadMode,
+    /// The received data bytes followed by the checksum sent by the sensor.
+    pub bytes: [u8; 5],
+    /// The checksum computed from the received data bytes.
+    pub checksum: u8,
+    /// The sensor's response to the start signal, if it was seen.
+    pub handshake: Option<Pulse>,
+    /// The pulses of each received bit.
+    pub bits: Vec<Pulse>,
+    /// The part of the read that failed, if any.
+    pub failed: Option<Phase>,
+    /// The decoded measurement if the read succeeded.
+    pub measurement: Option<Measurement>,
+}
+
+impl Frame {
+    /// Rebuilds a frame from the timestamped edges of the sensor's response.
+    ///
+    /// A complete frame is decoded from the last 40 high pulses, so edges missed at the start
+    /// of the response (e.g. while the line was switched to input) only affect the handshake.
+    pub fn from_edges(edges: &[Edge]) -> Frame {
+        let mut pulses = Vec::with_capacity(41);
+        let mut fall = None;
+        let mut rise = None;
+        for edge in edges {
+            match (edge.rising, rise) {
+                (true, _) => rise = Some(edge.time_us),
+                (false, Some(rise_us)) => {
+                    let low_us = fall.map_or(0, |fall_us| rise_us - fall_us);
+                    pulses.push(Pulse {
+                        low_us: low_us as u32,
+                        high_us: (edge.time_us - rise_us) as u32,
+                    });
+                    rise = None;
+                    fall = Some(edge.time_us);
+                }
+                (false, None) => fall = Some(edge.time_us),
+            }
+        }
+
+        let mut frame = Frame::default();
+        // A partial response starts with the handshake, a complete one ends with the bits
+        let bits = match pulses.len() {
+            0 => Vec::new(),
+            1..=39 => pulses.split_off(1),
+            _ => pulses.split_off(pulses.len() - 40),
+        };
+        frame.handshake = pulses.last().copied();
+        for pulse in bits {
+            frame.push(pulse);
+        }
+
+        frame
+    }
+
+    /// Appends the next bit.
+    fn push(&mut self, pulse: Pulse) {
+        let i = self.received;
+        self.bits[i] = pulse;
+        self.bytes[i / 8] <<= 1;
+        if pulse.bit() {
+            self.bytes[i / 8] |= 1;
+        }
+        self.received += 1;
+    }
+
+    /// Whether all 40 bits were received.
+    pub fn complete(&self) -> bool {
+        self.received == 40
+    }
+
+    /// The checksum computed from the data bytes.
+    pub fn checksum(&self) -> u8 {
+        self.bytes[..4]
+            .iter()
+            .fold(0u8, |crc, byte| crc.wrapping_add(*byte))
+    }
+
+    /// Whether the checksum matches the data bytes.
+    pub fn checksum_valid(&self) -> bool {
+        self.checksum() == self.bytes[4]
+    }
+
+    /// The part of the read that failed, if any.
+    pub fn failed(&self) -> Option<Phase> {
+        match (self.handshake, self.received) {
+            (None, 0) => Some(Phase::Handshake),
+            (_, received) if received < 40 => Some(Phase::Bit(received)),
+            _ if !self.checksum_valid() => Some(Phase::Checksum),
+            _ => None,
+        }
+    }
+}
+
+/// Model specific parameters of the single-wire protocol.
+#[derive(Copy, Clone, Debug)]
+pub struct Model {
+    /// The name of the model.
+    pub name: &'static str,
+    /// How long the host pulls the line low to request a measurement (in milliseconds)
+    pub start_ms: u16,
+    /// The minimum time between two measurements.
+    pub min_interval: Duration,
+    /// The lowest and highest temperature the sensor can measure, in degrees Celsius.
+    pub temperature_range: (f32, f32),
+    /// The lowest and highest relative humidity the sensor can measure, in percent.
+    pub humidity_range: (f32, f32),
+    /// Converts the 4 data bytes of a frame into a measurement.
+    pub decode: fn([u8; 4]) -> Measurement,
+}
+
+/// The DHT11, with whole number readings.
+pub const DHT11: Model = Model {
+    name: "DHT11",
+    start_ms: 20,
+    min_interval: Duration::from_secs(1),
+    temperature_range: (-20.0, 60.0),
+    humidity_range: (5.0, 95.0),
+    decode: decode_dht11,
+};
+
+/// The DHT22, also sold as the AM2302.
+pub const DHT22: Model = Model {
+    name: "DHT22",
+    start_ms: 2,
+    min_interval: Duration::from_secs(2),
+    temperature_range: (-40.0, 80.0),
+    humidity_range: (0.0, 100.0),
+    decode: decode_dht22,
+};
+
+/// The DHT21, also sold as the AM2301.
+pub const DHT21: Model = Model {
+    name: "DHT21",
+    start_ms: 2,
+    min_interval: Duration::from_secs(2),
+    temperature_range: (-40.0, 80.0),
+    humidity_range: (0.0, 100.0),
+    decode: decode_dht22,
+};
+
+/// The AM2320 with its SDA line used in single-wire mode (SCL tied to ground).
+pub const AM2320: Model = Model {
+    name: "AM2320",
+    start_ms: 2,
+    min_interval: Duration::from_secs(2),
+    temperature_range: (-40.0, 80.0),
+    humidity_range: (0.0, 100.0),
+    decode: decode_dht22,
+};
+
+/// How the response of the sensor is sampled.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum ReadMode {
+    /// Busy-poll the line, timing each pulse against the clock.
+    #[default]
+    Polling,
+    /// Let the GPIO driver timestamp edges and decode them once the response is complete.
+    Edges,
+}
+
+/// A DHT device.
+pub struct Dht<P> {
+    /// The concrete GPIO pin implementation.
+    gpio: P,
+    /// The model of the connected sensor.
+    model: Model,
+    /// How the response is sampled.
+    read_mode: ReadMode,
+    /// When the last start signal was sent, on the delay's clock.
+    last_start_us: Option<u64>,
+}
+
+impl<P> Dht<P> {
+    /// Creates a new device of the given model connected to the specified pin.
+    pub fn new(gpio: P, model: Model) -> Self {
+        Dht {
+            gpio,
+            model,
+            read_mode: ReadMode::default(),
+            last_start_us: None,
+        }
+    }
+
+    /// Sets how the response of the sensor is sampled.
+    pub fn with_read_mode(mut self, read_mode: ReadMode) -> Self {
+        self.read_mode = read_mode;
+        self
+    }
+
+    /// The model of the connected sensor.
+    pub fn model(&self) -> &Model {
+        &self.model
+    }
+
+    /// How long until the sensor may be sampled again.
+    pub fn ready_in<C: Clock>(&self, clock: &C) -> Duration {
+        match self.last_start_us {
+            Some(start_us) => self
+                .model
+                .min_interval
+                .saturating_sub(Duration::from_micros(clock.now_us() - start_us)),
+            None => Duration::ZERO,
+        }
+    }
+}
+
+impl<P> Dht<P>
+where
+    P: DataPin,
+    Error: From<PinError<P>>,
+{
+    /// Performs a reading of the sensor.
+    pub fn perform_measurement<D>(&mut self, delay: &mut D) -> Result<Measurement>
+    where
+        D: DelayUs<u16> + DelayMs<u16> + Clock,
+    {
+        let frame = self.read_frame(delay)?;
+        self.decode(frame)
+    }
+
+    fn decode(&self, frame: Frame) -> Result<Measurement> {
+        if !frame.checksum_valid() {
+            return Err(Error::ChecksumMismatch {
+                expected: frame.checksum(),
+                actual: frame.bytes[4],
+            });
+        }
+
+        let [b0, b1, b2, b3, _] = frame.bytes;
+        Ok((self.model.decode)([b0, b1, b2, b3]))
+    }
+
+    /// Requests a measurement and records the frame sent by the sensor.
+    pub fn read_frame<D>(&mut self, delay: &mut D) -> Result<Frame>
+    where
+        D: DelayUs<u16> + DelayMs<u16> + Clock,
+    {
+        let mut frame = Frame::default();
+        self.read_frame_into(delay, &mut frame)?;
+        Ok(frame)
+    }
+
+    /// Reads into `frame`, which keeps whatever was received if the sensor stops responding.
+    fn read_frame_into<D>(&mut self, delay: &mut D, frame: &mut Frame) -> Result<()>
+    where
+        D: DelayUs<u16> + DelayMs<u16> + Clock,
+    {
+        // Perform initial handshake
+        let mut edge = self.perform_handshake(delay)?;
+        let handshake = self.read_pulse(&mut edge, delay).map_err(|e| match e {
+            // Nothing pulled the released line low
+            Error::LineStuck { high: true } => Error::NoResponse,
+            e => e,
+        })?;
+        frame.handshake = Some(handshake);
+
+        // Read bits
+        while !frame.complete() {
+            let bit = frame.received;
+            let pulse = self.read_pulse(&mut edge, delay).map_err(|e| match e {
+                Error::LineStuck { .. } => Error::BitTimeout { bit },
+                e => e,
+            })?;
+            frame.push(pulse);
+        }
+
+        Ok(())
+    }
+
+    /// Sends the start signal and returns the time the line was released to the sensor.
+    fn perform_handshake<D>(&mut self, delay: &mut D) -> Result<u64>
+    where
+        D: DelayUs<u16> + DelayMs<u16> + Clock,
+    {
+        self.send_start(delay)?;
+        delay.delay_us(40);
+
+        self.gpio.set_input()?;
+
+        // As a response, the device pulls the line low for 80us and then high for 80us.
+        Ok(delay.now_us())
+    }
+
+    /// Pulls the line low long enough for the sensor to start a measurement, then releases it.
+    fn send_start<D>(&mut self, delay: &mut D) -> Result<()>
+    where
+        D: DelayUs<u16> + DelayMs<u16> + Clock,
+    {
+        let ready_in = self.ready_in(delay);
+        if !ready_in.is_zero() {
+            return Err(Error::TooFrequent {
+                elapsed: self.model.min_interval - ready_in,
+                min_interval: self.model.min_interval,
+            });
+        }
+        self.last_start_us = Some(delay.now_us());
+
+        self.gpio.set_output()?;
+        // Set pin as floating to let pull-up raise the line and start the reading process.
+        self.gpio.set_high()?;
+        delay.delay_ms(1);
+
+        // Pull line low to send a start command.
+        self.gpio.set_low()?;
+        delay.delay_ms(self.model.start_ms);
+        if self.gpio.is_high()? {
+            // Something else is driving the line
+            return Err(Error::LineStuck { high: true });
+        }
+
+        // Restore floating
+        self.gpio.set_high()?;
+
+        Ok(())
+    }
+
+    /// Measures a low pulse followed by a high pulse, starting from the falling `edge`
+    /// and advancing it to the falling edge that ends the high pulse.
+    fn read_pulse<C: Clock>(&mut self, edge: &mut u64, clock: &C) -> Result<Pulse> {
+        let rise = self.wait_for_level(true, clock)?;
+        let fall = self.wait_for_level(false, clock)?;
+        let pulse = Pulse {
+            low_us: (rise - *edge) as u32,
+            high_us: (fall - rise) as u32,
+        };
+        *edge = fall;
+        Ok(pulse)
+    }
+
+    /// Polls the line until it reaches `level` and returns the time it did.
+    fn wait_for_level<C: Clock>(&mut self, level: bool, clock: &C) -> Result<u64> {
+        let start = clock.now_us();
+
+        while self.gpio.is_high()? != level {
+            if clock.now_us() - start > TIMEOUT_US {
+                return Err(Error::LineStuck { high: !level });
+            }
+        }
+
+        Ok(clock.now_us())
+    }
+}
+
+impl<P> Dht<P>
+where
+    P: DataPin + EdgeCapture<Error = PinError<P>>,
+    Error: From<PinError<P>>,
+{
+    /// Performs a reading of the sensor, decoding the response from captured edges.
+    pub fn perform_measurement_from_edges<D>(&mut self, delay: &mut D) -> Result<Measurement>
+    where
+        D: DelayUs<u16> + DelayMs<u16> + Clock,
+    {
+        let frame = self.read_frame_from_edges(delay)?;
+        self.decode(frame)
+    }
+
+    /// Requests a measurement and rebuilds the frame from the edges recorded by the pin.
+    pub fn read_frame_from_edges<D>(&mut self, delay: &mut D) -> Result<Frame>
+    where
+        D: DelayUs<u16> + DelayMs<u16> + Clock,
+    {
+        let (frame, stuck_low) = self.capture_frame(delay)?;
+        match frame.failed() {
+            Some(Phase::Handshake) if stuck_low => Err(Error::LineStuck { high: false }),
+            Some(Phase::Handshake) => Err(Error::NoResponse),
+            Some(Phase::Bit(bit)) => Err(Error::BitTimeout { bit }),
+            Some(Phase::Checksum) | None => Ok(frame),
+        }
+    }
+
+    /// Captures the response, also returning whether the line was left low.
+    fn capture_frame<D>(&mut self, delay: &mut D) -> Result<(Frame, bool)>
+    where
+        D: DelayUs<u16> + DelayMs<u16> + Clock,
+    {
+        self.send_start(delay)?;
+        self.gpio.start_capture()?;
+        let edges = self.gpio.finish_capture(RESPONSE_EDGES, CAPTURE_TIMEOUT)?;
+        let stuck_low = edges.last().is_some_and(|edge| !edge.rising);
+        Ok((Frame::from_edges(&edges), stuck_low))
+    }
+
+    /// Performs a single read without retries, recording how far it got.
+    ///
+    /// Only GPIO errors are returned, protocol failures are reported in the diagnostics.
+    pub fn diagnose<D>(&mut self, delay: &mut D) -> Result<Diagnostics>
+    where
+        D: DelayUs<u16> + DelayMs<u16> + Clock,
+    {
+        let frame = match self.read_mode {
+            ReadMode::Polling => {
+                let mut frame = Frame::default();
+                match self.read_frame_into(delay, &mut frame) {
+                    Ok(())
+                    | Err(Error::NoResponse | Error::LineStuck { .. } | Error::BitTimeout { .. }) => {
+                        frame
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+            ReadMode::Edges => self.capture_frame(delay)?.0,
+        };
+
+        let failed = frame.failed();
+        let [b0, b1, b2, b3, _] = frame.bytes;
+        Ok(Diagnostics {
+            model: self.model.name,
+            read_mode: self.read_mode,
+            bytes: frame.bytes,
+            checksum: frame.checksum(),
+            handshake: frame.handshake,
+            bits: frame.bits[..frame.received].to_vec(),
+            failed,
+            measurement: failed
+                .is_none()
+                .then(|| (self.model.decode)([b0, b1, b2, b3])),
+        })
+    }
+}
+
+/// Decodes whole number humidity and temperature bytes, each followed by a tenths byte.
+fn decode_dht11(bytes: [u8; 4]) -> Measurement {
+    let [rh_h, rh_l, temp_h_signed, temp_l] = bytes;
+    let mut temp = i16::from(temp_h_signed & 0x7f) * 10 + i16::from(temp_l);
+    if temp_h_signed & 0x80 != 0 {
+        temp = -temp;
+    }
+
+    Measurement::new()
+        .with(Quantity::Temperature, temp as f32 / 10f32)
+        .with(Quantity::Humidity, f32::from(rh_h) + f32::from(rh_l) * 0.1)
+}
+
+/// Decodes 16 bit humidity and sign-magnitude temperature values, both in tenths.
+fn decode_dht22(bytes: [u8; 4]) -> Measurement {
+    let [rh_h, rh_l, temp_h_signed, temp_l] = bytes;
+    let humidity = ((rh_h as u16) << 8 | (rh_l as u16)) as f32 / 10.0;
+    let temperature = {
+        let (signed, magnitude) = convert_signed(temp_h_signed);
+        let temp_sign = if signed { -1.0 } else { 1.0 };
+        let temp_magnitude = ((magnitude as u16) << 8) | temp_l as u16;
+        temp_sign * temp_magnitude as f32 / 10.0
+    };
+    Measurement::new()
+        .with(Quantity::Temperature, temperature)
+        .with(Quantity::Humidity, humidity)
+}
+
+fn convert_signed(signed: u8) -> (bool, u8) {
+    let sign = signed & 0x80 != 0;
+    let magnitude = signed & 0x7F;
+    (sign, magnitude)
+}
+
+impl<P> Device for Dht<P>
+where
+    P: DataPin + EdgeCapture<Error = PinError<P>>,
+    Error: From<PinError<P>>,
+{
+    fn perform_measurement<D: DelayUs<u16> + DelayMs<u16> + Clock>(
+        &mut self,
+        delay: &mut D,
+    ) -> Result<Measurement> {
+        match self.read_mode {
+            ReadMode::Polling => self.perform_measurement(delay),
+            ReadMode::Edges => self.perform_measurement_from_edges(delay),
+        }
+    }
+
+    fn ready_in<C: Clock>(&self, clock: &C) -> Duration {
+        self.ready_in(clock)
+    }
+}
+
+#[cfg(all(test, feature = "sim"))]
+mod tests {
+    use super::*;
+    use crate::humidity::sim::{Fault, Response, SimClock, SimLine};
+
+    /// Reads a DHT22 whose line plays back `response`.
+    fn read(read_mode: ReadMode, response: Response) -> Result<Measurement> {
+        read_model(DHT22, read_mode, response)
+    }
+
+    /// Reads a sensor of the given model whose line plays back `response`.
+    fn read_model(model: Model, read_mode: ReadMode, response: Response) -> Result<Measurement> {
+        let clock = SimClock::new();
+        let mut dht =
+            Dht::new(SimLine::new(clock.clone(), vec![response]), model).with_read_mode(read_mode);
+        let mut delay = clock.delay();
+        match read_mode {
+            ReadMode::Polling => dht.perform_measurement(&mut delay),
+            ReadMode::Edges => dht.perform_measurement_from_edges(&mut delay),
+        }
+    }
+
+    fn reading(temperature: f32, humidity: f32) -> Measurement {
+        Measurement::new()
+            .with(Quantity::Temperature, temperature)
+            .with(Quantity::Humidity, humidity)
+    }
+
+    fn faulty(fault: Fault) -> Response {
+        Response::dht22(reading(21.5, 48.2)).with_fault(fault)
+    }
+
+    const READ_MODES: [ReadMode; 2] = [ReadMode::Polling, ReadMode::Edges];
+
+    #[test]
+    fn decodes_a_clean_frame() {
+        for read_mode in READ_MODES {
+            let measurement = read(read_mode, Response::dht22(reading(21.5, 48.2))).unwrap();
+            assert_eq!(measurement, reading(21.5, 48.2), "{read_mode:?}");
+        }
+    }
+
+    #[test]
+    fn decodes_negative_temperatures() {
+        for read_mode in READ_MODES {
+            let measurement = read(read_mode, Response::dht22(reading(-12.3, 81.0))).unwrap();
+            assert_eq!(
+                measurement.get(Quantity::Temperature),
+                Some(-12.3),
+                "{read_mode:?}"
+            );
+            assert_eq!(
+                measurement.get(Quantity::Humidity),
+                Some(81.0),
+                "{read_mode:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn reports_a_missing_sensor() {
+        for read_mode in READ_MODES {
+            let result = read(read_mode, faulty(Fault::NoResponse));
+            assert!(
+                matches!(result, Err(Error::NoResponse)),
+                "{read_mode:?}: {result:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn reports_a_line_held_low() {
+        for read_mode in READ_MODES {
+            let result = read(read_mode, faulty(Fault::StuckLow));
+            assert!(
+                matches!(result, Err(Error::LineStuck { high: false })),
+                "{read_mode:?}: {result:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_a_corrupted_bit() {
+        for read_mode in READ_MODES {
+            let result = read(read_mode, faulty(Fault::CorruptBit(5)));
+            assert!(
+                matches!(result, Err(Error::ChecksumMismatch { .. })),
+                "{read_mode:?}: {result:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn times_out_on_a_truncated_frame() {
+        for read_mode in READ_MODES {
+            // The last bit received has no falling edge closing its high pulse
+            let result = read(read_mode, faulty(Fault::Truncated(10)));
+            assert!(
+                matches!(result, Err(Error::BitTimeout { bit: 9 })),
+                "{read_mode:?}: {result:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn decodes_dht11_frames() {
+        for read_mode in READ_MODES {
+            for expected in [reading(21.5, 48.0), reading(-3.4, 61.0)] {
+                let measurement = read_model(DHT11, read_mode, Response::dht11(expected)).unwrap();
+                assert_eq!(measurement, expected, "{read_mode:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn recovers_from_a_missed_handshake_edge_only_when_capturing_edges() {
+        // The rise ending the handshake's low half is missed, merging it into one long low
+        let response = faulty(Fault::DroppedEdge(0));
+
+        let result = read(ReadMode::Polling, response.clone());
+        assert!(
+            matches!(result, Err(Error::BitTimeout { bit: 39 })),
+            "{result:?}"
+        );
+        // The bits are decoded from the end of the response, the handshake is not needed
+        let measurement = read(ReadMode::Edges, response).unwrap();
+        assert_eq!(measurement, reading(21.5, 48.2));
+    }
+
+    #[test]
+    fn rejects_a_frame_with_a_missed_bit_edge() {
+        // Merges the high pulse of bit 5 with the low pulse starting bit 6, losing a bit
+        let response = faulty(Fault::DroppedEdge(2 + 5 * 2 + 1));
+
+        let result = read(ReadMode::Polling, response.clone());
+        assert!(
+            matches!(result, Err(Error::BitTimeout { bit: 39 })),
+            "{result:?}"
+        );
+        // The handshake is taken for the first bit, shifting the others
+        let result = read(ReadMode::Edges, response);
+        assert!(
+            matches!(result, Err(Error::ChecksumMismatch { .. })),
+            "{result:?}"
+        );
+    }
+}
+
+#[cfg(test)]
+mod frame_tests {
+    use super::*;
+
+    const HANDSHAKE: Pulse = Pulse {
+        low_us: 80,
+        high_us: 80,
+    };
+
+    /// The pulse of a single bit as the sensor sends it.
+    fn bit(one: bool) -> Pulse {
+        Pulse {
+            low_us: 50,
+            high_us: if one { 70 } else { 27 },
+        }
+    }
+
+    /// The bits of a DHT22 frame for 48.2% and 21.5°C.
+    fn bits() -> Vec<Pulse> {
+        let bytes = [0x01, 0xe2, 0x00, 0xd7, 0xba];
+        (0..40)
+            .map(|i| bit(bytes[i / 8] & (0x80 >> (i % 8)) != 0))
+            .collect()
+    }
+
+    /// The edges of a response made of `pulses`, starting with the sensor pulling the line low
+    /// and ending with it releasing the line after the closing low pulse.
+    fn edges(pulses: &[Pulse]) -> Vec<Edge> {
+        let mut edges = Vec::new();
+        let mut time_us = 1_000;
+        for pulse in pulses {
+            edges.push(Edge {
+                time_us,
+                rising: false,
+            });
+            time_us += u64::from(pulse.low_us);
+            edges.push(Edge {
+                time_us,
+                rising: true,
+            });
+            time_us += u64::from(pulse.high_us);
+        }
+        edges.push(Edge {
+            time_us,
+            rising: false,
+        });
+        edges.push(Edge {
+            time_us: time_us + 50,
+            rising: true,
+        });
+        edges
+    }
+
+    fn response() -> Vec<Pulse> {
+        let mut pulses = vec![HANDSHAKE];
+        pulses.extend(bits());
+        pulses
+    }
+
+    #[test]
+    fn rebuilds_a_complete_response() {
+        let frame = Frame::from_edges(&edges(&response()));
+        assert_eq!(frame.handshake, Some(HANDSHAKE));
+        assert_eq!(frame.bits.to_vec(), bits());
+        assert_eq!(frame.bytes, [0x01, 0xe2, 0x00, 0xd7, 0xba]);
+        assert_eq!(frame.failed(), None);
+    }
+
+    #[test]
+    fn decodes_the_bits_without_the_leading_response_edge() {
+        // Switching the line to input took long enough to miss the sensor pulling it low
+        let mut edges = edges(&response());
+        edges.remove(0);
+
+        let frame = Frame::from_edges(&edges);
+        assert_eq!(
+            frame.handshake,
+            Some(Pulse {
+                low_us: 0,
+                high_us: 80
+            })
+        );
+        assert_eq!(frame.bytes, [0x01, 0xe2, 0x00, 0xd7, 0xba]);
+        assert_eq!(frame.failed(), None);
+    }
+
+    #[test]
+    fn decodes_the_bits_without_the_handshake() {
+        let mut edges = edges(&response());
+        edges.drain(..2);
+
+        let frame = Frame::from_edges(&edges);
+        assert_eq!(frame.handshake, None);
+        assert_eq!(frame.bytes, [0x01, 0xe2, 0x00, 0xd7, 0xba]);
+        assert_eq!(frame.failed(), None);
+    }
+
+    #[test]
+    fn rejects_a_frame_with_a_glitch() {
+        // A spike splits the high pulse of bit 20 into two short ones
+        let mut edges = edges(&response());
+        let rise = edges[2 + 20 * 2 + 1].time_us;
+        let glitch = [
+            Edge {
+                time_us: rise + 10,
+                rising: false,
+            },
+            Edge {
+                time_us: rise + 11,
+                rising: true,
+            },
+        ];
+        edges.splice(2 + 20 * 2 + 2..2 + 20 * 2 + 2, glitch);
+
+        let frame = Frame::from_edges(&edges);
+        assert!(frame.complete());
+        assert_eq!(frame.failed(), Some(Phase::Checksum));
+    }
+
+    #[test]
+    fn reports_the_bit_a_short_response_stopped_at() {
+        let mut pulses = response();
+        pulses.truncate(1 + 25);
+
+        let frame = Frame::from_edges(&edges(&pulses));
+        assert_eq!(frame.handshake, Some(HANDSHAKE));
+        assert_eq!(frame.received, 25);
+        assert_eq!(frame.bits[..25], bits()[..25]);
+        assert_eq!(frame.failed(), Some(Phase::Bit(25)));
+    }
+
+    #[test]
+    fn reports_a_missing_handshake_without_edges() {
+        let frame = Frame::from_edges(&[]);
+        assert_eq!(frame.received, 0);
+        assert_eq!(frame.failed(), Some(Phase::Handshake));
+    }
+}
@@ -0,0 +1,240 @@
+//! Rust driver for the Maxim DS18B20 temperature probe, read through the kernel's 1-Wire bus master.
+//!
+//! The `w1_therm` driver converts and reads the scratchpad whenever `w1_slave` is read, which
+//! takes up to 750ms at 12 bit resolution. Its first line holds the scratchpad and whether the
+//! CRC matched, the second the scratchpad again and the temperature in millidegrees:
+//!
+//! ```text
+//! 72 01 4b 46 7f ff 0e 10 57 : crc=57 YES
+//! 72 01 4b 46 7f ff 0e 10 57 t=23125
+//! ```
+//!
+#![deny(unsafe_code)]
+
+use embedded_hal::blocking::delay::{DelayMs, DelayUs};
+
+use super::{
+    bus::W1Bus,
+    device::{Device, Measurement},
+    pin::Clock,
+    quantity::Quantity,
+    Error, Result,
+};
+
+/// Operating range of the temperature measurement in degrees Celsius
+pub const TEMPERATURE_RANGE: (f32, f32) = (-55.0, 125.0);
+
+/// Prefix of the ids of DS18B20 devices, their 1-Wire family code
+const FAMILY_PREFIX: &str = "28-";
+/// Temperature register contents before the first conversion, 85°C
+const POWER_ON_RESET: [u8; 2] = [0x50, 0x05];
+/// Bits of the configuration register that always read as ones
+const CONFIG_RESERVED: u8 = 0x1f;
+
+/// The ids of the DS18B20 probes among the devices on a bus.
+pub fn probes(devices: Vec<String>) -> Vec<String> {
+    let mut probes: Vec<_> = devices
+        .into_iter()
+        .filter(|device| device.starts_with(FAMILY_PREFIX))
+        .collect();
+    probes.sort();
+    probes
+}
+
+/// The CRC-8 of the 1-Wire bus: polynomial 0x31, reflected, initialised to 0.
+pub fn crc8(bytes: &[u8]) -> u8 {
+    let mut crc = 0;
+    for &byte in bytes {
+        let mut byte = byte;
+        for _ in 0..8 {
+            let mix = (crc ^ byte) & 1;
+            crc >>= 1;
+            if mix != 0 {
+                crc ^= 0x8c;
+            }
+            byte >>= 1;
+        }
+    }
+    crc
+}
+
+/// Extracts the temperature in degrees Celsius from the contents of `w1_slave`.
+pub fn parse(w1_slave: &str) -> Result<f32> {
+    let unexpected = || Error::UnexpectedResponse(w1_slave.trim().to_owned());
+    let mut lines = w1_slave.lines();
+    let (Some(crc_line), Some(data_line)) = (lines.next(), lines.next()) else {
+        return Err(unexpected());
+    };
+
+    let (bytes, verdict) = crc_line.split_once(" : crc=").ok_or_else(unexpected)?;
+    let scratchpad: [u8; 9] = bytes
+        .split_whitespace()
+        .map(|byte| u8::from_str_radix(byte, 16))
+        .collect::<core::result::Result<Vec<_>, _>>()
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(unexpected)?;
+    if !verdict.trim_end().ends_with("YES") {
+        return Err(Error::ChecksumMismatch {
+            expected: crc8(&scratchpad[..8]),
+            actual: scratchpad[8],
+        });
+    }
+    // Without a probe answering, the bus reads as zeros and the CRC of zeros matches
+    if scratchpad[4] & CONFIG_RESERVED != CONFIG_RESERVED {
+        return Err(Error::NoResponse);
+    }
+    if scratchpad[..2] == POWER_ON_RESET {
+        return Err(Error::NotReady);
+    }
+
+    let (_, millidegrees) = data_line.rsplit_once("t=").ok_or_else(unexpected)?;
+    let millidegrees: i32 = millidegrees.trim().parse().map_err(|_| unexpected())?;
+    Ok(millidegrees as f32 / 1000.0)
+}
+
+/// A DS18B20 probe on a 1-Wire bus.
+pub struct Ds18b20<B> {
+    /// The bus the probe is connected to.
+    bus: B,
+    /// The id of the probe on the bus.
+    device: String,
+}
+
+impl<B> Ds18b20<B> {
+    /// Creates a probe with the given id, e.g. `28-0316a2799fff`.
+    pub fn new(bus: B, device: impl Into<String>) -> Self {
+        Ds18b20 {
+            bus,
+            device: device.into(),
+        }
+    }
+}
+
+impl<B> Ds18b20<B>
+where
+    B: W1Bus,
+    Error: From<B::Error>,
+{
+    /// Reads the temperature, the probe measures nothing else.
+    pub fn perform_measurement(&mut self) -> Result<Measurement> {
+        let devices = self.bus.devices()?;
+        if !devices.contains(&self.device) {
+            return Err(Error::NotFound {
+                device: self.device.clone(),
+                found: probes(devices),
+            });
+        }
+
+        Ok(Measurement::new().with(
+            Quantity::Temperature,
+            parse(&self.bus.read_slave(&self.device)?)?,
+        ))
+    }
+}
+
+impl<B> Device for Ds18b20<B>
+where
+    B: W1Bus,
+    Error: From<B::Error>,
+{
+    fn perform_measurement<D: DelayUs<u16> + DelayMs<u16> + Clock>(
+        &mut self,
+        _delay: &mut D,
+    ) -> Result<Measurement> {
+        self.perform_measurement()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Whether a parse failed the expected way.
+    type Expected = fn(&Error) -> bool;
+
+    #[test]
+    fn checks_the_scratchpad_crc() {
+        assert_eq!(
+            crc8(&[0x72, 0x01, 0x4b, 0x46, 0x7f, 0xff, 0x0e, 0x10]),
+            0x57
+        );
+        assert_eq!(
+            crc8(&[0x5e, 0xff, 0x4b, 0x46, 0x7f, 0xff, 0x02, 0x10]),
+            0xb6
+        );
+    }
+
+    #[test]
+    fn parses_temperatures() {
+        let captures = [
+            (
+                "72 01 4b 46 7f ff 0e 10 57 : crc=57 YES\n72 01 4b 46 7f ff 0e 10 57 t=23125\n",
+                23.125,
+            ),
+            (
+                "91 01 4b 46 7f ff 0f 10 25 : crc=25 YES\n91 01 4b 46 7f ff 0f 10 25 t=25062\n",
+                25.062,
+            ),
+            (
+                "5e ff 4b 46 7f ff 02 10 b6 : crc=b6 YES\n5e ff 4b 46 7f ff 02 10 b6 t=-10125\n",
+                -10.125,
+            ),
+            (
+                "6f fe 4b 46 7f ff 01 10 61 : crc=61 YES\n6f fe 4b 46 7f ff 01 10 61 t=-25062\n",
+                -25.062,
+            ),
+        ];
+        for (w1_slave, expected) in captures {
+            assert_eq!(parse(w1_slave).unwrap(), expected, "{w1_slave}");
+        }
+    }
+
+    #[test]
+    fn rejects_failed_reads() {
+        let captures: [(&str, Expected); 7] = [
+            // A bit flipped on the bus
+            (
+                "91 01 4b 46 7f ff 0f 10 20 : crc=25 NO\n91 01 4b 46 7f ff 0f 10 20 t=25062\n",
+                |e| {
+                    matches!(
+                        e,
+                        Error::ChecksumMismatch {
+                            expected: 0x25,
+                            actual: 0x20
+                        }
+                    )
+                },
+            ),
+            // Read before the first conversion finished
+            (
+                "50 05 4b 46 7f ff 0c 10 1c : crc=1c YES\n50 05 4b 46 7f ff 0c 10 1c t=85000\n",
+                |e| matches!(e, Error::NotReady),
+            ),
+            // The probe was unplugged while the kernel still listed it
+            (
+                "00 00 00 00 00 00 00 00 00 : crc=00 YES\n00 00 00 00 00 00 00 00 00 t=0\n",
+                |e| matches!(e, Error::NoResponse),
+            ),
+            ("", |e| matches!(e, Error::UnexpectedResponse(_))),
+            ("72 01 4b 46 7f ff 0e 10 57 : crc=57 YES\n", |e| {
+                matches!(e, Error::UnexpectedResponse(_))
+            }),
+            (
+                "72 01 4b 46 7f ff 0e 10 57 : crc=57 YES\n72 01 4b 46 7f ff 0e",
+                |e| matches!(e, Error::UnexpectedResponse(_)),
+            ),
+            (
+                "72 01 4b 46 7f ff : crc=57 YES\n72 01 4b 46 7f ff t=23125\n",
+                |e| matches!(e, Error::UnexpectedResponse(_)),
+            ),
+        ];
+        for (w1_slave, expected) in captures {
+            let result = parse(w1_slave);
+            assert!(
+                result.as_ref().is_err_and(expected),
+                "{w1_slave:?}: {result:?}"
+            );
+        }
+    }
+}
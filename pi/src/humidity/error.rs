@@ -1,11 +1,95 @@
+use core::{convert::Infallible, time::Duration};
+
+use super::Quantity;
+
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
-    #[error("Timeout during communication")]
-    Timeout,
-    #[error("CRC mismatch")]
-    CrcMismatch,
+    #[error("Sensor did not respond to the start signal")]
+    NoResponse,
+    #[error("Data line stuck {}", if *high { "high" } else { "low" })]
+    LineStuck { high: bool },
+    #[error("Timeout waiting for bit {bit}")]
+    BitTimeout { bit: usize },
+    #[error("Checksum mismatch, computed {expected:#04x} but received {actual:#04x}")]
+    ChecksumMismatch { expected: u8, actual: u8 },
+    #[error("{quantity} of {value} is outside the sensor's range of {min} to {max}")]
+    OutOfRange {
+        quantity: Quantity,
+        value: f32,
+        min: f32,
+        max: f32,
+    },
+    #[error("{quantity} of exactly 0, the sensor most likely dropped out")]
+    Dropout { quantity: Quantity },
+    #[error("{quantity} changed by {change:.1} since the last reading, more than the plausible {max_change:.1}")]
+    Spike {
+        quantity: Quantity,
+        change: f32,
+        max_change: f32,
+    },
+    #[error("Sampled {elapsed:?} after the previous read, the sensor needs {min_interval:?}")]
+    TooFrequent {
+        elapsed: Duration,
+        min_interval: Duration,
+    },
+    #[error("Unexpected chip id {id:#04x}")]
+    UnknownChip { id: u8 },
+    #[error("Sensor did not finish measuring")]
+    NotReady,
+    #[error("Sensor is saturated, the light is brighter than it can measure")]
+    Saturated,
+    #[error("1-Wire device {device} is not on the bus, found probes {found:?}")]
+    NotFound { device: String, found: Vec<String> },
+    #[error("Unexpected sensor response {0:?}")]
+    UnexpectedResponse(String),
+    #[error("Sensor response was corrupted")]
+    Corrupted,
+    #[error("This sensor does not support {0}")]
+    Unsupported(&'static str),
+    #[error("Sensor read did not finish within {0:?}")]
+    Deadline(Duration),
+    #[error("Sensor reader has stopped")]
+    Stopped,
+    #[error(transparent)]
+    Gpio(#[from] crate::gpio::Error),
     #[error(transparent)]
-    GpioError(#[from] rppal::gpio::Error),
+    Io(#[from] std::io::Error),
+}
+
+impl Error {
+    /// Whether the sensor looks disconnected rather than noisy.
+    pub fn is_disconnected(&self) -> bool {
+        matches!(
+            self,
+            Error::NoResponse | Error::LineStuck { .. } | Error::NotFound { .. }
+        )
+    }
+}
+
+#[cfg(feature = "sim")]
+impl From<super::sim_i2c::Nack> for Error {
+    fn from(e: super::sim_i2c::Nack) -> Self {
+        Error::Gpio(e.into())
+    }
+}
+
+impl From<Infallible> for Error {
+    fn from(e: Infallible) -> Self {
+        match e {}
+    }
 }
 
 pub type Result<T> = core::result::Result<T, Error>;
+
+/// An [`Error`] together with the sensor it happened on.
+#[derive(thiserror::Error, Debug)]
+#[error("Sensor {sensor} failed after {attempts} attempt(s): {error}")]
+pub struct SensorError {
+    /// The id of the sensor.
+    pub sensor: String,
+    /// How many reads were attempted before giving up.
+    pub attempts: u16,
+    /// The error of the last attempt.
+    #[source]
+    pub error: Error,
+}
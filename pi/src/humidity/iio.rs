@@ -0,0 +1,196 @@
+//! DHT sensors read through the kernel's `dht11` IIO driver (`dtoverlay=dht11,gpiopin=N`).
+//!
+//! The driver times the single-wire protocol in interrupt context, which copes with scheduling
+//! jitter far better than sampling the line from userspace. Reading `in_temp_input` triggers a
+//! measurement, values are in thousandths and stay cached for two seconds, so the humidity read
+//! right after belongs to the same measurement. Failed reads surface as errno values: `EIO` when
+//! the response could not be decoded and `ETIMEDOUT` when the sensor did not answer.
+//!
+#![deny(unsafe_code)]
+
+use std::io;
+
+use embedded_hal::blocking::delay::{DelayMs, DelayUs};
+
+use super::{
+    bus::IioDevice,
+    device::{Device, Measurement},
+    dht::Model,
+    pin::Clock,
+    quantity::Quantity,
+    Error, Result,
+};
+
+/// Linux errno of reads the driver could not decode, e.g. on a checksum mismatch
+pub const EIO: i32 = 5;
+/// Linux errno of reads the sensor did not answer
+pub const ETIMEDOUT: i32 = 110;
+
+const TEMPERATURE_CHANNEL: &str = "in_temp_input";
+const HUMIDITY_CHANNEL: &str = "in_humidityrelative_input";
+
+/// A DHT sensor bound to the kernel's `dht11` driver.
+pub struct IioDht<D> {
+    /// The IIO device the driver registered for the sensor.
+    device: D,
+    /// The model of the connected sensor, the driver tells them apart by itself.
+    model: Model,
+}
+
+impl<D> IioDht<D> {
+    pub fn new(device: D, model: Model) -> Self {
+        IioDht { device, model }
+    }
+
+    pub fn model(&self) -> &Model {
+        &self.model
+    }
+}
+
+impl<D: IioDevice> IioDht<D> {
+    /// Triggers a measurement and reads both of its channels.
+    pub fn perform_measurement(&mut self) -> Result<Measurement> {
+        let temperature = self.read(TEMPERATURE_CHANNEL)?;
+        let humidity = self.read(HUMIDITY_CHANNEL)?;
+        Ok(Measurement::new()
+            .with(Quantity::Temperature, temperature as f32 / 1000.0)
+            .with(Quantity::Humidity, humidity as f32 / 1000.0))
+    }
+
+    fn read(&mut self, channel: &str) -> Result<i32> {
+        let value = self.device.read_channel(channel).map_err(driver_error)?;
+        value
+            .trim()
+            .parse()
+            .map_err(|_| Error::UnexpectedResponse(value))
+    }
+}
+
+/// Maps the errno values the driver fails reads with to the errors of userspace decoding.
+fn driver_error(error: io::Error) -> Error {
+    match error.raw_os_error() {
+        Some(EIO) => Error::Corrupted,
+        Some(ETIMEDOUT) => Error::NoResponse,
+        _ => Error::Io(error),
+    }
+}
+
+impl<D: IioDevice> Device for IioDht<D> {
+    fn perform_measurement<T: DelayUs<u16> + DelayMs<u16> + Clock>(
+        &mut self,
+        _delay: &mut T,
+    ) -> Result<Measurement> {
+        self.perform_measurement()
+    }
+}
+
+#[cfg(all(test, feature = "sim"))]
+mod sim_tests {
+    use super::*;
+    use crate::humidity::{
+        dht::DHT22,
+        sim::Fault,
+        sim_iio::{self, SimIio},
+    };
+
+    fn read(
+        sample: impl FnMut() -> io::Result<Measurement> + Send + 'static,
+    ) -> Result<Measurement> {
+        IioDht::new(SimIio::new(sample), DHT22).perform_measurement()
+    }
+
+    #[test]
+    fn scales_thousandths() {
+        let measurement = read(|| {
+            Ok(Measurement::new()
+                .with(Quantity::Temperature, -3.2)
+                .with(Quantity::Humidity, 48.2))
+        })
+        .unwrap();
+        assert_eq!(measurement.get(Quantity::Temperature), Some(-3.2));
+        assert_eq!(measurement.get(Quantity::Humidity), Some(48.2));
+    }
+
+    #[test]
+    fn maps_driver_errors() {
+        let result = read(|| Err(sim_iio::driver_error(Fault::CorruptBit(7))));
+        assert!(matches!(result, Err(Error::Corrupted)), "{result:?}");
+
+        for fault in [Fault::NoResponse, Fault::StuckLow] {
+            let result = read(move || Err(sim_iio::driver_error(fault)));
+            assert!(matches!(result, Err(Error::NoResponse)), "{result:?}");
+        }
+
+        let result = read(|| Err(io::ErrorKind::PermissionDenied.into()));
+        assert!(
+            matches!(&result, Err(Error::Io(e)) if e.kind() == io::ErrorKind::PermissionDenied),
+            "{result:?}"
+        );
+    }
+}
+
+#[cfg(all(test, any(feature = "rppal", feature = "gpio-cdev")))]
+mod sysfs_tests {
+    use std::{fs, path::PathBuf};
+
+    use super::*;
+    use crate::{gpio::iio::Sysfs, humidity::dht::DHT22};
+
+    /// Creates a device directory holding the given channel files.
+    fn device(name: &str, channels: &[(&str, &str)]) -> PathBuf {
+        let device = std::env::temp_dir().join(format!("pi-iio-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&device);
+        fs::create_dir_all(&device).unwrap();
+        for (channel, contents) in channels {
+            fs::write(device.join(channel), contents).unwrap();
+        }
+        device
+    }
+
+    fn read(device: &PathBuf) -> Result<Measurement> {
+        IioDht::new(Sysfs::new(device), DHT22).perform_measurement()
+    }
+
+    #[test]
+    fn reads_both_channels() {
+        let device = device(
+            "reads",
+            &[
+                (TEMPERATURE_CHANNEL, "-3200\n"),
+                (HUMIDITY_CHANNEL, "48200\n"),
+            ],
+        );
+        let measurement = read(&device).unwrap();
+        assert_eq!(measurement.get(Quantity::Temperature), Some(-3.2));
+        assert_eq!(measurement.get(Quantity::Humidity), Some(48.2));
+        fs::remove_dir_all(device).unwrap();
+    }
+
+    #[test]
+    fn reports_missing_channels() {
+        let device = device("missing", &[(TEMPERATURE_CHANNEL, "21500\n")]);
+        let result = read(&device);
+        assert!(
+            matches!(&result, Err(Error::Io(e)) if e.kind() == io::ErrorKind::NotFound),
+            "{result:?}"
+        );
+        fs::remove_dir_all(device).unwrap();
+    }
+
+    #[test]
+    fn rejects_garbled_values() {
+        let device = device(
+            "garbled",
+            &[
+                (TEMPERATURE_CHANNEL, "21.5\n"),
+                (HUMIDITY_CHANNEL, "48200\n"),
+            ],
+        );
+        let result = read(&device);
+        assert!(
+            matches!(result, Err(Error::UnexpectedResponse(_))),
+            "{result:?}"
+        );
+        fs::remove_dir_all(device).unwrap();
+    }
+}
@@ -0,0 +1,266 @@
+//! Converts illuminance to photosynthetic photon flux and integrates it over the day.
+//!
+//! Light sensors weigh light by the sensitivity of the human eye, while plants use photons
+//! across the whole 400-700nm range. How many photons make up a lux depends on the spectrum
+//! of the light source, so the conversion takes factors measured for common sources. The
+//! photon flux is summed up into the daily light integral (DLI), split at local midnight.
+//!
+#![deny(unsafe_code)]
+
+use chrono::{DateTime, Duration, Local, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+
+/// Longest time between readings that is still integrated (in seconds), longer gaps don't count
+const MAX_GAP_SECS: i64 = 15 * 60;
+
+/// The light source a sensor sees, which sets how lux convert to photon flux.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Spectrum {
+    #[default]
+    Sunlight,
+    /// Cool white fluorescent tubes
+    Fluorescent,
+    MetalHalide,
+    /// High pressure sodium
+    Hps,
+    /// White LEDs without extra red or blue diodes
+    WhiteLed,
+    /// The PPFD in µmol/m²/s of a single lux
+    Custom(f32),
+}
+
+impl Spectrum {
+    /// The PPFD in µmol/m²/s of a single lux.
+    pub fn ppfd_per_lux(self) -> f32 {
+        match self {
+            Spectrum::Sunlight => 0.0185,
+            Spectrum::Fluorescent => 0.0135,
+            Spectrum::MetalHalide => 0.0141,
+            Spectrum::Hps => 0.0122,
+            Spectrum::WhiteLed => 0.0149,
+            Spectrum::Custom(factor) => factor,
+        }
+    }
+}
+
+/// The light integrated over a single day.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct DailyLight {
+    /// The local date.
+    pub date: NaiveDate,
+    /// The daily light integral in mol/m²/day.
+    pub dli: f32,
+    /// How many hours the readings covered, less than a full day means part of the light is missing.
+    pub covered_hours: f32,
+}
+
+impl DailyLight {
+    fn new(date: NaiveDate) -> Self {
+        DailyLight {
+            date,
+            dli: 0.0,
+            covered_hours: 0.0,
+        }
+    }
+}
+
+/// Integrates the PPFD of consecutive readings into the light of today and yesterday.
+#[derive(Debug, Default, Clone)]
+pub struct LightIntegral {
+    today: Option<DailyLight>,
+    yesterday: Option<DailyLight>,
+    /// The time and PPFD of the last reading.
+    previous: Option<(DateTime<Utc>, f32)>,
+}
+
+impl LightIntegral {
+    /// The light integrated on the day of the last reading.
+    pub fn today(&self) -> Option<DailyLight> {
+        self.today
+    }
+
+    /// The light integrated on the day before the last reading's.
+    pub fn yesterday(&self) -> Option<DailyLight> {
+        self.yesterday
+    }
+
+    /// Adds the light since the previous reading, averaging both readings' PPFD.
+    pub fn add(&mut self, time: DateTime<Utc>, ppfd: f32) {
+        if let Some((previous, previous_ppfd)) = self.previous.replace((time, ppfd)) {
+            let elapsed = time - previous;
+            if elapsed <= Duration::zero() || elapsed > Duration::seconds(MAX_GAP_SECS) {
+                return;
+            }
+
+            // Readings are close together, so they are at most a midnight apart
+            let start = previous.with_timezone(&Local);
+            let end = time.with_timezone(&Local);
+            let midnight = end
+                .date_naive()
+                .and_hms_opt(0, 0, 0)
+                .and_then(|midnight| midnight.and_local_timezone(Local).earliest())
+                .filter(|midnight| start.date_naive() != end.date_naive() && *midnight > start);
+            match midnight {
+                Some(midnight) => {
+                    let share = (midnight - start).num_milliseconds() as f32
+                        / elapsed.num_milliseconds() as f32;
+                    let at_midnight = previous_ppfd + (ppfd - previous_ppfd) * share;
+                    self.integrate(
+                        start.date_naive(),
+                        midnight - start,
+                        previous_ppfd,
+                        at_midnight,
+                    );
+                    self.integrate(end.date_naive(), end - midnight, at_midnight, ppfd);
+                }
+                None => self.integrate(end.date_naive(), elapsed, previous_ppfd, ppfd),
+            }
+        }
+    }
+
+    fn integrate(&mut self, date: NaiveDate, duration: Duration, from: f32, to: f32) {
+        let day = match &mut self.today {
+            Some(today) if today.date == date => today,
+            today => {
+                // Yesterday is only known if it was also integrated
+                self.yesterday = today
+                    .take()
+                    .filter(|today| today.date.succ_opt() == Some(date));
+                today.insert(DailyLight::new(date))
+            }
+        };
+        let seconds = duration.num_milliseconds() as f32 / 1000.0;
+        // µmol to mol
+        day.dli += (from + to) / 2.0 * seconds / 1_000_000.0;
+        day.covered_hours += seconds / 3600.0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    /// The given local time in June 2024, which has no daylight saving change.
+    fn at(day: u32, hour: u32, minute: u32) -> DateTime<Utc> {
+        Local
+            .with_ymd_and_hms(2024, 6, day, hour, minute, 0)
+            .single()
+            .unwrap()
+            .with_timezone(&Utc)
+    }
+
+    fn date(day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2024, 6, day).unwrap()
+    }
+
+    fn assert_light(light: Option<DailyLight>, day: u32, dli: f32, covered_hours: f32) {
+        let light = light.unwrap();
+        assert_eq!(light.date, date(day));
+        assert!((light.dli - dli).abs() < 1e-4, "{} != {dli}", light.dli);
+        assert!(
+            (light.covered_hours - covered_hours).abs() < 1e-4,
+            "{} != {covered_hours}",
+            light.covered_hours
+        );
+    }
+
+    #[test]
+    fn integrates_readings_over_the_day() {
+        let mut light = LightIntegral::default();
+        // 500 µmol/m²/s for an hour, read every minute
+        for minute in 0..=60 {
+            light.add(at(1, 12, 0) + Duration::minutes(minute), 500.0);
+        }
+
+        assert_light(light.today(), 1, 1.8, 1.0);
+        assert!(light.yesterday().is_none());
+    }
+
+    #[test]
+    fn averages_consecutive_readings() {
+        let mut light = LightIntegral::default();
+        light.add(at(1, 6, 0), 0.0);
+        light.add(at(1, 6, 10), 600.0);
+
+        // 300 µmol/m²/s on average for 600 seconds
+        assert_light(light.today(), 1, 0.18, 1.0 / 6.0);
+    }
+
+    #[test]
+    fn splits_readings_at_midnight() {
+        let mut light = LightIntegral::default();
+        light.add(at(1, 23, 55), 600.0);
+        light.add(at(2, 0, 5), 600.0);
+
+        assert_light(light.yesterday(), 1, 0.18, 5.0 / 60.0);
+        assert_light(light.today(), 2, 0.18, 5.0 / 60.0);
+    }
+
+    #[test]
+    fn interpolates_the_ppfd_at_midnight() {
+        let mut light = LightIntegral::default();
+        light.add(at(1, 23, 58), 0.0);
+        light.add(at(2, 0, 2), 400.0);
+
+        // 200 µmol/m²/s at midnight: 100 on average before it, 300 after it, for 120 seconds each
+        assert_light(light.yesterday(), 1, 0.012, 2.0 / 60.0);
+        assert_light(light.today(), 2, 0.036, 2.0 / 60.0);
+    }
+
+    #[test]
+    fn starts_a_new_day_with_a_reading_at_midnight() {
+        let mut light = LightIntegral::default();
+        light.add(at(1, 23, 50), 600.0);
+        light.add(at(2, 0, 0), 600.0);
+
+        assert_light(light.yesterday(), 1, 0.36, 10.0 / 60.0);
+        assert_light(light.today(), 2, 0.0, 0.0);
+
+        light.add(at(2, 0, 10), 600.0);
+        assert_light(light.yesterday(), 1, 0.36, 10.0 / 60.0);
+        assert_light(light.today(), 2, 0.36, 10.0 / 60.0);
+    }
+
+    #[test]
+    fn only_keeps_yesterday_if_it_was_the_day_before() {
+        let mut light = LightIntegral::default();
+        light.add(at(1, 12, 0), 500.0);
+        light.add(at(1, 12, 10), 500.0);
+        light.add(at(3, 12, 0), 500.0);
+        light.add(at(3, 12, 10), 500.0);
+
+        assert_light(light.today(), 3, 0.3, 10.0 / 60.0);
+        assert!(light.yesterday().is_none());
+    }
+
+    #[test]
+    fn leaves_out_gaps_and_readings_out_of_order() {
+        let mut light = LightIntegral::default();
+        light.add(at(1, 12, 0), 500.0);
+        light.add(at(1, 12, 20), 500.0);
+        assert!(light.today().is_none());
+
+        light.add(at(1, 12, 10), 500.0);
+        light.add(at(1, 12, 10), 500.0);
+        assert!(light.today().is_none());
+
+        light.add(at(1, 12, 25), 500.0);
+        assert_light(light.today(), 1, 0.45, 0.25);
+    }
+
+    #[test]
+    fn converts_lux_with_the_factor_of_the_light_source() {
+        assert!((100_000.0 * Spectrum::Sunlight.ppfd_per_lux() - 1850.0).abs() < 0.1);
+        assert!((50_000.0 * Spectrum::Hps.ppfd_per_lux() - 610.0).abs() < 0.1);
+        assert_eq!(Spectrum::Custom(0.02).ppfd_per_lux(), 0.02);
+        assert_eq!(Spectrum::default(), Spectrum::Sunlight);
+
+        let parse = |json| serde_json::from_str::<Spectrum>(json).unwrap();
+        assert_eq!(parse(r#""metal_halide""#), Spectrum::MetalHalide);
+        assert_eq!(parse(r#""white_led""#), Spectrum::WhiteLed);
+        assert_eq!(parse(r#"{ "custom": 0.016 }"#), Spectrum::Custom(0.016));
+    }
+}
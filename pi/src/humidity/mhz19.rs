@@ -0,0 +1,312 @@
+//! Rust driver for the Winsen MH-Z19B NDIR CO2 sensor, read over its UART at 9600 baud.
+//!
+//! Commands and responses are 9 byte frames starting with `0xff`, followed by the sensor
+//! number or the command they answer, and ending in a checksum over the bytes in between.
+//! The response to a read also holds the sensor's internal temperature, which is too coarse
+//! and too warm to report.
+//!
+//! The automatic baseline correction (ABC) assumes the sensor sees fresh air at 400ppm once a
+//! day, so it should be turned off in rooms that are enriched with CO2 around the clock.
+//!
+#![deny(unsafe_code)]
+
+use embedded_hal::blocking::delay::{DelayMs, DelayUs};
+
+use super::{
+    bus::SerialPort,
+    device::{Device, Measurement},
+    pin::Clock,
+    quantity::Quantity,
+    Error, Result,
+};
+
+/// Largest detection range in parts per million, the sensor can also be set to 2000 or 5000
+pub const CO2_RANGE: (f32, f32) = (0.0, 10_000.0);
+
+/// Length of every command and response
+pub(super) const FRAME_LEN: usize = 9;
+/// First byte of every frame
+pub(super) const START: u8 = 0xff;
+/// The sensor number commands are addressed to
+const SENSOR: u8 = 0x01;
+pub(super) const READ_CO2: u8 = 0x86;
+pub(super) const SET_ABC: u8 = 0x79;
+/// Argument of [`SET_ABC`] turning the baseline correction on, zero turns it off
+pub(super) const ABC_ON: u8 = 0xa0;
+/// How long to wait for a reply to [`SET_ABC`], which only some firmware sends (in milliseconds)
+const ABC_REPLY_MS: u16 = 100;
+
+/// The checksum of a frame: the negated sum of the bytes between the start byte and itself.
+pub fn checksum(frame: &[u8; FRAME_LEN]) -> u8 {
+    frame[1..8]
+        .iter()
+        .fold(0u8, |sum, byte| sum.wrapping_add(*byte))
+        .wrapping_neg()
+}
+
+/// Builds the frame sending `command` with the given arguments.
+pub fn command(command: u8, arguments: [u8; 5]) -> [u8; FRAME_LEN] {
+    let [a, b, c, d, e] = arguments;
+    let mut frame = [START, SENSOR, command, a, b, c, d, e, 0];
+    frame[8] = checksum(&frame);
+    frame
+}
+
+/// Extracts the CO2 concentration in parts per million from the response to a read.
+pub fn parse(frame: &[u8; FRAME_LEN]) -> Result<f32> {
+    if frame[..2] != [START, READ_CO2] {
+        return Err(Error::UnexpectedResponse(format!("{frame:02x?}")));
+    }
+    let expected = checksum(frame);
+    if expected != frame[8] {
+        return Err(Error::ChecksumMismatch {
+            expected,
+            actual: frame[8],
+        });
+    }
+    Ok(f32::from(u16::from_be_bytes([frame[2], frame[3]])))
+}
+
+/// An MH-Z19B device.
+pub struct Mhz19<S> {
+    /// The serial port the sensor is connected to.
+    port: S,
+    /// Whether the baseline correction is turned on or off before the next read.
+    self_calibration: Option<bool>,
+}
+
+impl<S> Mhz19<S> {
+    /// Creates a device on the given port, leaving its baseline correction as it is.
+    pub fn new(port: S) -> Self {
+        Mhz19 {
+            port,
+            self_calibration: None,
+        }
+    }
+
+    /// Turns the automatic baseline correction on or off before the first read.
+    pub fn with_self_calibration(mut self, enabled: bool) -> Self {
+        self.self_calibration = Some(enabled);
+        self
+    }
+}
+
+impl<S> Mhz19<S>
+where
+    S: SerialPort,
+    Error: From<S::Error>,
+{
+    /// Reads the CO2 concentration, the sensor measures continuously.
+    pub fn perform_measurement<D: DelayMs<u16>>(&mut self, delay: &mut D) -> Result<Measurement> {
+        if let Some(enabled) = self.self_calibration {
+            let argument = if enabled { ABC_ON } else { 0 };
+            self.port
+                .write_all(&command(SET_ABC, [argument, 0, 0, 0, 0]))?;
+            self.self_calibration = None;
+            delay.delay_ms(ABC_REPLY_MS);
+        }
+
+        // Leftovers of a reply or a late response would shift the frame
+        self.port.discard_input()?;
+        self.port.write_all(&command(READ_CO2, [0; 5]))?;
+        let mut frame = [0; FRAME_LEN];
+        match self.port.read(&mut frame)? {
+            0 => return Err(Error::NoResponse),
+            FRAME_LEN => {}
+            _ => return Err(Error::Corrupted),
+        }
+
+        Ok(Measurement::new().with(Quantity::Co2, parse(&frame)?))
+    }
+}
+
+impl<S> Device for Mhz19<S>
+where
+    S: SerialPort,
+    Error: From<S::Error>,
+{
+    fn perform_measurement<D: DelayUs<u16> + DelayMs<u16> + Clock>(
+        &mut self,
+        delay: &mut D,
+    ) -> Result<Measurement> {
+        self.perform_measurement(delay)
+    }
+}
+
+#[cfg(all(test, feature = "sim"))]
+mod tests {
+    use super::*;
+    use crate::humidity::{
+        sim::{Fault, SimClock},
+        sim_uart::SimMhz19,
+    };
+
+    fn read(fault: Option<Fault>) -> Result<Measurement> {
+        let port = SimMhz19::new(|| 812.0).with_faults(move || fault);
+        Mhz19::new(port).perform_measurement(&mut SimClock::new().delay())
+    }
+
+    #[test]
+    fn checksums_match_the_datasheet() {
+        // The read command as listed in the datasheet
+        assert_eq!(
+            command(READ_CO2, [0; 5]),
+            [0xff, 0x01, 0x86, 0x00, 0x00, 0x00, 0x00, 0x00, 0x79]
+        );
+    }
+
+    #[test]
+    fn reads_a_good_frame() {
+        let measurement = read(None).unwrap();
+        assert_eq!(measurement.get(Quantity::Co2), Some(812.0));
+    }
+
+    #[test]
+    fn rejects_a_bad_checksum() {
+        let result = read(Some(Fault::CorruptBit(12)));
+        assert!(
+            matches!(result, Err(Error::ChecksumMismatch { .. })),
+            "{result:?}"
+        );
+    }
+
+    #[test]
+    fn rejects_a_short_read() {
+        let result = read(Some(Fault::Truncated(40)));
+        assert!(matches!(result, Err(Error::Corrupted)), "{result:?}");
+
+        let result = read(Some(Fault::NoResponse));
+        assert!(matches!(result, Err(Error::NoResponse)), "{result:?}");
+    }
+
+    #[test]
+    fn rejects_other_frames() {
+        // A command echoed back starts with the sensor number instead of the command
+        let result = parse(&command(READ_CO2, [0; 5]));
+        assert!(
+            matches!(result, Err(Error::UnexpectedResponse(_))),
+            "{result:?}"
+        );
+    }
+}
+
+#[cfg(all(test, feature = "gpio-cdev"))]
+mod pty_tests {
+    use std::{
+        io::{Read, Write},
+        path::Path,
+        thread::{self, JoinHandle},
+        time::{Duration, Instant},
+    };
+
+    use nix::{
+        fcntl::OFlag,
+        pty::{grantpt, posix_openpt, ptsname_r, unlockpt, PtyMaster},
+    };
+
+    use super::*;
+    use crate::gpio::Serial;
+
+    /// Sleeps through delays, the port times reads out on its own.
+    struct Sleep;
+
+    impl DelayMs<u16> for Sleep {
+        fn delay_ms(&mut self, ms: u16) {
+            thread::sleep(Duration::from_millis(ms.into()));
+        }
+    }
+
+    /// Opens a pseudo-terminal, returning the port the driver reads and the sensor's end.
+    fn pseudo_terminal() -> (Serial, PtyMaster) {
+        let sensor = posix_openpt(OFlag::O_RDWR | OFlag::O_NOCTTY).unwrap();
+        grantpt(&sensor).unwrap();
+        unlockpt(&sensor).unwrap();
+        let port = Serial::termios(Path::new(&ptsname_r(&sensor).unwrap())).unwrap();
+        (port, sensor)
+    }
+
+    /// Receives `commands` frames on the sensor's end, answering reads with `ppm`.
+    fn answer(
+        mut sensor: PtyMaster,
+        commands: usize,
+        ppm: Option<u16>,
+    ) -> JoinHandle<(Vec<[u8; FRAME_LEN]>, PtyMaster)> {
+        thread::spawn(move || {
+            let mut received = Vec::new();
+            for _ in 0..commands {
+                let mut frame = [0; FRAME_LEN];
+                sensor.read_exact(&mut frame).unwrap();
+                if let (READ_CO2, Some(ppm)) = (frame[2], ppm) {
+                    // The temperature byte and the unused ones are XOFF, XON and ^C
+                    let [high, low] = ppm.to_be_bytes();
+                    let mut response = [START, READ_CO2, high, low, 0x13, 0x11, 0x03, 0x00, 0];
+                    response[8] = checksum(&response);
+                    sensor.write_all(&response).unwrap();
+                }
+                received.push(frame);
+            }
+            (received, sensor)
+        })
+    }
+
+    #[test]
+    fn reads_co2_through_a_pseudo_terminal() {
+        let (port, sensor) = pseudo_terminal();
+        // CR and LF, which a terminal not in raw mode would translate
+        let responder = answer(sensor, 1, Some(0x0d0a));
+
+        let measurement = Mhz19::new(port).perform_measurement(&mut Sleep).unwrap();
+        assert_eq!(measurement.get(Quantity::Co2), Some(3338.0));
+        let (received, _sensor) = responder.join().unwrap();
+        assert_eq!(received, [command(READ_CO2, [0; 5])]);
+    }
+
+    #[test]
+    fn sets_the_self_calibration_before_reading() {
+        let (port, sensor) = pseudo_terminal();
+        let responder = answer(sensor, 2, Some(420));
+
+        let measurement = Mhz19::new(port)
+            .with_self_calibration(true)
+            .perform_measurement(&mut Sleep)
+            .unwrap();
+        assert_eq!(measurement.get(Quantity::Co2), Some(420.0));
+        let (received, _sensor) = responder.join().unwrap();
+        assert_eq!(
+            received,
+            [
+                command(SET_ABC, [ABC_ON, 0, 0, 0, 0]),
+                command(READ_CO2, [0; 5])
+            ]
+        );
+    }
+
+    #[test]
+    fn drops_leftovers_before_reading() {
+        let (port, mut sensor) = pseudo_terminal();
+        // Half of a late response, which would shift the next frame
+        sensor.write_all(&[START, READ_CO2, 0x01, 0x90]).unwrap();
+        thread::sleep(Duration::from_millis(50));
+        let responder = answer(sensor, 1, Some(650));
+
+        let measurement = Mhz19::new(port).perform_measurement(&mut Sleep).unwrap();
+        assert_eq!(measurement.get(Quantity::Co2), Some(650.0));
+        responder.join().unwrap();
+    }
+
+    #[test]
+    fn gives_up_when_the_sensor_stays_silent() {
+        let (port, sensor) = pseudo_terminal();
+        let responder = answer(sensor, 1, None);
+
+        let started = Instant::now();
+        let result = Mhz19::new(port).perform_measurement(&mut Sleep);
+        assert!(matches!(result, Err(Error::NoResponse)), "{result:?}");
+        assert!(
+            started.elapsed() >= Duration::from_millis(900),
+            "{:?}",
+            started.elapsed()
+        );
+        responder.join().unwrap();
+    }
+}
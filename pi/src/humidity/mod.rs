@@ -1,60 +1,438 @@
+mod ads1115;
+mod bh1750;
+mod bme280;
+mod bus;
+mod climate;
+mod correction;
 mod device;
-mod dht11;
-mod dht22;
+mod dht;
+mod ds18b20;
 mod error;
+mod iio;
+mod light;
+mod mhz19;
+mod pin;
+mod quantity;
+mod retry;
+mod scd4x;
+mod sht;
+#[cfg(feature = "sim")]
+pub mod sim;
+#[cfg(feature = "sim")]
+pub mod sim_i2c;
+#[cfg(feature = "sim")]
+pub mod sim_iio;
+#[cfg(feature = "sim")]
+pub mod sim_uart;
+#[cfg(feature = "sim")]
+pub mod sim_w1;
+mod soil;
 mod tracker;
+mod tsl2591;
+mod validate;
 
-use std::sync::Arc;
+use std::{
+    path::PathBuf,
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
+};
 
 use chrono::DateTime;
 use serde::{Deserialize, Serialize};
-use tokio::{sync::RwLock, task::JoinHandle, time::Interval};
+use tokio::{
+    sync::{
+        mpsc::{self, error::TrySendError},
+        oneshot, RwLock,
+    },
+    task::JoinHandle,
+    time::{self, timeout, timeout_at, Interval},
+};
 
+use crate::gpio::{I2c, Iio, IoPin, OneWire, Serial};
+
+pub use ads1115::Settings as AdcSettings;
+pub use bus::{DeviceLock, IioDevice, SerialPort, SharedDevices, W1Bus};
+pub use correction::{
+    check as check_corrections, Corrections, FitError, Point as CalibrationPoint,
+};
 pub use device::Measurement;
-pub use error::{Error, Result};
+pub use dht::{Diagnostics, ReadMode};
+pub use error::{Error, Result, SensorError};
+pub use light::{DailyLight, LightIntegral, Spectrum};
+pub use pin::{Clock, Edge, EdgeCapture, IoMode};
+pub use quantity::{PressureUnit, Quantity, TemperatureUnit, Units, Value};
+pub use retry::RetryPolicy;
+pub use sht::Settings as ShtSettings;
+pub use soil::Calibration as SoilCalibration;
 pub use tracker::Tracker;
+pub use validate::{ValidationCounts, ValidationPolicy};
 
-#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum SensorType {
+    /// DHT22 or AM2302
     Dht22,
     Dht11,
+    /// DHT21 or AM2301
+    Dht21,
+    /// AM2320 wired in single-wire mode
+    Am2320,
+    /// BME280 or BMP280 on an I2C bus, told apart by their chip id
+    Bme280,
+    /// SHT30, SHT31 or SHT35 on an I2C bus
+    Sht3x,
+    /// SHT40, SHT41 or SHT45 on an I2C bus
+    Sht4x,
+    /// DS18B20 temperature probe on the kernel's 1-Wire bus
+    Ds18b20,
+    /// Capacitive soil moisture probe on a channel of an ADS1115 converter
+    #[serde(rename = "soil_moisture")]
+    SoilMoisture,
+    /// SCD40 or SCD41 CO2 sensor on an I2C bus, which also measures temperature and humidity
+    Scd4x,
+    /// MH-Z19B CO2 sensor on a serial port
+    Mhz19b,
+    /// BH1750 ambient light sensor on an I2C bus
+    Bh1750,
+    /// TSL2591 ambient light sensor on an I2C bus
+    Tsl2591,
+}
+
+impl SensorType {
+    /// The DHT model, for sensors read through a single-wire data line.
+    fn dht_model(self) -> Option<dht::Model> {
+        match self {
+            SensorType::Dht22 => Some(dht::DHT22),
+            SensorType::Dht11 => Some(dht::DHT11),
+            SensorType::Dht21 => Some(dht::DHT21),
+            SensorType::Am2320 => Some(dht::AM2320),
+            SensorType::Bme280
+            | SensorType::Sht3x
+            | SensorType::Sht4x
+            | SensorType::Ds18b20
+            | SensorType::SoilMoisture
+            | SensorType::Scd4x
+            | SensorType::Mhz19b
+            | SensorType::Bh1750
+            | SensorType::Tsl2591 => None,
+        }
+    }
+
+    /// The SHT family, for Sensirion sensors on an I2C bus.
+    pub fn sht_family(self) -> Option<sht::Family> {
+        match self {
+            SensorType::Sht3x => Some(sht::Family::Sht3x),
+            SensorType::Sht4x => Some(sht::Family::Sht4x),
+            SensorType::Dht22
+            | SensorType::Dht11
+            | SensorType::Dht21
+            | SensorType::Am2320
+            | SensorType::Bme280
+            | SensorType::Ds18b20
+            | SensorType::SoilMoisture
+            | SensorType::Scd4x
+            | SensorType::Mhz19b
+            | SensorType::Bh1750
+            | SensorType::Tsl2591 => None,
+        }
+    }
+
+    /// The address the sensor answers on by default, for sensors on an I2C bus.
+    pub fn default_address(self) -> Option<u8> {
+        match self {
+            SensorType::Bme280 => Some(bme280::DEFAULT_ADDRESS),
+            SensorType::Sht3x | SensorType::Sht4x => Some(sht::DEFAULT_ADDRESS),
+            SensorType::SoilMoisture => Some(ads1115::DEFAULT_ADDRESS),
+            SensorType::Scd4x => Some(scd4x::DEFAULT_ADDRESS),
+            SensorType::Bh1750 => Some(bh1750::DEFAULT_ADDRESS),
+            SensorType::Tsl2591 => Some(tsl2591::DEFAULT_ADDRESS),
+            SensorType::Dht22
+            | SensorType::Dht11
+            | SensorType::Dht21
+            | SensorType::Am2320
+            | SensorType::Ds18b20
+            | SensorType::Mhz19b => None,
+        }
+    }
 }
 
+/// How a sensor is wired to the controller.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Connection {
+    /// The data line of a single-wire sensor on a GPIO pin.
+    Pin(u8),
+    /// An I2C bus and the address of the sensor on it.
+    I2c { bus: u8, address: u8 },
+    /// The sysfs directory of the IIO device the kernel's `dht11` driver registered for the sensor.
+    Iio { device: PathBuf },
+    /// The sysfs directory listing the kernel's 1-Wire devices and the id of the sensor in it.
+    OneWire { root: PathBuf, device: String },
+    /// The serial port the sensor's UART is connected to.
+    Serial { port: PathBuf },
+}
+
+// Each tracker owns a single sensor, so the variant sizes don't add up
+#[allow(clippy::large_enum_variant)]
 enum Sensor {
-    Dht22(dht22::Dht22),
-    Dht11(dht11::Dht11),
+    Dht(dht::Dht<IoPin>),
+    Bme280(bme280::Bme280<I2c>),
+    Sht(sht::Sht<I2c>),
+    Ds18b20(ds18b20::Ds18b20<OneWire>),
+    Iio(iio::IioDht<Iio>),
+    Soil(soil::SoilProbe<I2c>),
+    Scd4x(scd4x::Scd4x<I2c>),
+    Mhz19(mhz19::Mhz19<Serial>),
+    Bh1750(bh1750::Bh1750<I2c>),
+    Tsl2591(tsl2591::Tsl2591<I2c>),
 }
 
+/// How long readings are paused after a heater pulse, while the sensor cools down
+const HEATER_COOLDOWN: Duration = Duration::from_secs(10);
+/// How long a heater pulse may take, including waiting for a read in progress
+pub const HEATER_DEADLINE: Duration = Duration::from_secs(5);
+
 #[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub struct Reading {
     pub result: Measurement,
     pub time: DateTime<chrono::Utc>,
+    /// How many attempts it took to get the measurement.
+    pub attempts: u16,
+    /// Why the reading failed validation, if it was kept anyway.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub suspect: Option<validate::Suspicion>,
 }
 
 pub trait Update {
     fn update(&mut self, reading: Reading);
-    fn error(&mut self, error: Error) {
-        println!("Error: {:?}", error);
+    fn error(&mut self, error: SensorError) {
+        if error.error.is_disconnected() {
+            println!("Disconnected: {}", error);
+        } else {
+            println!("Error: {}", error);
+        }
     }
 }
 
+/// Work handed to the thread owning a [`Tracker`].
+enum Request {
+    /// Reads the sensor, the result is sent back with the same sequence number.
+    Read(u64),
+    Diagnose(oneshot::Sender<core::result::Result<Diagnostics, SensorError>>),
+    Heat(oneshot::Sender<core::result::Result<(), SensorError>>),
+    Calibrate(
+        Corrections,
+        oneshot::Sender<core::result::Result<(), SensorError>>,
+    ),
+}
+
+/// Sends requests to a sensor that is being tracked.
+#[derive(Clone)]
+pub struct TrackerHandle {
+    sensor: String,
+    requests: mpsc::Sender<Request>,
+    validation: Arc<validate::ValidationStats>,
+}
+
+impl TrackerHandle {
+    /// Performs a diagnostic read in between the regular readings.
+    pub async fn diagnose(
+        &self,
+        deadline: Duration,
+    ) -> core::result::Result<Diagnostics, SensorError> {
+        self.request(Request::Diagnose, deadline).await
+    }
+
+    /// Pulses the sensor's heater in between the regular readings.
+    pub async fn heat(&self, deadline: Duration) -> core::result::Result<(), SensorError> {
+        self.request(Request::Heat, deadline).await
+    }
+
+    /// Replaces the corrections applied to the sensor's next readings.
+    pub async fn calibrate(
+        &self,
+        corrections: Corrections,
+        deadline: Duration,
+    ) -> core::result::Result<(), SensorError> {
+        self.request(|reply| Request::Calibrate(corrections, reply), deadline)
+            .await
+    }
+
+    /// How many readings failed validation so far.
+    pub fn validation(&self) -> ValidationCounts {
+        self.validation.counts()
+    }
+
+    /// Hands a request to the reader thread and waits for its reply.
+    async fn request<R>(
+        &self,
+        request: impl FnOnce(oneshot::Sender<core::result::Result<R, SensorError>>) -> Request,
+        deadline: Duration,
+    ) -> core::result::Result<R, SensorError> {
+        let (reply, result) = oneshot::channel();
+        let request = async {
+            self.requests
+                .send(request(reply))
+                .await
+                .map_err(|_| self.error(Error::Stopped, 0))?;
+            result.await.map_err(|_| self.error(Error::Stopped, 0))?
+        };
+        timeout(deadline, request)
+            .await
+            .map_err(|_| self.error(Error::Deadline(deadline), 0))?
+    }
+
+    /// Attaches the id of the tracked sensor to an error.
+    fn error(&self, error: Error, attempts: u16) -> SensorError {
+        SensorError {
+            sensor: self.sensor.clone(),
+            attempts,
+            error,
+        }
+    }
+}
+
+/// Reads the sensor on every tick of `interval` and reports the result to `state`.
+///
+/// Reads block, so they run on a dedicated thread. A read taking longer than `deadline`
+/// is reported as an error, and ticks are skipped until it finishes, e.g. while retrying.
+/// Its result is still reported once it arrives, but isn't mistaken for that of a later read.
+/// Readings are validated before they reach `state`. Readings at or above the tracker's
+/// condensation humidity pulse the heater, and reads pause while the sensor cools down.
 pub fn start_tracking<T: Update + Send + Sync + 'static>(
     state: Arc<RwLock<T>>,
-    mut tracker: Tracker,
+    tracker: Tracker,
     mut interval: Interval,
-) -> JoinHandle<()> {
-    tokio::task::spawn(async move {
+    deadline: Duration,
+) -> (JoinHandle<()>, TrackerHandle) {
+    let sensor = tracker.id().to_owned();
+    let condensation = tracker.condensation_humidity();
+    let mut validator = tracker.validator();
+    let validation = validator.stats();
+    let (requests, mut results) = spawn_reader(tracker);
+    let handle = TrackerHandle {
+        sensor,
+        requests,
+        validation,
+    };
+    let tracked = handle.clone();
+
+    let task = tokio::task::spawn(async move {
+        let mut cooling_until = None;
+        let mut sequence = 0;
         loop {
             interval.tick().await;
-            let read_result = tracker.read();
-            match read_result {
-                Ok(reading) => {
-                    state.write().await.update(reading);
+
+            // Results of reads that missed their deadline are still worth keeping
+            while let Ok((_, result)) = results.try_recv() {
+                report(&state, &tracked, &mut validator, result).await;
+            }
+
+            if cooling_until.is_some_and(|until| Instant::now() < until) {
+                continue;
+            }
+
+            sequence += 1;
+            match tracked.requests.try_send(Request::Read(sequence)) {
+                Ok(()) => {}
+                Err(TrySendError::Full(_)) => continue,
+                Err(TrySendError::Closed(_)) => break,
+            }
+
+            let until = time::Instant::now() + deadline;
+            let received = loop {
+                match timeout_at(until, results.recv()).await {
+                    // The result of an earlier read that missed its deadline
+                    Ok(Some((read, result))) if read != sequence => {
+                        report(&state, &tracked, &mut validator, result).await;
+                    }
+                    received => break received,
                 }
-                Err(e) => {
-                    println!("Error reading sensor: {:?}", e);
+            };
+            match received {
+                Ok(Some((_, result))) => {
+                    let condensed = match (&result, condensation) {
+                        (Ok(reading), Some(threshold)) => reading
+                            .result
+                            .get(Quantity::Humidity)
+                            .is_some_and(|h| h >= threshold),
+                        _ => false,
+                    };
+                    report(&state, &tracked, &mut validator, result).await;
+
+                    if condensed {
+                        if let Err(e) = tracked.heat(HEATER_DEADLINE).await {
+                            state.write().await.error(e);
+                        }
+                        cooling_until = Some(Instant::now() + HEATER_COOLDOWN);
+                    }
+                }
+                Ok(None) => break,
+                Err(_) => {
+                    let error = tracked.error(Error::Deadline(deadline), 0);
+                    state.write().await.error(error);
                 }
             }
         }
-    })
+
+        // The reader thread is gone, e.g. because it panicked
+        state.write().await.error(tracked.error(Error::Stopped, 0));
+    });
+
+    (task, handle)
+}
+
+/// The result of a read and the sequence number of the request it answers.
+type ReadResult = (u64, core::result::Result<Reading, SensorError>);
+
+/// Starts the thread performing reads, returning the channels to request and receive them.
+fn spawn_reader(mut tracker: Tracker) -> (mpsc::Sender<Request>, mpsc::Receiver<ReadResult>) {
+    let (requests, mut pending) = mpsc::channel(1);
+    let (results, received) = mpsc::channel(1);
+
+    thread::Builder::new()
+        .name(tracker.id().to_owned())
+        .spawn(move || {
+            while let Some(request) = pending.blocking_recv() {
+                match request {
+                    Request::Read(sequence) => {
+                        if results.blocking_send((sequence, tracker.read())).is_err() {
+                            break;
+                        }
+                    }
+                    // Nobody to tell if these requests were abandoned
+                    Request::Diagnose(reply) => {
+                        let _ = reply.send(tracker.diagnose());
+                    }
+                    Request::Heat(reply) => {
+                        let _ = reply.send(tracker.heat());
+                    }
+                    Request::Calibrate(corrections, reply) => {
+                        tracker.set_corrections(corrections);
+                        let _ = reply.send(Ok(()));
+                    }
+                }
+            }
+        })
+        .expect("failed to spawn the humidity reader thread");
+
+    (requests, received)
+}
+
+async fn report<T: Update>(
+    state: &RwLock<T>,
+    tracked: &TrackerHandle,
+    validator: &mut validate::Validator,
+    result: core::result::Result<Reading, SensorError>,
+) {
+    let result = result.and_then(|reading| {
+        validator
+            .check(reading)
+            .map_err(|error| tracked.error(error, reading.attempts))
+    });
+    match result {
+        Ok(reading) => state.write().await.update(reading),
+        Err(e) => state.write().await.error(e),
+    }
 }
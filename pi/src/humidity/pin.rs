@@ -0,0 +1,55 @@
+use core::time::Duration;
+
+use embedded_hal::digital::v2::{InputPin, OutputPin};
+
+/// A GPIO pin that can switch between driving and sampling the data line.
+pub trait IoMode {
+    /// The error returned when the pin cannot change mode.
+    type Error;
+
+    /// Switches the pin to input mode so the sensor can drive the line.
+    fn set_input(&mut self) -> Result<(), Self::Error>;
+    /// Switches the pin to output mode so the host can drive the line.
+    fn set_output(&mut self) -> Result<(), Self::Error>;
+}
+
+/// A pin that can be used as the data line of a DHT sensor.
+pub trait DataPin:
+    InputPin + OutputPin<Error = PinError<Self>> + IoMode<Error = PinError<Self>>
+{
+}
+
+impl<P> DataPin for P where
+    P: InputPin + OutputPin<Error = PinError<P>> + IoMode<Error = PinError<P>>
+{
+}
+
+/// The error type shared by all operations of a [`DataPin`].
+pub type PinError<P> = <P as InputPin>::Error;
+
+/// A monotonic clock used to time the pulses on the data line.
+pub trait Clock {
+    /// The current time in microseconds since an arbitrary fixed point.
+    fn now_us(&self) -> u64;
+}
+
+/// A level change on the data line, timestamped when it happened.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Edge {
+    /// When the edge occurred in microseconds, only differences between edges are meaningful.
+    pub time_us: u64,
+    /// Whether the line went from low to high.
+    pub rising: bool,
+}
+
+/// A pin that can record its edges in the background, e.g. through GPIO interrupts.
+pub trait EdgeCapture {
+    /// The error returned when edges cannot be captured.
+    type Error;
+
+    /// Switches the pin to input and starts recording edges.
+    fn start_capture(&mut self) -> Result<(), Self::Error>;
+    /// Waits until `count` edges were recorded or `timeout` elapsed, then stops recording.
+    fn finish_capture(&mut self, count: usize, timeout: Duration)
+        -> Result<Vec<Edge>, Self::Error>;
+}
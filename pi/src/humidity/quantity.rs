@@ -0,0 +1,403 @@
+//! The physical quantities sensors measure, the units their values are recorded in and the
+//! units they can be converted to for display.
+//!
+#![deny(unsafe_code)]
+
+use core::fmt;
+
+use serde::{Deserialize, Serialize};
+
+/// A hectopascal is this many inches of mercury at 0°C
+const HECTOPASCAL_IN_INCHES_OF_MERCURY: f32 = 0.029_53;
+/// 0°C in kelvin
+const ZERO_CELSIUS_IN_KELVIN: f32 = 273.15;
+
+/// Something a sensor measures.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Quantity {
+    Temperature,
+    /// Relative humidity
+    Humidity,
+    /// Air pressure
+    Pressure,
+    /// Volumetric soil moisture
+    SoilMoisture,
+    /// CO2 concentration
+    Co2,
+    Illuminance,
+    /// Photosynthetic photon flux density
+    Ppfd,
+    /// Vapour pressure deficit of the air, derived from temperature and humidity
+    Vpd,
+    /// Vapour pressure deficit between the leaves and the air
+    LeafVpd,
+    DewPoint,
+    AbsoluteHumidity,
+    /// The temperature the air feels like
+    HeatIndex,
+}
+
+impl Quantity {
+    /// Every quantity, in the order readings list them.
+    pub const ALL: [Quantity; 12] = [
+        Quantity::Temperature,
+        Quantity::Humidity,
+        Quantity::Pressure,
+        Quantity::SoilMoisture,
+        Quantity::Co2,
+        Quantity::Illuminance,
+        Quantity::Ppfd,
+        Quantity::Vpd,
+        Quantity::LeafVpd,
+        Quantity::DewPoint,
+        Quantity::AbsoluteHumidity,
+        Quantity::HeatIndex,
+    ];
+
+    /// The unit values of this quantity are recorded in.
+    pub fn unit(self) -> Unit {
+        match self {
+            Quantity::Temperature | Quantity::DewPoint | Quantity::HeatIndex => Unit::Celsius,
+            Quantity::Humidity | Quantity::SoilMoisture => Unit::Percent,
+            Quantity::Pressure => Unit::Hectopascal,
+            Quantity::Co2 => Unit::PartsPerMillion,
+            Quantity::Illuminance => Unit::Lux,
+            Quantity::Ppfd => Unit::MicromolePerSquareMetreSecond,
+            Quantity::Vpd | Quantity::LeafVpd => Unit::Kilopascal,
+            Quantity::AbsoluteHumidity => Unit::GramPerCubicMetre,
+        }
+    }
+
+    /// Whether the quantity is computed from others rather than measured.
+    pub fn is_derived(self) -> bool {
+        matches!(
+            self,
+            Quantity::Ppfd
+                | Quantity::Vpd
+                | Quantity::LeafVpd
+                | Quantity::DewPoint
+                | Quantity::AbsoluteHumidity
+                | Quantity::HeatIndex
+        )
+    }
+
+    /// The position of this quantity in [`Quantity::ALL`].
+    pub(super) fn index(self) -> usize {
+        self as usize
+    }
+}
+
+impl fmt::Display for Quantity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Quantity::Temperature => "Temperature",
+            Quantity::Humidity => "Humidity",
+            Quantity::Pressure => "Pressure",
+            Quantity::SoilMoisture => "Soil moisture",
+            Quantity::Co2 => "CO2",
+            Quantity::Illuminance => "Illuminance",
+            Quantity::Ppfd => "PPFD",
+            Quantity::Vpd => "VPD",
+            Quantity::LeafVpd => "Leaf VPD",
+            Quantity::DewPoint => "Dew point",
+            Quantity::AbsoluteHumidity => "Absolute humidity",
+            Quantity::HeatIndex => "Heat index",
+        })
+    }
+}
+
+/// The unit of a value, serialized as its symbol.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Unit {
+    #[serde(rename = "°C")]
+    Celsius,
+    #[serde(rename = "°F")]
+    Fahrenheit,
+    #[serde(rename = "K")]
+    Kelvin,
+    #[serde(rename = "%")]
+    Percent,
+    #[serde(rename = "hPa")]
+    Hectopascal,
+    #[serde(rename = "inHg")]
+    InchOfMercury,
+    #[serde(rename = "ppm")]
+    PartsPerMillion,
+    #[serde(rename = "lx")]
+    Lux,
+    #[serde(rename = "µmol/m²/s")]
+    MicromolePerSquareMetreSecond,
+    #[serde(rename = "kPa")]
+    Kilopascal,
+    #[serde(rename = "g/m³")]
+    GramPerCubicMetre,
+}
+
+impl Unit {
+    pub fn symbol(self) -> &'static str {
+        match self {
+            Unit::Celsius => "°C",
+            Unit::Fahrenheit => "°F",
+            Unit::Kelvin => "K",
+            Unit::Percent => "%",
+            Unit::Hectopascal => "hPa",
+            Unit::InchOfMercury => "inHg",
+            Unit::PartsPerMillion => "ppm",
+            Unit::Lux => "lx",
+            Unit::MicromolePerSquareMetreSecond => "µmol/m²/s",
+            Unit::Kilopascal => "kPa",
+            Unit::GramPerCubicMetre => "g/m³",
+        }
+    }
+}
+
+impl fmt::Display for Unit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.symbol())
+    }
+}
+
+/// A single measured value.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Value {
+    pub quantity: Quantity,
+    pub value: f32,
+    pub unit: Unit,
+}
+
+impl Value {
+    /// A value of `quantity` in the unit it is recorded in.
+    pub fn new(quantity: Quantity, value: f32) -> Self {
+        Value {
+            quantity,
+            value,
+            unit: quantity.unit(),
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}{}", self.quantity, self.value, self.unit)
+    }
+}
+
+/// The unit temperatures are shown in.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum TemperatureUnit {
+    #[default]
+    Celsius,
+    Fahrenheit,
+    Kelvin,
+}
+
+/// The unit air pressures are shown in.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+pub enum PressureUnit {
+    #[default]
+    #[serde(rename = "hpa")]
+    #[value(name = "hpa")]
+    Hectopascal,
+    #[serde(rename = "kpa")]
+    #[value(name = "kpa")]
+    Kilopascal,
+    /// Inches of mercury
+    #[serde(rename = "inhg")]
+    #[value(name = "inhg")]
+    InchOfMercury,
+}
+
+/// The units values are shown in, quantities without a choice keep the unit they are recorded in.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, clap::Args)]
+#[serde(default)]
+#[command(about = None, long_about = None, next_help_heading = "Units")]
+pub struct Units {
+    /// Unit of temperatures, dew points and heat indices in API responses
+    #[arg(long = "temperature-unit", value_enum, default_value_t)]
+    pub temperature: TemperatureUnit,
+    /// Unit of air pressures in API responses
+    #[arg(long = "pressure-unit", value_enum, default_value_t)]
+    pub pressure: PressureUnit,
+}
+
+impl Units {
+    /// Converts a value recorded in its quantity's unit to the preferred one.
+    pub fn convert(self, value: Value) -> Value {
+        let (converted, unit) = match (value.unit, self.temperature, self.pressure) {
+            (Unit::Celsius, TemperatureUnit::Fahrenheit, _) => {
+                (value.value * 9.0 / 5.0 + 32.0, Unit::Fahrenheit)
+            }
+            (Unit::Celsius, TemperatureUnit::Kelvin, _) => {
+                (value.value + ZERO_CELSIUS_IN_KELVIN, Unit::Kelvin)
+            }
+            (Unit::Hectopascal, _, PressureUnit::Kilopascal) => {
+                (value.value / 10.0, Unit::Kilopascal)
+            }
+            (Unit::Hectopascal, _, PressureUnit::InchOfMercury) => (
+                value.value * HECTOPASCAL_IN_INCHES_OF_MERCURY,
+                Unit::InchOfMercury,
+            ),
+            _ => return value,
+        };
+        Value {
+            value: converted,
+            unit,
+            ..value
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::humidity::{climate, Measurement};
+
+    fn units(temperature: TemperatureUnit, pressure: PressureUnit) -> Units {
+        Units {
+            temperature,
+            pressure,
+        }
+    }
+
+    fn assert_converted(units: Units, quantity: Quantity, value: f32, expected: f32, unit: Unit) {
+        let converted = units.convert(Value::new(quantity, value));
+        assert_eq!(converted.quantity, quantity);
+        assert_eq!(converted.unit, unit);
+        assert!(
+            (converted.value - expected).abs() < 1e-3,
+            "{value} {quantity} is {} {unit}, not {expected}",
+            converted.value
+        );
+    }
+
+    #[test]
+    fn converts_temperatures() {
+        let fahrenheit = units(TemperatureUnit::Fahrenheit, PressureUnit::default());
+        let kelvin = units(TemperatureUnit::Kelvin, PressureUnit::default());
+        for (celsius, degrees_fahrenheit, kelvins) in [
+            (0.0, 32.0, 273.15),
+            (100.0, 212.0, 373.15),
+            (-40.0, -40.0, 233.15),
+            (21.5, 70.7, 294.65),
+        ] {
+            for quantity in [
+                Quantity::Temperature,
+                Quantity::DewPoint,
+                Quantity::HeatIndex,
+            ] {
+                assert_converted(
+                    fahrenheit,
+                    quantity,
+                    celsius,
+                    degrees_fahrenheit,
+                    Unit::Fahrenheit,
+                );
+                assert_converted(kelvin, quantity, celsius, kelvins, Unit::Kelvin);
+            }
+        }
+    }
+
+    #[test]
+    fn converts_air_pressures() {
+        let kilopascal = units(TemperatureUnit::default(), PressureUnit::Kilopascal);
+        let inch_of_mercury = units(TemperatureUnit::default(), PressureUnit::InchOfMercury);
+        assert_converted(
+            kilopascal,
+            Quantity::Pressure,
+            1013.25,
+            101.325,
+            Unit::Kilopascal,
+        );
+        assert_converted(
+            inch_of_mercury,
+            Quantity::Pressure,
+            1013.25,
+            29.921,
+            Unit::InchOfMercury,
+        );
+    }
+
+    #[test]
+    fn keeps_the_recorded_unit_by_default_and_without_a_choice() {
+        let defaults = Units::default();
+        assert_converted(defaults, Quantity::Temperature, 21.5, 21.5, Unit::Celsius);
+        assert_converted(
+            defaults,
+            Quantity::Pressure,
+            1013.25,
+            1013.25,
+            Unit::Hectopascal,
+        );
+
+        let imperial = units(TemperatureUnit::Fahrenheit, PressureUnit::InchOfMercury);
+        assert_converted(imperial, Quantity::Humidity, 55.0, 55.0, Unit::Percent);
+        assert_converted(imperial, Quantity::Co2, 800.0, 800.0, Unit::PartsPerMillion);
+        // Vapour pressure deficits are not air pressures
+        assert_converted(imperial, Quantity::Vpd, 1.2, 1.2, Unit::Kilopascal);
+        assert_converted(imperial, Quantity::LeafVpd, 0.9, 0.9, Unit::Kilopascal);
+    }
+
+    #[test]
+    fn derives_from_celsius_before_converting() {
+        let mut measurement = Measurement::new()
+            .with(Quantity::Temperature, 25.0)
+            .with(Quantity::Humidity, 60.0);
+        climate::derive(&mut measurement, -2.0);
+        let mut cooler_leaves = Measurement::new()
+            .with(Quantity::Temperature, 25.0)
+            .with(Quantity::Humidity, 60.0);
+        climate::derive(&mut cooler_leaves, -3.6);
+
+        let fahrenheit = units(TemperatureUnit::Fahrenheit, PressureUnit::default());
+        let converted = |measurement: Measurement, quantity| {
+            let value = measurement
+                .values()
+                .find(|value| value.quantity == quantity);
+            fahrenheit.convert(value.unwrap()).value
+        };
+        // The leaf offset is a difference in °C, applied before converting
+        assert!(
+            converted(cooler_leaves, Quantity::LeafVpd) < converted(measurement, Quantity::LeafVpd)
+        );
+        assert_eq!(
+            converted(measurement, Quantity::LeafVpd),
+            measurement.get(Quantity::LeafVpd).unwrap()
+        );
+        // Differences between temperatures scale by 9/5 without the 32°F shift
+        let spread = measurement.get(Quantity::Temperature).unwrap()
+            - measurement.get(Quantity::DewPoint).unwrap();
+        let spread_fahrenheit = converted(measurement, Quantity::Temperature)
+            - converted(measurement, Quantity::DewPoint);
+        assert!((spread_fahrenheit - spread * 9.0 / 5.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn serializes_values_with_their_unit_symbol() {
+        let value = Units {
+            temperature: TemperatureUnit::Kelvin,
+            ..Units::default()
+        }
+        .convert(Value::new(Quantity::Temperature, 20.0));
+        assert_eq!(
+            serde_json::to_string(&value).unwrap(),
+            r#"{"quantity":"temperature","value":293.15,"unit":"K"}"#
+        );
+        let ppfd = Value::new(Quantity::Ppfd, 650.0);
+        let json = serde_json::to_string(&ppfd).unwrap();
+        assert_eq!(
+            json,
+            r#"{"quantity":"ppfd","value":650.0,"unit":"µmol/m²/s"}"#
+        );
+        assert_eq!(serde_json::from_str::<Value>(&json).unwrap(), ppfd);
+
+        for unit in [r#""°C""#, r#""inHg""#, r#""g/m³""#] {
+            let unit: Unit = serde_json::from_str(unit).unwrap();
+            assert_eq!(
+                serde_json::to_string(&unit).unwrap(),
+                format!(r#""{}""#, unit.symbol())
+            );
+        }
+    }
+}
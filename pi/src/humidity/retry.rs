@@ -0,0 +1,102 @@
+use core::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+/// How the delay between attempts grows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Backoff {
+    /// Always wait the initial delay.
+    Fixed,
+    /// Double the delay after every failed attempt.
+    Exponential,
+}
+
+/// When and how often a failed sensor read is attempted again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, clap::Args)]
+#[serde(default)]
+#[command(about = None, long_about = None, next_help_heading = "Retries")]
+pub struct RetryPolicy {
+    /// Attempts per reading, including the first one
+    #[arg(long = "retry-attempts", default_value_t = 3)]
+    pub max_attempts: u16,
+    /// How the delay between attempts grows
+    #[arg(long = "retry-backoff", value_enum, default_value_t = Backoff::Exponential)]
+    pub backoff: Backoff,
+    /// Delay before the first retry, in milliseconds
+    #[arg(long = "retry-delay-ms", default_value_t = 500)]
+    pub delay_ms: u64,
+    /// Longest delay between attempts, in milliseconds
+    #[arg(long = "retry-max-delay-ms", default_value_t = 5_000)]
+    pub max_delay_ms: u64,
+    /// Retry as soon as the backoff allows, attempts within the sensor's minimum sampling interval fail as too frequent
+    #[arg(long = "retry-ignore-min-interval", action = clap::ArgAction::SetFalse)]
+    pub respect_min_interval: bool,
+    /// Give up once a reading would take longer than this, in milliseconds
+    #[arg(long = "retry-budget-ms", default_value_t = 5_000)]
+    pub budget_ms: u64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 3,
+            backoff: Backoff::Exponential,
+            delay_ms: 500,
+            max_delay_ms: 5_000,
+            respect_min_interval: true,
+            budget_ms: 5_000,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// How long to wait after the given number of failed attempts.
+    pub fn backoff_delay(&self, failures: u16) -> Duration {
+        let delay_ms = match self.backoff {
+            Backoff::Fixed => self.delay_ms,
+            Backoff::Exponential => {
+                let doublings = u32::from(failures.saturating_sub(1)).min(63);
+                self.delay_ms.saturating_mul(1 << doublings)
+            }
+        };
+        Duration::from_millis(delay_ms.min(self.max_delay_ms))
+    }
+
+    /// The longest a reading may take before giving up.
+    pub fn budget(&self) -> Duration {
+        Duration::from_millis(self.budget_ms)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(backoff: Backoff) -> RetryPolicy {
+        RetryPolicy {
+            backoff,
+            delay_ms: 500,
+            max_delay_ms: 5_000,
+            ..RetryPolicy::default()
+        }
+    }
+
+    #[test]
+    fn waits_the_same_with_a_fixed_backoff() {
+        let policy = policy(Backoff::Fixed);
+        for failures in [1, 2, 5, u16::MAX] {
+            assert_eq!(policy.backoff_delay(failures), Duration::from_millis(500));
+        }
+    }
+
+    #[test]
+    fn doubles_an_exponential_backoff_up_to_the_limit() {
+        let policy = policy(Backoff::Exponential);
+        let delays: Vec<_> = (1..=6)
+            .map(|failures| policy.backoff_delay(failures).as_millis())
+            .collect();
+        assert_eq!(delays, [500, 1_000, 2_000, 4_000, 5_000, 5_000]);
+        assert_eq!(policy.backoff_delay(u16::MAX), Duration::from_millis(5_000));
+    }
+}
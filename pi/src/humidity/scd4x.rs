@@ -0,0 +1,389 @@
+//! Rust driver for the Sensirion SCD40 and SCD41 CO2 sensors, generic over `embedded-hal` I2C buses.
+//!
+//! The sensors measure CO2 photoacoustically along with temperature and humidity. They are
+//! run in periodic mode, measuring every 5 seconds, and reads fetch the latest result. Like
+//! the SHT sensors, they answer with 16 bit words that are each followed by a CRC-8.
+//!
+//! The automatic self-calibration (ASC) assumes the sensor sees fresh air at 400ppm at least
+//! once a week, so it should be turned off in rooms that are enriched with CO2 around the clock.
+//!
+#![deny(unsafe_code)]
+
+use core::time::Duration;
+
+use embedded_hal::blocking::delay::{DelayMs, DelayUs};
+
+use super::{
+    bus::{BusError, I2cBus},
+    device::{Device, Measurement},
+    pin::Clock,
+    quantity::Quantity,
+    sht::{crc8, word},
+    Error, Result,
+};
+
+/// The only address the sensors answer on
+pub const DEFAULT_ADDRESS: u8 = 0x62;
+
+/// Output range of the CO2 measurement in parts per million
+pub const CO2_RANGE: (f32, f32) = (0.0, 40_000.0);
+/// Operating range of the temperature measurement in degrees Celsius
+pub const TEMPERATURE_RANGE: (f32, f32) = (-10.0, 60.0);
+/// Operating range of the humidity measurement in percent
+pub const HUMIDITY_RANGE: (f32, f32) = (0.0, 100.0);
+
+/// How often the sensor measures in periodic mode
+pub const MEASUREMENT_INTERVAL: Duration = Duration::from_secs(5);
+
+pub(super) const START_PERIODIC: [u8; 2] = [0x21, 0xb1];
+pub(super) const STOP_PERIODIC: [u8; 2] = [0x3f, 0x86];
+pub(super) const READ_MEASUREMENT: [u8; 2] = [0xec, 0x05];
+pub(super) const GET_DATA_READY: [u8; 2] = [0xe4, 0xb8];
+pub(super) const SET_ASC: [u8; 2] = [0x24, 0x16];
+/// Bits of the data ready status that are all zero while no measurement is waiting
+pub(super) const DATA_READY_MASK: u16 = 0x07ff;
+
+/// How long the sensor takes to stop measuring (in milliseconds)
+const STOP_MS: u16 = 500;
+/// How long the sensor takes to execute the other commands (in milliseconds)
+const COMMAND_MS: u16 = 1;
+/// How often the data ready status is polled once the interval has passed
+const READY_POLLS: u16 = 10;
+/// Time between polls of the data ready status (in milliseconds)
+const READY_POLL_MS: u16 = 100;
+
+/// An SCD40 or SCD41 device.
+pub struct Scd4x<B> {
+    /// The bus the sensor is connected to.
+    bus: B,
+    /// The address of the sensor on the bus.
+    address: u8,
+    /// Whether the self-calibration is turned on or off when measurements are started.
+    self_calibration: Option<bool>,
+    /// Whether periodic measurements were started and are believed to still run.
+    measuring: bool,
+    /// When measurements were last started or read, `None` until they are first started.
+    last_us: Option<u64>,
+}
+
+impl<B> Scd4x<B> {
+    /// Creates a device at `address` on the given bus, leaving its self-calibration as it is.
+    pub fn new(bus: B, address: u8) -> Self {
+        Scd4x {
+            bus,
+            address,
+            self_calibration: None,
+            measuring: false,
+            last_us: None,
+        }
+    }
+
+    /// Turns the automatic self-calibration on or off when measurements are started.
+    pub fn with_self_calibration(mut self, enabled: bool) -> Self {
+        self.self_calibration = Some(enabled);
+        self
+    }
+
+    /// How long until the sensor has a new measurement.
+    pub fn ready_in<C: Clock>(&self, clock: &C) -> Duration {
+        match self.last_us {
+            Some(last_us) => {
+                MEASUREMENT_INTERVAL.saturating_sub(Duration::from_micros(clock.now_us() - last_us))
+            }
+            None => Duration::ZERO,
+        }
+    }
+}
+
+impl<B> Scd4x<B>
+where
+    B: I2cBus,
+    Error: From<BusError<B>>,
+{
+    /// Fetches the latest measurement, starting periodic measurements on first use.
+    pub fn perform_measurement<D>(&mut self, delay: &mut D) -> Result<Measurement>
+    where
+        D: DelayMs<u16> + Clock,
+    {
+        let result = self.read_measurement(delay);
+        if let Err(Error::NotReady) = result {
+            // The sensor may have lost power and stopped measuring, other errors leave it
+            // measuring and the next attempt reads the measurement it is working on
+            self.measuring = false;
+        }
+        result
+    }
+
+    fn read_measurement<D>(&mut self, delay: &mut D) -> Result<Measurement>
+    where
+        D: DelayMs<u16> + Clock,
+    {
+        if !self.measuring {
+            self.start(delay)?;
+            // The first measurement takes a full interval
+            delay.delay_ms(MEASUREMENT_INTERVAL.as_millis() as u16);
+        }
+        self.wait_for_data(delay)?;
+
+        self.bus.write(self.address, &READ_MEASUREMENT)?;
+        delay.delay_ms(COMMAND_MS);
+        let mut data = [0; 9];
+        self.bus.read(self.address, &mut data)?;
+        let co2 = word(&data[0..3])?;
+        let temperature = word(&data[3..6])?;
+        let humidity = word(&data[6..9])?;
+        self.last_us = Some(delay.now_us());
+
+        Ok(Measurement::new()
+            .with(
+                Quantity::Temperature,
+                -45.0 + 175.0 * f32::from(temperature) / 65536.0,
+            )
+            .with(Quantity::Humidity, 100.0 * f32::from(humidity) / 65536.0)
+            .with(Quantity::Co2, f32::from(co2)))
+    }
+
+    /// Applies the settings and starts periodic measurements.
+    fn start<D>(&mut self, delay: &mut D) -> Result<()>
+    where
+        D: DelayMs<u16> + Clock,
+    {
+        // A previous run may have left it measuring, which rejects the other commands
+        self.bus.write(self.address, &STOP_PERIODIC)?;
+        delay.delay_ms(STOP_MS);
+
+        if let Some(enabled) = self.self_calibration {
+            let [command_msb, command_lsb] = SET_ASC;
+            let argument = u16::from(enabled).to_be_bytes();
+            let [msb, lsb] = argument;
+            self.bus.write(
+                self.address,
+                &[command_msb, command_lsb, msb, lsb, crc8(&argument)],
+            )?;
+            delay.delay_ms(COMMAND_MS);
+        }
+
+        self.bus.write(self.address, &START_PERIODIC)?;
+        self.measuring = true;
+        self.last_us = Some(delay.now_us());
+        Ok(())
+    }
+
+    fn wait_for_data<D: DelayMs<u16>>(&mut self, delay: &mut D) -> Result<()> {
+        for _ in 0..READY_POLLS {
+            self.bus.write(self.address, &GET_DATA_READY)?;
+            delay.delay_ms(COMMAND_MS);
+            let mut status = [0; 3];
+            self.bus.read(self.address, &mut status)?;
+            if word(&status)? & DATA_READY_MASK != 0 {
+                return Ok(());
+            }
+            delay.delay_ms(READY_POLL_MS);
+        }
+        Err(Error::NotReady)
+    }
+}
+
+impl<B> Device for Scd4x<B>
+where
+    B: I2cBus,
+    Error: From<BusError<B>>,
+{
+    fn perform_measurement<D: DelayUs<u16> + DelayMs<u16> + Clock>(
+        &mut self,
+        delay: &mut D,
+    ) -> Result<Measurement> {
+        self.perform_measurement(delay)
+    }
+
+    fn ready_in<C: Clock>(&self, clock: &C) -> Duration {
+        self.ready_in(clock)
+    }
+}
+
+#[cfg(all(test, feature = "sim"))]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    };
+
+    use super::*;
+    use crate::humidity::{
+        sim::SimClock,
+        sim_i2c::{SimBus, SimDevice, SimScd4x, Spy},
+    };
+
+    fn climate() -> Measurement {
+        Measurement::new()
+            .with(Quantity::Temperature, 24.0)
+            .with(Quantity::Humidity, 55.0)
+            .with(Quantity::Co2, 1150.0)
+    }
+
+    fn sensor() -> SimScd4x<fn() -> Measurement> {
+        SimScd4x::new(climate)
+    }
+
+    /// A sensor that never starts measuring, e.g. because it is still busy.
+    struct Stalled(SimScd4x<fn() -> Measurement>);
+
+    impl SimDevice for Stalled {
+        fn write(&mut self, bytes: &[u8]) {
+            if bytes != START_PERIODIC {
+                self.0.write(bytes);
+            }
+        }
+
+        fn read(&mut self, buffer: &mut [u8]) {
+            self.0.read(buffer);
+        }
+    }
+
+    /// Faults injected into a working sensor from the test.
+    #[derive(Clone, Default)]
+    struct Faults {
+        /// Flips the CRC of the first word of the next response.
+        corrupt: Arc<AtomicBool>,
+        /// Loses power, which stops periodic measurements.
+        power_loss: Arc<AtomicBool>,
+    }
+
+    struct Faulty(SimScd4x<fn() -> Measurement>, Faults);
+
+    impl SimDevice for Faulty {
+        fn write(&mut self, bytes: &[u8]) {
+            if self.1.power_loss.swap(false, Ordering::Relaxed) {
+                self.0.write(&STOP_PERIODIC);
+            }
+            self.0.write(bytes);
+        }
+
+        fn read(&mut self, buffer: &mut [u8]) {
+            self.0.read(buffer);
+            if self.1.corrupt.swap(false, Ordering::Relaxed) {
+                buffer[2] = !buffer[2];
+            }
+        }
+    }
+
+    /// The commands written to a sensor.
+    type Writes = Arc<Mutex<Vec<Vec<u8>>>>;
+
+    /// A sensor that has been measuring since its first read, with the log of its writes.
+    fn measuring(clock: &SimClock) -> (Scd4x<SimBus>, Faults, Writes) {
+        let faults = Faults::default();
+        let (spy, writes) = Spy::new(Faulty(sensor(), faults.clone()));
+        let mut scd4x = Scd4x::new(
+            SimBus::new().with_device(DEFAULT_ADDRESS, spy),
+            DEFAULT_ADDRESS,
+        );
+        scd4x.perform_measurement(&mut clock.delay()).unwrap();
+        (scd4x, faults, writes)
+    }
+
+    fn starts(writes: &[Vec<u8>]) -> usize {
+        writes
+            .iter()
+            .filter(|write| **write == START_PERIODIC)
+            .count()
+    }
+
+    #[test]
+    fn reads_a_measurement() {
+        let bus = SimBus::new().with_device(DEFAULT_ADDRESS, sensor());
+        let measurement = Scd4x::new(bus, DEFAULT_ADDRESS)
+            .perform_measurement(&mut SimClock::new().delay())
+            .unwrap();
+
+        assert_eq!(measurement.get(Quantity::Co2), Some(1150.0));
+        let temperature = measurement.get(Quantity::Temperature).unwrap();
+        let humidity = measurement.get(Quantity::Humidity).unwrap();
+        assert!((temperature - 24.0).abs() < 0.01, "{temperature}");
+        assert!((humidity - 55.0).abs() < 0.01, "{humidity}");
+    }
+
+    #[test]
+    fn gives_up_when_no_data_is_ready() {
+        let bus = SimBus::new().with_device(DEFAULT_ADDRESS, Stalled(sensor()));
+        let result =
+            Scd4x::new(bus, DEFAULT_ADDRESS).perform_measurement(&mut SimClock::new().delay());
+        assert!(matches!(result, Err(Error::NotReady)), "{result:?}");
+    }
+
+    #[test]
+    fn rejects_a_word_with_a_bad_crc() {
+        let (spy, _) = Spy::new(sensor());
+        // Flips the CRC of the first word of every response
+        let bus = SimBus::new().with_device(DEFAULT_ADDRESS, spy.corrupting(2));
+        let result =
+            Scd4x::new(bus, DEFAULT_ADDRESS).perform_measurement(&mut SimClock::new().delay());
+        assert!(
+            matches!(result, Err(Error::ChecksumMismatch { .. })),
+            "{result:?}"
+        );
+    }
+
+    #[test]
+    fn sets_the_self_calibration_before_starting() {
+        for (enabled, command) in [
+            (false, [0x24, 0x16, 0x00, 0x00, 0x81]),
+            (true, [0x24, 0x16, 0x00, 0x01, 0xb0]),
+        ] {
+            let (spy, writes) = Spy::new(sensor());
+            let bus = SimBus::new().with_device(DEFAULT_ADDRESS, spy);
+            Scd4x::new(bus, DEFAULT_ADDRESS)
+                .with_self_calibration(enabled)
+                .perform_measurement(&mut SimClock::new().delay())
+                .unwrap();
+
+            assert_eq!(
+                writes.lock().unwrap()[..3],
+                [
+                    STOP_PERIODIC.to_vec(),
+                    command.to_vec(),
+                    START_PERIODIC.to_vec()
+                ],
+                "{enabled}"
+            );
+        }
+    }
+
+    #[test]
+    fn keeps_measuring_after_a_transient_error() {
+        let clock = SimClock::new();
+        let (mut scd4x, faults, writes) = measuring(&clock);
+        clock.advance_us(1_000_000);
+
+        faults.corrupt.store(true, Ordering::Relaxed);
+        let result = scd4x.perform_measurement(&mut clock.delay());
+        assert!(
+            matches!(result, Err(Error::ChecksumMismatch { .. })),
+            "{result:?}"
+        );
+        // Still waiting for the measurement after the last one read
+        let ready_in = scd4x.ready_in(&clock.delay());
+        assert!(ready_in < MEASUREMENT_INTERVAL, "{ready_in:?}");
+        assert!(ready_in > MEASUREMENT_INTERVAL / 2, "{ready_in:?}");
+
+        clock.advance_us(ready_in.as_micros() as u64);
+        let started_us = clock.now_us();
+        scd4x.perform_measurement(&mut clock.delay()).unwrap();
+        assert_eq!(starts(&writes.lock().unwrap()), 1);
+        assert!(clock.now_us() - started_us < 1_000_000);
+    }
+
+    #[test]
+    fn restarts_a_sensor_that_stopped_measuring() {
+        let clock = SimClock::new();
+        let (mut scd4x, faults, writes) = measuring(&clock);
+        clock.advance_us(MEASUREMENT_INTERVAL.as_micros() as u64);
+
+        faults.power_loss.store(true, Ordering::Relaxed);
+        let result = scd4x.perform_measurement(&mut clock.delay());
+        assert!(matches!(result, Err(Error::NotReady)), "{result:?}");
+
+        scd4x.perform_measurement(&mut clock.delay()).unwrap();
+        assert_eq!(starts(&writes.lock().unwrap()), 2);
+    }
+}
@@ -0,0 +1,409 @@
+//! Rust driver for the Sensirion SHT3x and SHT4x temperature and humidity sensors, generic over `embedded-hal` I2C buses.
+//!
+//! Both families answer with two 16 bit words, each followed by a CRC-8, but use different
+//! commands. The SHT3x can also measure periodically on its own, in which case reads fetch
+//! the latest result. Both have a heater to drive off condensation, which skews the
+//! temperature while it is on.
+//!
+#![deny(unsafe_code)]
+
+use core::time::Duration;
+
+use embedded_hal::blocking::delay::{DelayMs, DelayUs};
+use serde::{Deserialize, Serialize};
+
+use super::{
+    bus::{BusError, I2cBus},
+    device::{Device, Measurement},
+    pin::Clock,
+    quantity::Quantity,
+    Error, Result,
+};
+
+/// The address with ADDR tied to ground (SHT3x) or of the common SHT4x parts
+pub const DEFAULT_ADDRESS: u8 = 0x44;
+
+/// Operating range of the temperature measurement in degrees Celsius
+pub const TEMPERATURE_RANGE: (f32, f32) = (-40.0, 125.0);
+/// Operating range of the humidity measurement in percent
+pub const HUMIDITY_RANGE: (f32, f32) = (0.0, 100.0);
+
+/// How long the heater is on for each pulse
+pub const HEATER_PULSE: Duration = Duration::from_secs(1);
+
+pub(super) const SHT3X_FETCH: [u8; 2] = [0xe0, 0x00];
+pub(super) const SHT3X_BREAK: [u8; 2] = [0x30, 0x93];
+pub(super) const SHT3X_HEATER_ON: [u8; 2] = [0x30, 0x6d];
+pub(super) const SHT3X_HEATER_OFF: [u8; 2] = [0x30, 0x66];
+/// Heats with 200mW for a second, then measures with high precision
+pub(super) const SHT4X_HEAT_200MW_1S: u8 = 0x39;
+/// How long the SHT4x takes to heat and measure (in milliseconds)
+const SHT4X_HEAT_MS: u16 = 1_100;
+
+/// Which family of the sensor is connected.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Family {
+    /// SHT30, SHT31 and SHT35.
+    Sht3x,
+    /// SHT40, SHT41 and SHT45.
+    Sht4x,
+}
+
+/// How measurements are started.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Mode {
+    /// Every read starts a measurement and waits for it.
+    #[default]
+    SingleShot,
+    /// The sensor measures once a second and reads fetch the latest result, SHT3x only.
+    Periodic,
+}
+
+/// Trades noise for measurement time, called repeatability on the SHT3x.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Precision {
+    /// Lowest noise, slowest measurement.
+    #[default]
+    High,
+    /// Balance between noise and measurement time.
+    Medium,
+    /// Fastest measurement.
+    Low,
+}
+
+/// How the sensor measures.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    /// How measurements are started.
+    pub mode: Mode,
+    /// The precision of each measurement.
+    pub precision: Precision,
+}
+
+impl Family {
+    /// The command starting a single measurement.
+    pub(super) fn single_shot(self, precision: Precision) -> &'static [u8] {
+        match (self, precision) {
+            // Without clock stretching, the sensor doesn't acknowledge reads until it is done
+            (Family::Sht3x, Precision::High) => &[0x24, 0x00],
+            (Family::Sht3x, Precision::Medium) => &[0x24, 0x0b],
+            (Family::Sht3x, Precision::Low) => &[0x24, 0x16],
+            (Family::Sht4x, Precision::High) => &[0xfd],
+            (Family::Sht4x, Precision::Medium) => &[0xf6],
+            (Family::Sht4x, Precision::Low) => &[0xe0],
+        }
+    }
+
+    /// Longest time a measurement takes (in milliseconds).
+    fn measurement_ms(self, precision: Precision) -> u16 {
+        match (self, precision) {
+            (Family::Sht3x, Precision::High) => 16,
+            (Family::Sht3x, Precision::Medium) => 7,
+            (Family::Sht3x, Precision::Low) => 5,
+            (Family::Sht4x, Precision::High) => 9,
+            (Family::Sht4x, Precision::Medium) => 5,
+            (Family::Sht4x, Precision::Low) => 2,
+        }
+    }
+
+    /// Converts the raw humidity word, the SHT4x can report values slightly outside 0-100%.
+    fn humidity(self, raw: u16) -> f32 {
+        let fraction = f32::from(raw) / 65535.0;
+        match self {
+            Family::Sht3x => 100.0 * fraction,
+            Family::Sht4x => (-6.0 + 125.0 * fraction).clamp(0.0, 100.0),
+        }
+    }
+}
+
+/// The command starting periodic measurements at one per second on an SHT3x.
+pub(super) fn sht3x_periodic(precision: Precision) -> [u8; 2] {
+    match precision {
+        Precision::High => [0x21, 0x30],
+        Precision::Medium => [0x21, 0x26],
+        Precision::Low => [0x21, 0x2d],
+    }
+}
+
+/// The CRC-8 Sensirion appends to every word: polynomial 0x31, initialised to 0xff.
+pub fn crc8(bytes: &[u8]) -> u8 {
+    let mut crc = 0xff;
+    for byte in bytes {
+        crc ^= byte;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x31
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+/// An SHT3x or SHT4x device.
+pub struct Sht<B> {
+    /// The bus the sensor is connected to.
+    bus: B,
+    /// The address of the sensor on the bus.
+    address: u8,
+    family: Family,
+    settings: Settings,
+    /// Whether periodic measurements were started.
+    running: bool,
+}
+
+impl<B> Sht<B> {
+    /// Creates a device of the given family at `address` on the bus, measuring single shots.
+    pub fn new(bus: B, address: u8, family: Family) -> Self {
+        Sht {
+            bus,
+            address,
+            family,
+            settings: Settings::default(),
+            running: false,
+        }
+    }
+
+    /// Sets how the sensor measures.
+    pub fn with_settings(mut self, settings: Settings) -> Self {
+        self.settings = settings;
+        self
+    }
+}
+
+impl<B> Sht<B>
+where
+    B: I2cBus,
+    Error: From<BusError<B>>,
+{
+    /// Measures, or fetches the latest periodic measurement.
+    pub fn perform_measurement<D>(&mut self, delay: &mut D) -> Result<Measurement>
+    where
+        D: DelayUs<u16> + DelayMs<u16>,
+    {
+        let Settings { mode, precision } = self.settings;
+        match (self.family, mode) {
+            (Family::Sht3x, Mode::Periodic) => {
+                if !self.running {
+                    self.bus.write(self.address, &sht3x_periodic(precision))?;
+                    self.running = true;
+                    delay.delay_ms(self.family.measurement_ms(precision));
+                }
+                self.bus.write(self.address, &SHT3X_FETCH)?;
+            }
+            (Family::Sht4x, Mode::Periodic) => {
+                return Err(Error::Unsupported("periodic measurements"))
+            }
+            (family, Mode::SingleShot) => {
+                self.bus
+                    .write(self.address, family.single_shot(precision))?;
+                delay.delay_ms(family.measurement_ms(precision));
+            }
+        }
+        self.read_measurement()
+    }
+
+    /// Heats the sensor for [`HEATER_PULSE`] to evaporate condensation.
+    pub fn heat<D: DelayMs<u16>>(&mut self, delay: &mut D) -> Result<()> {
+        match self.family {
+            Family::Sht3x => {
+                // The heater can only be switched while idle
+                if self.running {
+                    self.bus.write(self.address, &SHT3X_BREAK)?;
+                    self.running = false;
+                    delay.delay_ms(1);
+                }
+                self.bus.write(self.address, &SHT3X_HEATER_ON)?;
+                delay.delay_ms(HEATER_PULSE.as_millis() as u16);
+                self.bus.write(self.address, &SHT3X_HEATER_OFF)?;
+            }
+            Family::Sht4x => {
+                self.bus.write(self.address, &[SHT4X_HEAT_200MW_1S])?;
+                delay.delay_ms(SHT4X_HEAT_MS);
+                // The measurement taken at the end of the pulse is skewed by the heat
+                self.read_measurement()?;
+            }
+        }
+        Ok(())
+    }
+
+    fn read_measurement(&mut self) -> Result<Measurement> {
+        let mut data = [0; 6];
+        self.bus.read(self.address, &mut data)?;
+        let temperature = word(&data[0..3])?;
+        let humidity = word(&data[3..6])?;
+
+        Ok(Measurement::new()
+            .with(
+                Quantity::Temperature,
+                -45.0 + 175.0 * f32::from(temperature) / 65535.0,
+            )
+            .with(Quantity::Humidity, self.family.humidity(humidity)))
+    }
+}
+
+/// Checks the CRC following a word and returns the word.
+pub(super) fn word(bytes: &[u8]) -> Result<u16> {
+    let expected = crc8(&bytes[..2]);
+    if expected != bytes[2] {
+        return Err(Error::ChecksumMismatch {
+            expected,
+            actual: bytes[2],
+        });
+    }
+    Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+}
+
+impl<B> Device for Sht<B>
+where
+    B: I2cBus,
+    Error: From<BusError<B>>,
+{
+    fn perform_measurement<D: DelayUs<u16> + DelayMs<u16> + Clock>(
+        &mut self,
+        delay: &mut D,
+    ) -> Result<Measurement> {
+        self.perform_measurement(delay)
+    }
+}
+
+#[cfg(all(test, feature = "sim"))]
+mod tests {
+    use super::*;
+    use crate::humidity::{
+        sim::SimClock,
+        sim_i2c::{SimBus, SimSht, Spy},
+    };
+
+    const PRECISIONS: [Precision; 3] = [Precision::High, Precision::Medium, Precision::Low];
+
+    fn climate() -> Measurement {
+        Measurement::new()
+            .with(Quantity::Temperature, 23.4)
+            .with(Quantity::Humidity, 56.7)
+    }
+
+    fn sensor(family: Family) -> SimSht<fn() -> Measurement> {
+        SimSht::new(family, climate)
+    }
+
+    /// Asserts that a measurement matches [`climate`] up to the resolution of the raw words.
+    fn assert_climate(measurement: Measurement) {
+        let temperature = measurement.get(Quantity::Temperature).unwrap();
+        let humidity = measurement.get(Quantity::Humidity).unwrap();
+        assert!((temperature - 23.4).abs() < 0.01, "{temperature}");
+        assert!((humidity - 56.7).abs() < 0.01, "{humidity}");
+    }
+
+    #[test]
+    fn crc8_matches_the_datasheet() {
+        assert_eq!(crc8(&[0xbe, 0xef]), 0x92);
+    }
+
+    #[test]
+    fn measures_single_shots_at_every_precision() {
+        let mut delay = SimClock::new().delay();
+        for family in [Family::Sht3x, Family::Sht4x] {
+            for precision in PRECISIONS {
+                let (spy, writes) = Spy::new(sensor(family));
+                let bus = SimBus::new().with_device(DEFAULT_ADDRESS, spy);
+                let mut sht = Sht::new(bus, DEFAULT_ADDRESS, family).with_settings(Settings {
+                    mode: Mode::SingleShot,
+                    precision,
+                });
+
+                assert_climate(sht.perform_measurement(&mut delay).unwrap());
+                assert_eq!(
+                    *writes.lock().unwrap(),
+                    [family.single_shot(precision).to_vec()],
+                    "{family:?} {precision:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn starts_periodic_measurements_once_on_sht3x() {
+        let mut delay = SimClock::new().delay();
+        let (spy, writes) = Spy::new(sensor(Family::Sht3x));
+        let bus = SimBus::new().with_device(DEFAULT_ADDRESS, spy);
+        let mut sht = Sht::new(bus, DEFAULT_ADDRESS, Family::Sht3x).with_settings(Settings {
+            mode: Mode::Periodic,
+            precision: Precision::Medium,
+        });
+
+        assert_climate(sht.perform_measurement(&mut delay).unwrap());
+        assert_climate(sht.perform_measurement(&mut delay).unwrap());
+        assert_eq!(
+            *writes.lock().unwrap(),
+            [
+                sht3x_periodic(Precision::Medium).to_vec(),
+                SHT3X_FETCH.to_vec(),
+                SHT3X_FETCH.to_vec(),
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_periodic_measurements_on_sht4x() {
+        let mut delay = SimClock::new().delay();
+        let bus = SimBus::new().with_device(DEFAULT_ADDRESS, sensor(Family::Sht4x));
+        let mut sht = Sht::new(bus, DEFAULT_ADDRESS, Family::Sht4x).with_settings(Settings {
+            mode: Mode::Periodic,
+            precision: Precision::High,
+        });
+
+        let result = sht.perform_measurement(&mut delay);
+        assert!(matches!(result, Err(Error::Unsupported(_))), "{result:?}");
+    }
+
+    #[test]
+    fn rejects_a_word_with_a_bad_crc() {
+        let mut delay = SimClock::new().delay();
+        let (spy, _) = Spy::new(sensor(Family::Sht3x));
+        // Flips the CRC of the humidity word
+        let bus = SimBus::new().with_device(DEFAULT_ADDRESS, spy.corrupting(5));
+        let mut sht = Sht::new(bus, DEFAULT_ADDRESS, Family::Sht3x);
+
+        let result = sht.perform_measurement(&mut delay);
+        assert!(
+            matches!(result, Err(Error::ChecksumMismatch { expected, actual }) if expected == !actual),
+            "{result:?}"
+        );
+    }
+
+    #[test]
+    fn pulses_the_heater() {
+        let mut delay = SimClock::new().delay();
+        let (spy, writes) = Spy::new(sensor(Family::Sht3x));
+        let bus = SimBus::new().with_device(DEFAULT_ADDRESS, spy);
+        let mut sht = Sht::new(bus, DEFAULT_ADDRESS, Family::Sht3x).with_settings(Settings {
+            mode: Mode::Periodic,
+            precision: Precision::High,
+        });
+        sht.perform_measurement(&mut delay).unwrap();
+        sht.heat(&mut delay).unwrap();
+        // Periodic measurements are stopped first and restarted by the next read
+        sht.perform_measurement(&mut delay).unwrap();
+        assert_eq!(
+            writes.lock().unwrap()[2..],
+            [
+                SHT3X_BREAK.to_vec(),
+                SHT3X_HEATER_ON.to_vec(),
+                SHT3X_HEATER_OFF.to_vec(),
+                sht3x_periodic(Precision::High).to_vec(),
+                SHT3X_FETCH.to_vec(),
+            ]
+        );
+
+        let (spy, writes) = Spy::new(sensor(Family::Sht4x));
+        let bus = SimBus::new().with_device(DEFAULT_ADDRESS, spy);
+        let mut sht = Sht::new(bus, DEFAULT_ADDRESS, Family::Sht4x);
+        sht.heat(&mut delay).unwrap();
+        assert_eq!(*writes.lock().unwrap(), [vec![SHT4X_HEAT_200MW_1S]]);
+    }
+}
@@ -0,0 +1,418 @@
+//! Software stand-in for a DHT sensor's data line.
+//!
+//! [`SimLine`] plays back the waveform a real sensor produces in response to a start
+//! signal, and [`SimDelay`] advances the shared virtual [`SimClock`] instead of
+//! sleeping, so a full read completes instantly and always takes the same path.
+//!
+#![deny(unsafe_code)]
+
+use core::convert::Infallible;
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use embedded_hal::{
+    blocking::delay::{DelayMs, DelayUs},
+    digital::v2::{InputPin, OutputPin},
+};
+
+use super::{
+    device::Measurement,
+    pin::{Clock, Edge, EdgeCapture, IoMode},
+    quantity::Quantity,
+};
+
+/// How long the sensor waits after the host releases the line before answering (in microseconds)
+const RESPONSE_DELAY_US: u64 = 20;
+/// Length of each half of the sensor's handshake response (in microseconds)
+const HANDSHAKE_US: u32 = 80;
+/// Length of the low pulse preceding every data bit (in microseconds)
+const BIT_START_US: u32 = 50;
+/// Length of the high pulse encoding a `0` bit (in microseconds)
+const ZERO_US: u32 = 27;
+/// Length of the high pulse encoding a `1` bit (in microseconds)
+const ONE_US: u32 = 70;
+/// How long sampling the line takes (in microseconds)
+const SAMPLE_US: u64 = 1;
+
+/// A virtual microsecond clock shared between a [`SimLine`] and a [`SimDelay`].
+#[derive(Clone, Debug, Default)]
+pub struct SimClock(Arc<AtomicU64>);
+
+impl SimClock {
+    /// Creates a new clock starting at zero.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The current virtual time in microseconds.
+    pub fn now_us(&self) -> u64 {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    /// Moves the clock forward by `us` microseconds.
+    pub fn advance_us(&self, us: u64) {
+        self.0.fetch_add(us, Ordering::SeqCst);
+    }
+
+    /// Creates a delay that advances this clock.
+    pub fn delay(&self) -> SimDelay {
+        SimDelay {
+            clock: self.clone(),
+        }
+    }
+}
+
+/// A delay provider that advances a [`SimClock`] rather than sleeping.
+#[derive(Clone, Debug)]
+pub struct SimDelay {
+    clock: SimClock,
+}
+
+impl Clock for SimDelay {
+    fn now_us(&self) -> u64 {
+        self.clock.now_us()
+    }
+}
+
+impl DelayUs<u16> for SimDelay {
+    fn delay_us(&mut self, us: u16) {
+        self.clock.advance_us(u64::from(us));
+    }
+}
+
+impl DelayMs<u16> for SimDelay {
+    fn delay_ms(&mut self, ms: u16) {
+        self.clock.advance_us(u64::from(ms) * 1_000);
+    }
+}
+
+/// A fault injected into the simulated response.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Fault {
+    /// The sensor never answers the start signal and the pull-up keeps the line high.
+    NoResponse,
+    /// The line is held low, e.g. by a short or a missing pull-up.
+    StuckLow,
+    /// The bit at the given index (0-39) is flipped while in transit.
+    CorruptBit(usize),
+    /// The edge at the given index of the response is missed, merging two pulses.
+    DroppedEdge(usize),
+    /// The sensor stops transmitting after the given number of bits.
+    Truncated(usize),
+}
+
+/// The waveform a sensor drives onto the data line after a start signal.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Response {
+    /// Successive `(level, duration in microseconds)` pulses, after which the line idles high.
+    pulses: Vec<(bool, u32)>,
+    /// Whether the line is held low regardless of the pulses.
+    stuck_low: bool,
+}
+
+impl Response {
+    /// Creates the response for a raw 5-byte frame (4 data bytes followed by the checksum).
+    pub fn from_bytes(data: [u8; 5]) -> Self {
+        let mut pulses = vec![(false, HANDSHAKE_US), (true, HANDSHAKE_US)];
+        for i in 0..40 {
+            let bit = data[i / 8] & (0x80 >> (i % 8)) != 0;
+            pulses.push((false, BIT_START_US));
+            pulses.push((true, if bit { ONE_US } else { ZERO_US }));
+        }
+        pulses.push((false, BIT_START_US));
+
+        Response {
+            pulses,
+            stuck_low: false,
+        }
+    }
+
+    /// Creates the response a DHT22 sends for the given measurement.
+    pub fn dht22(measurement: Measurement) -> Self {
+        let humidity =
+            (measurement.get(Quantity::Humidity).unwrap_or_default() * 10.0).round() as u16;
+        let temperature = measurement.get(Quantity::Temperature).unwrap_or_default();
+        let magnitude = (temperature.abs() * 10.0).round() as u16 & 0x7fff;
+        let sign = if temperature < 0.0 { 0x8000 } else { 0 };
+        let [rh_h, rh_l] = humidity.to_be_bytes();
+        let [temp_h, temp_l] = (magnitude | sign).to_be_bytes();
+        Self::from_bytes(with_checksum([rh_h, rh_l, temp_h, temp_l]))
+    }
+
+    /// Creates the response a DHT11 sends for the given measurement.
+    pub fn dht11(measurement: Measurement) -> Self {
+        let humidity =
+            (measurement.get(Quantity::Humidity).unwrap_or_default() * 10.0).round() as u16;
+        let temperature = measurement.get(Quantity::Temperature).unwrap_or_default();
+        let magnitude = (temperature.abs() * 10.0).round() as u16;
+        let sign = if temperature < 0.0 { 0x80 } else { 0 };
+        Self::from_bytes(with_checksum([
+            (humidity / 10) as u8,
+            (humidity % 10) as u8,
+            (magnitude / 10) as u8 & 0x7f | sign,
+            (magnitude % 10) as u8,
+        ]))
+    }
+
+    /// Applies a fault to the response.
+    pub fn with_fault(mut self, fault: Fault) -> Self {
+        match fault {
+            Fault::NoResponse => self.pulses.clear(),
+            Fault::StuckLow => self.stuck_low = true,
+            Fault::CorruptBit(bit) => {
+                // Skip the handshake pulses, then each bit is a low/high pair.
+                if let Some((_, width)) = self.pulses.get_mut(2 + bit * 2 + 1) {
+                    *width = if *width == ONE_US { ZERO_US } else { ONE_US };
+                }
+            }
+            Fault::DroppedEdge(edge) => {
+                if edge + 1 < self.pulses.len() {
+                    let (_, width) = self.pulses.remove(edge + 1);
+                    self.pulses[edge].1 += width;
+                }
+            }
+            Fault::Truncated(bits) => self.pulses.truncate(2 + bits * 2),
+        }
+        self
+    }
+
+    /// The level of the line `elapsed_us` microseconds after the response started.
+    fn level_at(&self, elapsed_us: u64) -> bool {
+        if self.stuck_low {
+            return false;
+        }
+
+        let mut start = 0;
+        for &(level, width) in &self.pulses {
+            let end = start + u64::from(width);
+            if elapsed_us < end {
+                return level;
+            }
+            start = end;
+        }
+
+        // Released line is pulled up once the sensor is done
+        true
+    }
+
+    /// The edges of the waveform, relative to the start of the response.
+    fn edges(&self) -> Vec<Edge> {
+        if self.stuck_low {
+            return vec![Edge {
+                time_us: 0,
+                rising: false,
+            }];
+        }
+
+        let mut edges = Vec::new();
+        let mut level = true;
+        let mut start = 0;
+        for &(pulse_level, width) in &self.pulses {
+            if pulse_level != level {
+                edges.push(Edge {
+                    time_us: start,
+                    rising: pulse_level,
+                });
+                level = pulse_level;
+            }
+            start += u64::from(width);
+        }
+        if !level {
+            edges.push(Edge {
+                time_us: start,
+                rising: true,
+            });
+        }
+        edges
+    }
+}
+
+/// Appends the DHT checksum to 4 data bytes.
+pub fn with_checksum(bytes: [u8; 4]) -> [u8; 5] {
+    let crc = bytes.iter().fold(0u8, |crc, byte| crc.wrapping_add(*byte));
+    [bytes[0], bytes[1], bytes[2], bytes[3], crc]
+}
+
+/// Produces the response to each start signal seen by a [`SimLine`].
+pub trait Responder: Send {
+    /// Returns the waveform to play back, or `None` to leave the line idle.
+    fn respond(&mut self) -> Option<Response>;
+}
+
+/// Replies with the queued responses in order, repeating the last one once the queue is exhausted.
+impl Responder for Vec<Response> {
+    fn respond(&mut self) -> Option<Response> {
+        if self.len() > 1 {
+            Some(self.remove(0))
+        } else {
+            self.first().cloned()
+        }
+    }
+}
+
+/// A simulated DHT data line that answers start signals with a [`Response`].
+pub struct SimLine {
+    clock: SimClock,
+    responder: Box<dyn Responder>,
+    /// Minimum length of the host's start pulse for the sensor to respond (in microseconds)
+    min_start_us: u64,
+    output: bool,
+    driven_high: bool,
+    low_since: Option<u64>,
+    response: Option<(u64, Response)>,
+    /// When edge capture was started.
+    capture_since: Option<u64>,
+}
+
+impl SimLine {
+    /// Creates a line whose responses are produced by `responder`, timed by `clock`.
+    ///
+    /// A `Vec<Response>` can be used to script a fixed sequence of responses.
+    pub fn new<R: Responder + 'static>(clock: SimClock, responder: R) -> Self {
+        SimLine {
+            clock,
+            responder: Box::new(responder),
+            min_start_us: 1_000,
+            output: false,
+            driven_high: true,
+            low_since: None,
+            response: None,
+            capture_since: None,
+        }
+    }
+
+    /// Sets how long the start pulse has to be for the sensor to respond, e.g. 18ms for a DHT11.
+    pub fn with_min_start_us(mut self, min_start_us: u64) -> Self {
+        self.min_start_us = min_start_us;
+        self
+    }
+
+    fn release(&mut self) {
+        let now = self.clock.now_us();
+        if let Some(since) = self.low_since.take() {
+            if now - since >= self.min_start_us {
+                self.response = self
+                    .responder
+                    .respond()
+                    .map(|r| (now + RESPONSE_DELAY_US, r));
+            }
+        }
+    }
+
+    fn level(&self) -> bool {
+        if self.output {
+            return self.driven_high;
+        }
+
+        let now = self.clock.now_us();
+        match &self.response {
+            Some((_, response)) if response.stuck_low => false,
+            Some((start, response)) if now >= *start => response.level_at(now - start),
+            _ => true,
+        }
+    }
+}
+
+impl InputPin for SimLine {
+    type Error = Infallible;
+
+    fn is_high(&self) -> Result<bool, Self::Error> {
+        self.clock.advance_us(SAMPLE_US);
+        Ok(self.level())
+    }
+
+    fn is_low(&self) -> Result<bool, Self::Error> {
+        self.clock.advance_us(SAMPLE_US);
+        Ok(!self.level())
+    }
+}
+
+impl OutputPin for SimLine {
+    type Error = Infallible;
+
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        if self.output && self.driven_high {
+            self.low_since = Some(self.clock.now_us());
+        }
+        self.driven_high = false;
+        Ok(())
+    }
+
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        if self.output {
+            self.release();
+        }
+        self.driven_high = true;
+        Ok(())
+    }
+}
+
+impl EdgeCapture for SimLine {
+    type Error = Infallible;
+
+    fn start_capture(&mut self) -> Result<(), Self::Error> {
+        self.set_input()?;
+        self.capture_since = Some(self.clock.now_us());
+        Ok(())
+    }
+
+    fn finish_capture(
+        &mut self,
+        count: usize,
+        timeout: Duration,
+    ) -> Result<Vec<Edge>, Self::Error> {
+        let Some(since) = self.capture_since.take() else {
+            return Ok(Vec::new());
+        };
+        let deadline = since + timeout.as_micros() as u64;
+
+        let edges: Vec<Edge> = match &self.response {
+            Some((start, response)) => response
+                .edges()
+                .into_iter()
+                .map(|edge| Edge {
+                    time_us: start + edge.time_us,
+                    ..edge
+                })
+                .filter(|edge| (since..deadline).contains(&edge.time_us))
+                .take(count)
+                .collect(),
+            None => Vec::new(),
+        };
+
+        // Waiting for the edges takes as long as the sensor takes to send them
+        let done = match edges.last() {
+            Some(edge) if edges.len() == count => edge.time_us,
+            _ => deadline,
+        };
+        self.clock
+            .advance_us(done.saturating_sub(self.clock.now_us()));
+        Ok(edges)
+    }
+}
+
+impl IoMode for SimLine {
+    type Error = Infallible;
+
+    fn set_input(&mut self) -> Result<(), Self::Error> {
+        if self.output && !self.driven_high {
+            self.release();
+        }
+        self.output = false;
+        Ok(())
+    }
+
+    fn set_output(&mut self) -> Result<(), Self::Error> {
+        self.output = true;
+        self.response = None;
+        if !self.driven_high {
+            self.low_since = Some(self.clock.now_us());
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,636 @@
+//! Software stand-ins for sensors on an I2C bus.
+//!
+//! [`SimBus`] routes each transfer to the [`SimDevice`] at its address. [`Registers`] behaves
+//! like a chip with an auto-incrementing register pointer and can be loaded from an `i2cdump`
+//! of real hardware, so drivers can be run against captured register maps. [`SimBme280`]
+//! builds on it to measure a climate supplied by the caller, [`SimSht`] and [`SimScd4x`]
+//! answer the commands of the Sensirion sensors the same way, [`SimAds1115`] converts
+//! voltages supplied per channel, and [`SimBh1750`] and [`SimTsl2591`] measure a supplied
+//! illuminance.
+//!
+#![deny(unsafe_code)]
+
+use std::{collections::HashMap, num::ParseIntError, sync::Mutex};
+
+use embedded_hal::blocking::i2c::{Read, Write, WriteRead};
+
+use super::{
+    ads1115::{self, REG_CONFIG, REG_CONVERSION},
+    bh1750,
+    bme280::{
+        Calibration, CHIP_ID_BME280, MODE_FORCED, REG_CALIBRATION_H, REG_CALIBRATION_TP,
+        REG_CHIP_ID, REG_CTRL_MEAS, REG_DATA,
+    },
+    bus::{DeviceLock, SharedDevices},
+    device::Measurement,
+    quantity::Quantity,
+    scd4x::{DATA_READY_MASK, GET_DATA_READY, READ_MEASUREMENT, START_PERIODIC, STOP_PERIODIC},
+    sht::{self, crc8, Family, Precision, SHT3X_BREAK, SHT3X_FETCH, SHT4X_HEAT_200MW_1S},
+    tsl2591,
+};
+
+/// Calibration of a typical BME280, the temperature and pressure part is the datasheet's example
+const BME280_CALIBRATION_TP: [u8; 26] = [
+    0x70, 0x6b, 0x43, 0x67, 0x18, 0xfc, 0x7d, 0x8e, 0x43, 0xd6, 0xd0, 0x0b, 0x27, 0x0b, 0x8c, 0x00,
+    0xf9, 0xff, 0x8c, 0x3c, 0xf8, 0xc6, 0x70, 0x17, 0x00, 0x4b,
+];
+const BME280_CALIBRATION_H: [u8; 7] = [0x6a, 0x01, 0x00, 0x14, 0x24, 0x03, 0x1e];
+
+/// The error returned for transfers to an address without a device.
+#[derive(thiserror::Error, Debug, Clone, Copy, PartialEq, Eq)]
+#[error("No device acknowledged I2C address {address:#04x}")]
+pub struct Nack {
+    /// The address of the transfer.
+    pub address: u8,
+}
+
+/// A simulated device on a [`SimBus`].
+pub trait SimDevice: Send {
+    /// Handles the bytes written by the host.
+    fn write(&mut self, bytes: &[u8]);
+    /// Fills `buffer` with the bytes sent to the host.
+    fn read(&mut self, buffer: &mut [u8]);
+}
+
+/// A simulated I2C bus, transfers to addresses without a device are not acknowledged.
+#[derive(Default)]
+pub struct SimBus {
+    devices: HashMap<u8, Box<dyn SimDevice>>,
+    /// The locks of the devices, no other handle reaches them.
+    locks: Mutex<HashMap<u8, DeviceLock>>,
+}
+
+impl SimBus {
+    /// Creates a bus without any devices.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Places `device` at `address`, replacing any device already there.
+    pub fn with_device<D: SimDevice + 'static>(mut self, address: u8, device: D) -> Self {
+        self.devices.insert(address, Box::new(device));
+        self
+    }
+
+    fn device(&mut self, address: u8) -> Result<&mut Box<dyn SimDevice>, Nack> {
+        self.devices.get_mut(&address).ok_or(Nack { address })
+    }
+}
+
+impl SharedDevices for SimBus {
+    fn device_lock(&self, address: u8) -> DeviceLock {
+        let mut locks = self.locks.lock().unwrap_or_else(|e| e.into_inner());
+        locks.entry(address).or_default().clone()
+    }
+}
+
+impl Read for SimBus {
+    type Error = Nack;
+
+    fn read(&mut self, address: u8, buffer: &mut [u8]) -> Result<(), Self::Error> {
+        self.device(address)?.read(buffer);
+        Ok(())
+    }
+}
+
+impl Write for SimBus {
+    type Error = Nack;
+
+    fn write(&mut self, address: u8, bytes: &[u8]) -> Result<(), Self::Error> {
+        self.device(address)?.write(bytes);
+        Ok(())
+    }
+}
+
+impl WriteRead for SimBus {
+    type Error = Nack;
+
+    fn write_read(
+        &mut self,
+        address: u8,
+        bytes: &[u8],
+        buffer: &mut [u8],
+    ) -> Result<(), Self::Error> {
+        let device = self.device(address)?;
+        device.write(bytes);
+        device.read(buffer);
+        Ok(())
+    }
+}
+
+/// A register map addressed through a pointer, which is set by the first byte of each write.
+#[derive(Clone, Debug)]
+pub struct Registers {
+    data: [u8; 256],
+    pointer: u8,
+}
+
+impl Default for Registers {
+    fn default() -> Self {
+        Registers {
+            data: [0; 256],
+            pointer: 0,
+        }
+    }
+}
+
+impl Registers {
+    /// Creates a register map with all registers cleared.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parses the output of `i2cdump -y <bus> <address>`, bytes that could not be read (`XX`) are `0xff`.
+    pub fn from_dump(dump: &str) -> Result<Self, ParseIntError> {
+        let mut registers = Registers::new();
+        for line in dump.lines() {
+            // The header line listing the columns has no row address
+            let Some((row, bytes)) = line.split_once(':') else {
+                continue;
+            };
+            let row = u8::from_str_radix(row.trim(), 16)?;
+            for (column, byte) in bytes.split_whitespace().take(16).enumerate() {
+                let value = match byte {
+                    "XX" => 0xff,
+                    byte => u8::from_str_radix(byte, 16)?,
+                };
+                registers.data[usize::from(row) + column] = value;
+            }
+        }
+        Ok(registers)
+    }
+
+    /// The contents of `len` registers starting at `register`.
+    pub fn get(&self, register: u8, len: usize) -> &[u8] {
+        let start = usize::from(register);
+        &self.data[start..(start + len).min(self.data.len())]
+    }
+
+    /// Overwrites the registers starting at `register`.
+    pub fn set(&mut self, register: u8, bytes: &[u8]) {
+        let start = usize::from(register);
+        self.data[start..start + bytes.len()].copy_from_slice(bytes);
+    }
+}
+
+impl SimDevice for Registers {
+    fn write(&mut self, bytes: &[u8]) {
+        let Some((&pointer, values)) = bytes.split_first() else {
+            return;
+        };
+        self.pointer = pointer;
+        for value in values {
+            self.data[usize::from(self.pointer)] = *value;
+            self.pointer = self.pointer.wrapping_add(1);
+        }
+    }
+
+    fn read(&mut self, buffer: &mut [u8]) {
+        for byte in buffer {
+            *byte = self.data[usize::from(self.pointer)];
+            self.pointer = self.pointer.wrapping_add(1);
+        }
+    }
+}
+
+/// A BME280 that measures whatever `sample` returns when a measurement is triggered.
+///
+/// Measurements complete instantly, the values are encoded so the driver's compensation
+/// reproduces them up to the resolution of the raw values.
+pub struct SimBme280<F> {
+    registers: Registers,
+    calibration: Calibration,
+    sample: F,
+}
+
+impl<F: FnMut() -> Measurement + Send> SimBme280<F> {
+    /// Creates a sensor with the calibration of a typical BME280.
+    pub fn new(sample: F) -> Self {
+        let mut registers = Registers::new();
+        registers.set(REG_CHIP_ID, &[CHIP_ID_BME280]);
+        registers.set(REG_CALIBRATION_TP, &BME280_CALIBRATION_TP);
+        registers.set(REG_CALIBRATION_H, &BME280_CALIBRATION_H);
+        Self::with_registers(registers, sample)
+    }
+
+    /// Creates a sensor with the chip id and calibration of a captured register map.
+    pub fn with_registers(registers: Registers, sample: F) -> Self {
+        let mut tp = [0; 26];
+        tp.copy_from_slice(registers.get(REG_CALIBRATION_TP, 26));
+        let mut h = [0; 7];
+        h.copy_from_slice(registers.get(REG_CALIBRATION_H, 7));
+        SimBme280 {
+            calibration: Calibration::from_registers(&tp, &h),
+            registers,
+            sample,
+        }
+    }
+
+    fn measure(&mut self) {
+        let measurement = (self.sample)();
+        let calibration = &self.calibration;
+
+        let target = f64::from(measurement.get(Quantity::Temperature).unwrap_or_default()) * 5120.0;
+        let adc_t = invert(1 << 20, target, |adc| calibration.t_fine(adc));
+        let t_fine = calibration.t_fine(adc_t);
+        // Pressure decreases as the raw value increases
+        let target = -f64::from(measurement.get(Quantity::Pressure).unwrap_or_default()) * 100.0;
+        let adc_p = invert(1 << 20, target, |adc| -calibration.pressure(t_fine, adc));
+        let target = f64::from(measurement.get(Quantity::Humidity).unwrap_or_default());
+        let adc_h = invert(1 << 16, target, |adc| {
+            calibration.humidity(t_fine, adc as u16)
+        });
+
+        let [_, p_msb, p_lsb, p_xlsb] = (adc_p << 4).to_be_bytes();
+        let [_, t_msb, t_lsb, t_xlsb] = (adc_t << 4).to_be_bytes();
+        let [h_msb, h_lsb] = (adc_h as u16).to_be_bytes();
+        self.registers.set(
+            REG_DATA,
+            &[p_msb, p_lsb, p_xlsb, t_msb, t_lsb, t_xlsb, h_msb, h_lsb],
+        );
+    }
+}
+
+impl<F: FnMut() -> Measurement + Send> SimDevice for SimBme280<F> {
+    fn write(&mut self, bytes: &[u8]) {
+        self.registers.write(bytes);
+        if let [REG_CTRL_MEAS, ctrl_meas, ..] = *bytes {
+            if ctrl_meas & 0b11 == MODE_FORCED {
+                self.measure();
+                // Back to sleep once the forced measurement is done
+                self.registers.set(REG_CTRL_MEAS, &[ctrl_meas & !0b11]);
+            }
+        }
+    }
+
+    fn read(&mut self, buffer: &mut [u8]) {
+        self.registers.read(buffer);
+    }
+}
+
+/// Finds the smallest raw value below `limit` whose compensated value reaches `target`,
+/// for compensations that increase with the raw value.
+fn invert(limit: u32, target: f64, compensate: impl Fn(u32) -> f64) -> u32 {
+    let (mut low, mut high) = (0, limit - 1);
+    while low < high {
+        let middle = low + (high - low) / 2;
+        if compensate(middle) < target {
+            low = middle + 1;
+        } else {
+            high = middle;
+        }
+    }
+    low
+}
+
+/// An SHT3x or SHT4x that measures whatever `sample` returns when a measurement is started.
+///
+/// Measurements complete instantly. `sample` can also replay a scripted sequence of values.
+pub struct SimSht<F> {
+    family: Family,
+    sample: F,
+    /// Whether the SHT3x measures periodically.
+    periodic: bool,
+    /// The words and CRCs returned by the next read.
+    response: Vec<u8>,
+}
+
+impl<F: FnMut() -> Measurement + Send> SimSht<F> {
+    /// Creates a sensor of the given family.
+    pub fn new(family: Family, sample: F) -> Self {
+        SimSht {
+            family,
+            sample,
+            periodic: false,
+            response: Vec::new(),
+        }
+    }
+
+    fn measure(&mut self) {
+        let measurement = (self.sample)();
+        let temperature =
+            (measurement.get(Quantity::Temperature).unwrap_or_default() + 45.0) / 175.0;
+        let humidity = measurement.get(Quantity::Humidity).unwrap_or_default();
+        let humidity = match self.family {
+            Family::Sht3x => humidity / 100.0,
+            Family::Sht4x => (humidity + 6.0) / 125.0,
+        };
+
+        self.response.clear();
+        for value in [temperature, humidity] {
+            let word = (value.clamp(0.0, 1.0) * 65535.0).round() as u16;
+            let bytes = word.to_be_bytes();
+            self.response.extend(bytes);
+            self.response.push(crc8(&bytes));
+        }
+    }
+}
+
+impl<F: FnMut() -> Measurement + Send> SimDevice for SimSht<F> {
+    fn write(&mut self, bytes: &[u8]) {
+        let single_shot = [Precision::High, Precision::Medium, Precision::Low]
+            .into_iter()
+            .any(|precision| self.family.single_shot(precision) == bytes);
+        let periodic = self.family == Family::Sht3x
+            && [Precision::High, Precision::Medium, Precision::Low]
+                .into_iter()
+                .any(|precision| sht::sht3x_periodic(precision) == bytes);
+
+        if single_shot || (self.periodic && bytes == SHT3X_FETCH) {
+            self.measure();
+        } else if periodic {
+            self.periodic = true;
+        } else if bytes == SHT3X_BREAK {
+            self.periodic = false;
+        } else if self.family == Family::Sht4x && bytes == [SHT4X_HEAT_200MW_1S] {
+            self.measure();
+        }
+    }
+
+    fn read(&mut self, buffer: &mut [u8]) {
+        // Without a result the sensor leaves the bus idle
+        buffer.fill(0xff);
+        let len = buffer.len().min(self.response.len());
+        buffer[..len].copy_from_slice(&self.response[..len]);
+        self.response.clear();
+    }
+}
+
+/// An SCD40 or SCD41 measuring the climate `sample` returns.
+///
+/// Measurements complete instantly, so a new one is ready whenever it is measuring. Settings
+/// such as the self-calibration are accepted and ignored.
+pub struct SimScd4x<F> {
+    sample: F,
+    /// Whether periodic measurements were started.
+    periodic: bool,
+    /// The words and CRCs returned by the next read.
+    response: Vec<u8>,
+}
+
+impl<F: FnMut() -> Measurement + Send> SimScd4x<F> {
+    /// Creates an idle sensor.
+    pub fn new(sample: F) -> Self {
+        SimScd4x {
+            sample,
+            periodic: false,
+            response: Vec::new(),
+        }
+    }
+
+    fn respond(&mut self, words: &[u16]) {
+        self.response.clear();
+        for word in words {
+            let bytes = word.to_be_bytes();
+            self.response.extend(bytes);
+            self.response.push(crc8(&bytes));
+        }
+    }
+}
+
+impl<F: FnMut() -> Measurement + Send> SimDevice for SimScd4x<F> {
+    fn write(&mut self, bytes: &[u8]) {
+        let Some(command) = bytes.get(..2) else {
+            return;
+        };
+
+        if command == START_PERIODIC {
+            self.periodic = true;
+        } else if command == STOP_PERIODIC {
+            self.periodic = false;
+        } else if command == GET_DATA_READY {
+            let status = if self.periodic { DATA_READY_MASK } else { 0 };
+            self.respond(&[0x8000 | status]);
+        } else if command == READ_MEASUREMENT && self.periodic {
+            let measurement = (self.sample)();
+            let temperature =
+                (measurement.get(Quantity::Temperature).unwrap_or_default() + 45.0) / 175.0;
+            let humidity = measurement.get(Quantity::Humidity).unwrap_or_default() / 100.0;
+            self.respond(&[
+                measurement.get(Quantity::Co2).unwrap_or_default().round() as u16,
+                (temperature.clamp(0.0, 1.0) * 65535.0).round() as u16,
+                (humidity.clamp(0.0, 1.0) * 65535.0).round() as u16,
+            ]);
+        }
+    }
+
+    fn read(&mut self, buffer: &mut [u8]) {
+        // Without a result the sensor leaves the bus idle
+        buffer.fill(0xff);
+        let len = buffer.len().min(self.response.len());
+        buffer[..len].copy_from_slice(&self.response[..len]);
+        self.response.clear();
+    }
+}
+
+/// An ADS1115 converting whatever `sample` returns for a channel, in volts.
+///
+/// Conversions complete instantly and saturate at the full scale of the configured gain.
+pub struct SimAds1115<F> {
+    sample: F,
+    pointer: u8,
+    config: u16,
+    conversion: i16,
+}
+
+impl<F: FnMut(u8) -> f32 + Send> SimAds1115<F> {
+    /// Creates a converter with its power-on config.
+    pub fn new(sample: F) -> Self {
+        SimAds1115 {
+            sample,
+            pointer: REG_CONVERSION,
+            config: 0x8583,
+            conversion: 0,
+        }
+    }
+
+    fn convert(&mut self) {
+        let channel = (self.config >> 12) as u8 & 0b11;
+        let full_scale = ads1115::FULL_SCALE[usize::from(self.config >> 9 & 0b111)];
+        let volts = (self.sample)(channel);
+        self.conversion = (volts / full_scale * 32768.0)
+            .round()
+            .clamp(-32768.0, 32767.0) as i16;
+    }
+}
+
+impl<F: FnMut(u8) -> f32 + Send> SimDevice for SimAds1115<F> {
+    fn write(&mut self, bytes: &[u8]) {
+        match *bytes {
+            [REG_CONFIG, msb, lsb] => {
+                self.pointer = REG_CONFIG;
+                self.config = u16::from_be_bytes([msb, lsb]);
+                if self.config & ads1115::START != 0 {
+                    self.convert();
+                }
+                // Idle again once the single-shot conversion is done
+                self.config |= ads1115::START;
+            }
+            [pointer, ..] => self.pointer = pointer,
+            [] => {}
+        }
+    }
+
+    fn read(&mut self, buffer: &mut [u8]) {
+        let value = match self.pointer {
+            REG_CONFIG => self.config,
+            _ => self.conversion as u16,
+        };
+        for (byte, value) in buffer.iter_mut().zip(value.to_be_bytes()) {
+            *byte = value;
+        }
+    }
+}
+
+/// A BH1750 measuring whatever illuminance `sample` returns.
+///
+/// Measurements complete instantly, and reads before the first one return zero.
+pub struct SimBh1750<F> {
+    sample: F,
+    counts: u16,
+}
+
+impl<F: FnMut() -> Measurement + Send> SimBh1750<F> {
+    /// Creates a sensor that didn't measure yet.
+    pub fn new(sample: F) -> Self {
+        SimBh1750 { sample, counts: 0 }
+    }
+}
+
+impl<F: FnMut() -> Measurement + Send> SimDevice for SimBh1750<F> {
+    fn write(&mut self, bytes: &[u8]) {
+        if bytes == [bh1750::ONE_TIME_HIGH_RES] {
+            let lux = (self.sample)()
+                .get(Quantity::Illuminance)
+                .unwrap_or_default();
+            self.counts = (lux * bh1750::COUNTS_PER_LUX)
+                .round()
+                .clamp(0.0, f32::from(u16::MAX)) as u16;
+        }
+    }
+
+    fn read(&mut self, buffer: &mut [u8]) {
+        for (byte, value) in buffer.iter_mut().zip(self.counts.to_be_bytes()) {
+            *byte = value;
+        }
+    }
+}
+
+/// A TSL2591 measuring whatever illuminance `sample` returns.
+///
+/// Integrations complete as soon as the sensor is enabled. The infrared channel sees a
+/// quarter of the full spectrum's counts, and both saturate according to the configured gain.
+pub struct SimTsl2591<F> {
+    sample: F,
+    registers: [u8; 0x20],
+    pointer: u8,
+}
+
+impl<F: FnMut() -> Measurement + Send> SimTsl2591<F> {
+    /// Creates a powered down sensor.
+    pub fn new(sample: F) -> Self {
+        let mut registers = [0; 0x20];
+        registers[usize::from(tsl2591::REG_ID)] = tsl2591::CHIP_ID;
+        SimTsl2591 {
+            sample,
+            registers,
+            pointer: 0,
+        }
+    }
+
+    fn integrate(&mut self) {
+        const INFRARED_SHARE: f32 = 0.25;
+        let gain_bits = self.registers[usize::from(tsl2591::REG_CONFIG)] & tsl2591::GAIN_MASK;
+        let (_, gain) = tsl2591::GAINS
+            .into_iter()
+            .find(|(bits, _)| *bits == gain_bits)
+            .unwrap_or(tsl2591::GAINS[0]);
+        let counts_per_lux = f32::from(tsl2591::INTEGRATION_MS) * gain / tsl2591::LUX_COEFFICIENT;
+
+        // Inverts the lux formula of the driver for the fixed infrared share
+        let lux = (self.sample)()
+            .get(Quantity::Illuminance)
+            .unwrap_or_default();
+        let full = lux * counts_per_lux / (1.0 - INFRARED_SHARE).powi(2);
+        let count = |value: f32| value.round().clamp(0.0, f32::from(tsl2591::MAX_COUNT)) as u16;
+        let data = usize::from(tsl2591::REG_DATA);
+        self.registers[data..data + 2].copy_from_slice(&count(full).to_le_bytes());
+        self.registers[data + 2..data + 4]
+            .copy_from_slice(&count(full * INFRARED_SHARE).to_le_bytes());
+        self.registers[usize::from(tsl2591::REG_STATUS)] = tsl2591::STATUS_VALID;
+    }
+}
+
+impl<F: FnMut() -> Measurement + Send> SimDevice for SimTsl2591<F> {
+    fn write(&mut self, bytes: &[u8]) {
+        let Some((&command, values)) = bytes.split_first() else {
+            return;
+        };
+        if command & tsl2591::COMMAND != tsl2591::COMMAND {
+            return;
+        }
+
+        self.pointer = command & 0x1f;
+        if let [value] = *values {
+            self.registers[usize::from(self.pointer)] = value;
+            if self.pointer == tsl2591::REG_ENABLE {
+                if value & tsl2591::ENABLE_ON == tsl2591::ENABLE_ON {
+                    self.integrate();
+                } else {
+                    self.registers[usize::from(tsl2591::REG_STATUS)] = 0;
+                }
+            }
+        }
+    }
+
+    fn read(&mut self, buffer: &mut [u8]) {
+        for byte in buffer {
+            *byte = self.registers[usize::from(self.pointer)];
+            self.pointer = (self.pointer + 1) % 0x20;
+        }
+    }
+}
+
+/// Wraps a device to record what the host writes to it and to corrupt what it sends back.
+#[cfg(test)]
+pub struct Spy<D> {
+    device: D,
+    writes: std::sync::Arc<std::sync::Mutex<Vec<Vec<u8>>>>,
+    /// The index of a byte flipped in every read.
+    corrupt: Option<usize>,
+}
+
+#[cfg(test)]
+impl<D: SimDevice> Spy<D> {
+    /// Wraps `device`, returning the log of writes along with it.
+    pub fn new(device: D) -> (Self, std::sync::Arc<std::sync::Mutex<Vec<Vec<u8>>>>) {
+        let writes = std::sync::Arc::default();
+        let spy = Spy {
+            device,
+            writes: std::sync::Arc::clone(&writes),
+            corrupt: None,
+        };
+        (spy, writes)
+    }
+
+    /// Flips the bits of the byte at `index` in every read.
+    pub fn corrupting(mut self, index: usize) -> Self {
+        self.corrupt = Some(index);
+        self
+    }
+}
+
+#[cfg(test)]
+impl<D: SimDevice> SimDevice for Spy<D> {
+    fn write(&mut self, bytes: &[u8]) {
+        self.writes.lock().unwrap().push(bytes.to_vec());
+        self.device.write(bytes);
+    }
+
+    fn read(&mut self, buffer: &mut [u8]) {
+        self.device.read(buffer);
+        if let Some(byte) = self.corrupt.and_then(|index| buffer.get_mut(index)) {
+            *byte = !*byte;
+        }
+    }
+}
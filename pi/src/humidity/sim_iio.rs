@@ -0,0 +1,62 @@
+//! A software stand-in for a DHT sensor bound to the kernel's `dht11` IIO driver.
+//!
+#![deny(unsafe_code)]
+
+use std::io;
+
+use super::{
+    bus::IioDevice,
+    device::Measurement,
+    iio::{EIO, ETIMEDOUT},
+    quantity::Quantity,
+    sim::Fault,
+};
+
+/// The error the driver fails a read with when `fault` garbles the response.
+pub fn driver_error(fault: Fault) -> io::Error {
+    match fault {
+        Fault::NoResponse | Fault::StuckLow => io::Error::from_raw_os_error(ETIMEDOUT),
+        Fault::CorruptBit(_) | Fault::DroppedEdge(_) | Fault::Truncated(_) => {
+            io::Error::from_raw_os_error(EIO)
+        }
+    }
+}
+
+/// An IIO device whose channels report whatever `sample` returns.
+///
+/// Like the driver, reading the temperature starts a measurement and the humidity is taken
+/// from it. Errors returned by `sample` are passed on, e.g. `EIO` for a garbled response.
+pub struct SimIio {
+    sample: Box<dyn FnMut() -> io::Result<Measurement> + Send>,
+    /// The measurement started by the last temperature read.
+    latest: Option<Measurement>,
+}
+
+impl SimIio {
+    pub fn new(sample: impl FnMut() -> io::Result<Measurement> + Send + 'static) -> Self {
+        SimIio {
+            sample: Box::new(sample),
+            latest: None,
+        }
+    }
+}
+
+impl IioDevice for SimIio {
+    fn read_channel(&mut self, channel: &str) -> io::Result<String> {
+        let measurement = match (channel, self.latest) {
+            ("in_humidityrelative_input", Some(latest)) => latest,
+            _ => {
+                let measurement = (self.sample)()?;
+                self.latest = Some(measurement);
+                measurement
+            }
+        };
+
+        let value = match channel {
+            "in_temp_input" => measurement.get(Quantity::Temperature).unwrap_or_default(),
+            "in_humidityrelative_input" => measurement.get(Quantity::Humidity).unwrap_or_default(),
+            _ => return Err(io::ErrorKind::NotFound.into()),
+        };
+        Ok(format!("{}\n", (value * 1000.0).round() as i32))
+    }
+}
@@ -0,0 +1,101 @@
+//! A software stand-in for a serial port with an MH-Z19B on the other end.
+//!
+//! [`SimMhz19`] answers complete command frames the way the sensor does, taking the place of
+//! a pseudo-terminal wired to a fake sensor. Frames with a bad checksum are ignored, and so
+//! are the commands that the sensor doesn't reply to.
+//!
+#![deny(unsafe_code)]
+
+use core::convert::Infallible;
+use std::collections::VecDeque;
+
+use super::{
+    bus::SerialPort,
+    mhz19::{checksum, FRAME_LEN, READ_CO2, START},
+    sim::Fault,
+};
+
+/// The internal temperature reported along with the CO2 concentration, offset by 40°C
+const TEMPERATURE: u8 = 25 + 40;
+
+/// A serial port whose sensor measures whatever `sample` returns, in parts per million.
+pub struct SimMhz19 {
+    sample: Box<dyn FnMut() -> f32 + Send>,
+    faults: Box<dyn FnMut() -> Option<Fault> + Send>,
+    /// Bytes written by the host that don't form a frame yet.
+    received: Vec<u8>,
+    /// Bytes sent to the host that it didn't read yet.
+    sent: VecDeque<u8>,
+}
+
+impl SimMhz19 {
+    /// Creates a sensor that always answers correctly.
+    pub fn new(sample: impl FnMut() -> f32 + Send + 'static) -> Self {
+        SimMhz19 {
+            sample: Box::new(sample),
+            faults: Box::new(|| None),
+            received: Vec::new(),
+            sent: VecDeque::new(),
+        }
+    }
+
+    /// Garbles each response with the fault `faults` returns, if any.
+    pub fn with_faults(mut self, faults: impl FnMut() -> Option<Fault> + Send + 'static) -> Self {
+        self.faults = Box::new(faults);
+        self
+    }
+
+    fn respond(&mut self, command: &[u8; FRAME_LEN]) {
+        if checksum(command) != command[8] || command[2] != READ_CO2 {
+            return;
+        }
+
+        let ppm = (self.sample)().round().clamp(0.0, f32::from(u16::MAX)) as u16;
+        let [high, low] = ppm.to_be_bytes();
+        let mut frame = [START, READ_CO2, high, low, TEMPERATURE, 0, 0, 0, 0];
+        frame[8] = checksum(&frame);
+
+        let len = match (self.faults)() {
+            None => FRAME_LEN,
+            Some(Fault::NoResponse | Fault::StuckLow) => 0,
+            Some(Fault::CorruptBit(bit) | Fault::DroppedEdge(bit)) => {
+                // Keeps the start byte, so the frame fails its checksum
+                frame[1 + bit / 8 % (FRAME_LEN - 1)] ^= 1 << (bit % 8);
+                FRAME_LEN
+            }
+            Some(Fault::Truncated(bit)) => (bit / 8).min(FRAME_LEN),
+        };
+        self.sent.extend(&frame[..len]);
+    }
+}
+
+impl SerialPort for SimMhz19 {
+    type Error = Infallible;
+
+    fn write_all(&mut self, bytes: &[u8]) -> Result<(), Self::Error> {
+        self.received.extend(bytes);
+        while self.received.len() >= FRAME_LEN {
+            // Skips noise until a frame starts
+            if self.received[0] != START {
+                self.received.remove(0);
+                continue;
+            }
+            let frame: Vec<_> = self.received.drain(..FRAME_LEN).collect();
+            self.respond(&frame.try_into().expect("drained a whole frame"));
+        }
+        Ok(())
+    }
+
+    fn read(&mut self, buffer: &mut [u8]) -> Result<usize, Self::Error> {
+        let len = buffer.len().min(self.sent.len());
+        for (byte, sent) in buffer.iter_mut().zip(self.sent.drain(..len)) {
+            *byte = sent;
+        }
+        Ok(len)
+    }
+
+    fn discard_input(&mut self) -> Result<(), Self::Error> {
+        self.sent.clear();
+        Ok(())
+    }
+}
@@ -0,0 +1,66 @@
+//! A software stand-in for the kernel's 1-Wire bus master with DS18B20 probes on it.
+//!
+//! [`SimW1`] formats `w1_slave` the way the `w1_therm` driver does, so the same parser reads
+//! simulated and real probes.
+//!
+#![deny(unsafe_code)]
+
+use core::convert::Infallible;
+use std::collections::BTreeMap;
+
+use super::{bus::W1Bus, ds18b20::crc8};
+
+/// A 1-Wire bus whose probes measure whatever their `sample` returns.
+#[derive(Default)]
+pub struct SimW1 {
+    probes: BTreeMap<String, Box<dyn FnMut() -> f32 + Send>>,
+}
+
+impl SimW1 {
+    /// Creates a bus without any devices.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a probe with the given id, measuring the temperature `sample` returns in degrees Celsius.
+    pub fn with_probe(
+        mut self,
+        device: impl Into<String>,
+        sample: impl FnMut() -> f32 + Send + 'static,
+    ) -> Self {
+        self.probes.insert(device.into(), Box::new(sample));
+        self
+    }
+}
+
+impl W1Bus for SimW1 {
+    type Error = Infallible;
+
+    fn devices(&mut self) -> Result<Vec<String>, Self::Error> {
+        Ok(self.probes.keys().cloned().collect())
+    }
+
+    fn read_slave(&mut self, device: &str) -> Result<String, Self::Error> {
+        // The file disappears along with the device, reading it yields nothing
+        let Some(sample) = self.probes.get_mut(device) else {
+            return Ok(String::new());
+        };
+
+        // 12 bit resolution, sixteenths of a degree
+        let raw = (sample() * 16.0).round() as i16;
+        let [lsb, msb] = raw.to_le_bytes();
+        let mut scratchpad = vec![lsb, msb, 0x4b, 0x46, 0x7f, 0xff, 0x0c, 0x10];
+        scratchpad.push(crc8(&scratchpad));
+        let bytes = scratchpad
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        Ok(format!(
+            "{bytes} : crc={:02x} YES\n{bytes} t={}\n",
+            scratchpad[8],
+            i32::from(raw) * 1000 / 16
+        ))
+    }
+}
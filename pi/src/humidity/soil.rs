@@ -0,0 +1,120 @@
+//! Capacitive soil moisture probes, whose analog output is read through an ADS1115.
+//!
+//! The output voltage drops as the soil gets wetter. It is mapped linearly onto 0-100% between
+//! the voltages measured with the probe in dry air and submerged in water.
+//!
+#![deny(unsafe_code)]
+
+use embedded_hal::blocking::delay::{DelayMs, DelayUs};
+use serde::{Deserialize, Serialize};
+
+use super::{
+    ads1115::{self, Ads1115},
+    bus::{BusError, I2cBus, SharedDevices},
+    device::{Device, Measurement},
+    pin::Clock,
+    quantity::Quantity,
+    Error, Result,
+};
+
+/// Soil moisture is reported in percent of the calibrated span
+pub const SOIL_MOISTURE_RANGE: (f32, f32) = (0.0, 100.0);
+
+/// The output of a probe at both ends of its span.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Calibration {
+    /// Output in dry air, in volts.
+    pub dry: f32,
+    /// Output submerged in water, in volts.
+    pub wet: f32,
+}
+
+impl Default for Calibration {
+    /// Typical of the common capacitive probes powered from 3.3V.
+    fn default() -> Self {
+        Calibration { dry: 2.8, wet: 1.2 }
+    }
+}
+
+impl Calibration {
+    /// Maps an output voltage onto the calibrated span, clamped to 0-100%.
+    pub fn moisture(&self, volts: f32) -> f32 {
+        ((self.dry - volts) / (self.dry - self.wet) * 100.0).clamp(0.0, 100.0)
+    }
+}
+
+/// A soil moisture probe on a channel of an ADS1115.
+pub struct SoilProbe<B> {
+    adc: Ads1115<B>,
+    settings: ads1115::Settings,
+    calibration: Calibration,
+}
+
+impl<B: SharedDevices> SoilProbe<B> {
+    /// Creates a probe on channel 0 of the converter at `address`, with a typical calibration.
+    pub fn new(bus: B, address: u8) -> Self {
+        SoilProbe {
+            adc: Ads1115::new(bus, address),
+            settings: ads1115::Settings::default(),
+            calibration: Calibration::default(),
+        }
+    }
+}
+
+impl<B> SoilProbe<B> {
+    /// Sets the channel the probe is connected to and how it is converted.
+    pub fn with_settings(mut self, settings: ads1115::Settings) -> Self {
+        self.settings = settings;
+        self
+    }
+
+    /// Sets the voltages the probe outputs when dry and wet.
+    pub fn with_calibration(mut self, calibration: Calibration) -> Self {
+        self.calibration = calibration;
+        self
+    }
+}
+
+impl<B> Device for SoilProbe<B>
+where
+    B: I2cBus,
+    Error: From<BusError<B>>,
+{
+    fn perform_measurement<D: DelayUs<u16> + DelayMs<u16> + Clock>(
+        &mut self,
+        delay: &mut D,
+    ) -> Result<Measurement> {
+        let volts = self.adc.read_voltage(self.settings, delay)?;
+        Ok(Measurement::new().with(Quantity::SoilMoisture, self.calibration.moisture(volts)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maps_the_span_between_dry_and_wet() {
+        let calibration = Calibration { dry: 2.8, wet: 1.2 };
+        assert_eq!(calibration.moisture(2.8), 0.0);
+        assert_eq!(calibration.moisture(1.2), 100.0);
+        assert!((calibration.moisture(2.0) - 50.0).abs() < 1e-4);
+        assert!((calibration.moisture(2.4) - 25.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn clamps_outputs_beyond_the_span() {
+        let calibration = Calibration::default();
+        assert_eq!(calibration.moisture(3.3), 0.0);
+        assert_eq!(calibration.moisture(0.5), 100.0);
+    }
+
+    #[test]
+    fn maps_probes_whose_output_rises_with_moisture() {
+        let calibration = Calibration { dry: 0.5, wet: 2.5 };
+        assert_eq!(calibration.moisture(0.5), 0.0);
+        assert!((calibration.moisture(1.0) - 25.0).abs() < 1e-4);
+        assert_eq!(calibration.moisture(3.0), 100.0);
+    }
+}
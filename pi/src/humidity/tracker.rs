@@ -1,49 +1,544 @@
-use super::{dht11::Dht11, dht22::Dht22, Reading, Result, Sensor, SensorType};
+use core::time::Duration;
 
-use rppal::{
-    gpio::{Gpio, Mode},
-    hal::Delay,
+use embedded_hal::blocking::delay::DelayMs;
+
+use super::{
+    bh1750::{self, Bh1750},
+    bme280::{self, Bme280},
+    climate::{self, DEFAULT_LEAF_TEMPERATURE_OFFSET},
+    correction::{self, Corrections},
+    device::{Device, Measurement},
+    dht::{Dht, Diagnostics, ReadMode},
+    ds18b20::{self, Ds18b20},
+    error::SensorError,
+    iio::IioDht,
+    light::Spectrum,
+    mhz19::{self, Mhz19},
+    pin::Clock,
+    quantity::Quantity,
+    retry::RetryPolicy,
+    scd4x::{self, Scd4x},
+    sht::{self, Sht},
+    soil::{self, SoilProbe},
+    tsl2591::{self, Tsl2591},
+    validate::{ValidationPolicy, Validator},
+    AdcSettings, Connection, Error, Reading, Result, Sensor, SensorType, ShtSettings,
+    SoilCalibration,
 };
 
+use crate::gpio::{Backend, Delay};
+
 pub struct Tracker {
+    id: String,
     sensor: Sensor,
+    delay: Delay,
+    retry_policy: RetryPolicy,
+    validation_policy: ValidationPolicy,
+    /// Humidity at which the heater is pulsed to drive off condensation.
+    condensation_humidity: Option<f32>,
+    /// The light source of light sensors, converting their illuminance to PPFD.
+    spectrum: Spectrum,
+    /// How much warmer than the air leaves are assumed to be for the leaf VPD.
+    leaf_temperature_offset: f32,
+    /// Corrections of the measured quantities.
+    corrections: Corrections,
 }
 
 impl Tracker {
-    pub fn new(sensor_type: SensorType, gpio_pin: u8) -> Result<Self> {
-        let gpio = Gpio::new()?;
-        let pin = gpio.get(gpio_pin)?.into_io(Mode::Input);
-
-        match sensor_type {
-            SensorType::Dht22 => Ok(Tracker {
-                sensor: Sensor::Dht22(Dht22::new(pin)),
-            }),
-            SensorType::Dht11 => Ok(Tracker {
-                sensor: Sensor::Dht11(Dht11::new(pin)),
-            }),
-        }
-    }
-
-    pub fn read(&mut self) -> Result<Reading> {
-        match self.sensor {
-            Sensor::Dht22(ref mut dht22) => {
-                match dht22.perform_measurement_with_retries(&mut Delay, 10) {
-                    Ok(result) => Ok(Reading {
-                        result,
-                        time: chrono::Utc::now(),
-                    }),
-                    Err(e) => Err(e),
+    pub fn new(
+        id: impl Into<String>,
+        backend: &Backend,
+        sensor_type: SensorType,
+        connection: Connection,
+        read_mode: ReadMode,
+    ) -> Result<Self> {
+        let delay = match connection {
+            Connection::Pin(pin) => backend.line_delay(pin),
+            _ => backend.delay(),
+        };
+        let sensor = match connection {
+            Connection::Pin(pin) => {
+                let model = sensor_type
+                    .dht_model()
+                    .ok_or(Error::Unsupported("connecting through a GPIO pin"))?;
+                Sensor::Dht(Dht::new(backend.io(pin)?, model).with_read_mode(read_mode))
+            }
+            Connection::I2c { bus, address } => match sensor_type {
+                SensorType::Bme280 => Sensor::Bme280(Bme280::new(backend.i2c(bus)?, address)),
+                SensorType::SoilMoisture => {
+                    Sensor::Soil(SoilProbe::new(backend.i2c(bus)?, address))
                 }
+                SensorType::Scd4x => Sensor::Scd4x(Scd4x::new(backend.i2c(bus)?, address)),
+                SensorType::Bh1750 => Sensor::Bh1750(Bh1750::new(backend.i2c(bus)?, address)),
+                SensorType::Tsl2591 => Sensor::Tsl2591(Tsl2591::new(backend.i2c(bus)?, address)),
+                sensor_type => match sensor_type.sht_family() {
+                    Some(family) => Sensor::Sht(Sht::new(backend.i2c(bus)?, address, family)),
+                    None => return Err(Error::Unsupported("connecting over I2C")),
+                },
+            },
+            Connection::Iio { device } => {
+                let model = sensor_type.dht_model().ok_or(Error::Unsupported(
+                    "reading through the kernel's dht11 driver",
+                ))?;
+                Sensor::Iio(IioDht::new(backend.iio(&device), model))
             }
-            Sensor::Dht11(ref mut dht11) => {
-                match dht11.perform_measurement_with_retries(&mut Delay, 10) {
-                    Ok(result) => Ok(Reading {
-                        result,
-                        time: chrono::Utc::now(),
-                    }),
-                    Err(e) => Err(e),
+            Connection::OneWire { root, device } => match sensor_type {
+                SensorType::Ds18b20 => {
+                    Sensor::Ds18b20(Ds18b20::new(backend.one_wire(&root), device))
                 }
+                _ => return Err(Error::Unsupported("connecting over 1-Wire")),
+            },
+            Connection::Serial { port } => match sensor_type {
+                SensorType::Mhz19b => Sensor::Mhz19(Mhz19::new(backend.serial(&port)?)),
+                _ => return Err(Error::Unsupported("connecting over a serial port")),
+            },
+        };
+
+        Ok(Tracker {
+            id: id.into(),
+            sensor,
+            delay,
+            retry_policy: RetryPolicy::default(),
+            validation_policy: ValidationPolicy::default(),
+            condensation_humidity: None,
+            spectrum: Spectrum::default(),
+            leaf_temperature_offset: DEFAULT_LEAF_TEMPERATURE_OFFSET,
+            corrections: Corrections::new(),
+        })
+    }
+
+    /// Sets how failed reads are retried.
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// Sets the limits readings are validated against.
+    pub fn with_validation_policy(mut self, validation_policy: ValidationPolicy) -> Self {
+        self.validation_policy = validation_policy;
+        self
+    }
+
+    /// Sets how SHT sensors measure, other sensors ignore it.
+    pub fn with_sht_settings(mut self, settings: ShtSettings) -> Self {
+        self.sensor = match self.sensor {
+            Sensor::Sht(sht) => Sensor::Sht(sht.with_settings(settings)),
+            sensor => sensor,
+        };
+        self
+    }
+
+    /// Sets the channel and conversion of soil moisture probes, other sensors ignore it.
+    pub fn with_adc_settings(mut self, settings: AdcSettings) -> Self {
+        self.sensor = match self.sensor {
+            Sensor::Soil(probe) => Sensor::Soil(probe.with_settings(settings)),
+            sensor => sensor,
+        };
+        self
+    }
+
+    /// Sets the dry and wet voltages of soil moisture probes, other sensors ignore it.
+    pub fn with_soil_calibration(mut self, calibration: SoilCalibration) -> Self {
+        self.sensor = match self.sensor {
+            Sensor::Soil(probe) => Sensor::Soil(probe.with_calibration(calibration)),
+            sensor => sensor,
+        };
+        self
+    }
+
+    /// Turns the automatic baseline correction of CO2 sensors on or off, other sensors ignore it.
+    pub fn with_self_calibration(mut self, enabled: bool) -> Self {
+        self.sensor = match self.sensor {
+            Sensor::Scd4x(scd4x) => Sensor::Scd4x(scd4x.with_self_calibration(enabled)),
+            Sensor::Mhz19(mhz19) => Sensor::Mhz19(mhz19.with_self_calibration(enabled)),
+            sensor => sensor,
+        };
+        self
+    }
+
+    /// Sets the light source that the illuminance of light sensors is converted to PPFD for.
+    pub fn with_spectrum(mut self, spectrum: Spectrum) -> Self {
+        self.spectrum = spectrum;
+        self
+    }
+
+    /// Sets how much warmer than the air leaves are for the leaf VPD, negative when they are cooler.
+    pub fn with_leaf_temperature_offset(mut self, offset: f32) -> Self {
+        self.leaf_temperature_offset = offset;
+        self
+    }
+
+    /// Sets the corrections of the measured quantities, which the derived ones are computed from.
+    pub fn with_corrections(mut self, corrections: Corrections) -> Self {
+        self.set_corrections(corrections);
+        self
+    }
+
+    /// Replaces the corrections, e.g. after a new calibration point was captured.
+    pub fn set_corrections(&mut self, corrections: Corrections) {
+        self.corrections = corrections;
+    }
+
+    /// Pulses the heater whenever the humidity reaches `humidity`, for sensors with a heater.
+    pub fn with_condensation_humidity(mut self, humidity: f32) -> Self {
+        self.condensation_humidity = Some(humidity);
+        self
+    }
+
+    /// The humidity at which the heater is pulsed, if any.
+    pub fn condensation_humidity(&self) -> Option<f32> {
+        self.condensation_humidity
+    }
+
+    /// Creates a validator for the readings of this sensor.
+    pub fn validator(&self) -> Validator {
+        let validator = Validator::new(self.validation_policy);
+        match &self.sensor {
+            Sensor::Dht(dht) => validator
+                .with_range(Quantity::Temperature, dht.model().temperature_range)
+                .with_range(Quantity::Humidity, dht.model().humidity_range),
+            Sensor::Iio(dht) => validator
+                .with_range(Quantity::Temperature, dht.model().temperature_range)
+                .with_range(Quantity::Humidity, dht.model().humidity_range),
+            Sensor::Bme280(_) => validator
+                .with_range(Quantity::Temperature, bme280::TEMPERATURE_RANGE)
+                .with_range(Quantity::Humidity, bme280::HUMIDITY_RANGE)
+                .with_range(Quantity::Pressure, bme280::PRESSURE_RANGE),
+            Sensor::Sht(_) => validator
+                .with_range(Quantity::Temperature, sht::TEMPERATURE_RANGE)
+                .with_range(Quantity::Humidity, sht::HUMIDITY_RANGE),
+            Sensor::Ds18b20(_) => {
+                validator.with_range(Quantity::Temperature, ds18b20::TEMPERATURE_RANGE)
+            }
+            Sensor::Soil(_) => {
+                validator.with_range(Quantity::SoilMoisture, soil::SOIL_MOISTURE_RANGE)
+            }
+            Sensor::Scd4x(_) => validator
+                .with_range(Quantity::Temperature, scd4x::TEMPERATURE_RANGE)
+                .with_range(Quantity::Humidity, scd4x::HUMIDITY_RANGE)
+                .with_range(Quantity::Co2, scd4x::CO2_RANGE),
+            Sensor::Mhz19(_) => validator.with_range(Quantity::Co2, mhz19::CO2_RANGE),
+            Sensor::Bh1750(_) => {
+                validator.with_range(Quantity::Illuminance, bh1750::ILLUMINANCE_RANGE)
+            }
+            Sensor::Tsl2591(_) => {
+                validator.with_range(Quantity::Illuminance, tsl2591::ILLUMINANCE_RANGE)
+            }
+        }
+    }
+
+    /// The id identifying the sensor in errors and the API.
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn read(&mut self) -> core::result::Result<Reading, SensorError> {
+        let result = match self.sensor {
+            Sensor::Dht(ref mut dht) => measure(dht, &mut self.delay, &self.retry_policy),
+            Sensor::Iio(ref mut dht) => measure(dht, &mut self.delay, &self.retry_policy),
+            Sensor::Bme280(ref mut bme280) => measure(bme280, &mut self.delay, &self.retry_policy),
+            Sensor::Sht(ref mut sht) => measure(sht, &mut self.delay, &self.retry_policy),
+            Sensor::Ds18b20(ref mut probe) => measure(probe, &mut self.delay, &self.retry_policy),
+            Sensor::Soil(ref mut probe) => measure(probe, &mut self.delay, &self.retry_policy),
+            Sensor::Scd4x(ref mut scd4x) => measure(scd4x, &mut self.delay, &self.retry_policy),
+            Sensor::Mhz19(ref mut mhz19) => measure(mhz19, &mut self.delay, &self.retry_policy),
+            Sensor::Bh1750(ref mut bh1750) => measure(bh1750, &mut self.delay, &self.retry_policy),
+            Sensor::Tsl2591(ref mut tsl2591) => {
+                measure(tsl2591, &mut self.delay, &self.retry_policy)
+            }
+        };
+        result
+            .map(|mut reading| {
+                self.derive(&mut reading.result);
+                reading
+            })
+            .map_err(|(error, attempts)| self.error(error, attempts))
+    }
+
+    /// Corrects the measured quantities and adds the ones computed from them, such as the PPFD and the VPD.
+    fn derive(&self, measurement: &mut Measurement) {
+        correction::apply(&self.corrections, measurement);
+        let ppfd = measurement
+            .get(Quantity::Illuminance)
+            .map(|lux| lux * self.spectrum.ppfd_per_lux());
+        measurement.set(Quantity::Ppfd, ppfd);
+        climate::derive(measurement, self.leaf_temperature_offset);
+    }
+
+    /// Performs a single read of the sensor, recording the raw frame and timings.
+    pub fn diagnose(&mut self) -> core::result::Result<Diagnostics, SensorError> {
+        let result = match self.sensor {
+            Sensor::Dht(ref mut dht) => {
+                let ready_in = dht.ready_in(&self.delay);
+                wait(&mut self.delay, ready_in);
+                dht.diagnose(&mut self.delay)
             }
+            // The kernel driver keeps the raw frame to itself
+            Sensor::Iio(_)
+            | Sensor::Bme280(_)
+            | Sensor::Sht(_)
+            | Sensor::Ds18b20(_)
+            | Sensor::Soil(_)
+            | Sensor::Scd4x(_)
+            | Sensor::Mhz19(_)
+            | Sensor::Bh1750(_)
+            | Sensor::Tsl2591(_) => Err(Error::Unsupported("diagnostic reads")),
+        };
+        result.map_err(|error| self.error(error, 1))
+    }
+
+    /// Pulses the heater to evaporate condensation, readings are too warm for a while after.
+    pub fn heat(&mut self) -> core::result::Result<(), SensorError> {
+        let result = match self.sensor {
+            Sensor::Sht(ref mut sht) => sht.heat(&mut self.delay),
+            Sensor::Dht(_)
+            | Sensor::Iio(_)
+            | Sensor::Bme280(_)
+            | Sensor::Ds18b20(_)
+            | Sensor::Soil(_)
+            | Sensor::Scd4x(_)
+            | Sensor::Mhz19(_)
+            | Sensor::Bh1750(_)
+            | Sensor::Tsl2591(_) => Err(Error::Unsupported("heater control")),
+        };
+        result.map_err(|error| self.error(error, 1))
+    }
+
+    /// Attaches the id of this sensor to `error`.
+    pub fn error(&self, error: Error, attempts: u16) -> SensorError {
+        SensorError {
+            sensor: self.id.clone(),
+            attempts,
+            error,
+        }
+    }
+}
+
+/// Measures until an attempt succeeds or the retry policy gives up,
+/// returning the last error and the number of attempts.
+fn measure<S: Device>(
+    sensor: &mut S,
+    delay: &mut Delay,
+    policy: &RetryPolicy,
+) -> core::result::Result<Reading, (Error, u16)> {
+    let started_us = delay.now_us();
+    let ready_in = |sensor: &S, delay: &Delay| {
+        if policy.respect_min_interval {
+            sensor.ready_in(delay)
+        } else {
+            Duration::ZERO
         }
+    };
+
+    let mut attempts = 0;
+    loop {
+        let until_ready = ready_in(sensor, delay);
+        wait(delay, until_ready);
+        attempts += 1;
+
+        let error = match sensor.perform_measurement(delay) {
+            Ok(result) => {
+                return Ok(Reading {
+                    result,
+                    time: chrono::Utc::now(),
+                    attempts,
+                    suspect: None,
+                })
+            }
+            Err(e) => e,
+        };
+
+        let backoff = policy.backoff_delay(attempts);
+        let elapsed = Duration::from_micros(delay.now_us() - started_us);
+        let next_attempt = elapsed + backoff.max(ready_in(sensor, delay));
+        if attempts >= policy.max_attempts || next_attempt > policy.budget() {
+            return Err((error, attempts));
+        }
+        wait(delay, backoff);
+    }
+}
+
+/// Waits at least `duration`, rounded up to whole milliseconds.
+fn wait(delay: &mut Delay, duration: Duration) {
+    let mut remaining_ms = duration.as_micros().div_ceil(1_000);
+    while remaining_ms > 0 {
+        let ms = remaining_ms.min(u128::from(u16::MAX)) as u16;
+        delay.delay_ms(ms);
+        remaining_ms -= u128::from(ms);
+    }
+}
+
+#[cfg(all(test, feature = "sim"))]
+mod tests {
+    use super::*;
+    use crate::humidity::{
+        retry::Backoff,
+        sim::{SimClock, SimDelay},
+    };
+
+    /// How long each attempt takes (in milliseconds)
+    const READ_MS: u16 = 5;
+
+    /// A sensor failing its first reads, which records when each attempt started.
+    struct Flaky {
+        failures: u16,
+        min_interval: Duration,
+        last_us: Option<u64>,
+        attempts_ms: Vec<u64>,
+    }
+
+    impl Flaky {
+        fn new(failures: u16) -> Self {
+            Flaky {
+                failures,
+                min_interval: Duration::ZERO,
+                last_us: None,
+                attempts_ms: Vec::new(),
+            }
+        }
+
+        fn with_min_interval(mut self, min_interval: Duration) -> Self {
+            self.min_interval = min_interval;
+            self
+        }
+    }
+
+    impl Device for Flaky {
+        fn perform_measurement<D: DelayMs<u16> + Clock>(
+            &mut self,
+            delay: &mut D,
+        ) -> Result<Measurement> {
+            self.last_us = Some(delay.now_us());
+            self.attempts_ms.push(delay.now_us() / 1_000);
+            delay.delay_ms(READ_MS);
+            if self.failures > 0 {
+                self.failures -= 1;
+                return Err(Error::NoResponse);
+            }
+            Ok(Measurement::new().with(Quantity::Temperature, 21.0))
+        }
+
+        fn ready_in<C: Clock>(&self, clock: &C) -> Duration {
+            match self.last_us {
+                Some(last_us) => self
+                    .min_interval
+                    .saturating_sub(Duration::from_micros(clock.now_us() - last_us)),
+                None => Duration::ZERO,
+            }
+        }
+    }
+
+    fn policy(backoff: Backoff, max_attempts: u16) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts,
+            backoff,
+            delay_ms: 100,
+            max_delay_ms: 5_000,
+            respect_min_interval: true,
+            budget_ms: 60_000,
+        }
+    }
+
+    fn delay(clock: &SimClock) -> Delay {
+        Delay::Simulated(SimDelay::clone(&clock.delay()))
+    }
+
+    #[test]
+    fn retries_after_a_fixed_backoff() {
+        let clock = SimClock::new();
+        let mut sensor = Flaky::new(2);
+        let reading = measure(&mut sensor, &mut delay(&clock), &policy(Backoff::Fixed, 3)).unwrap();
+
+        assert_eq!(reading.attempts, 3);
+        assert_eq!(sensor.attempts_ms, [0, 105, 210]);
+    }
+
+    #[test]
+    fn retries_after_an_exponential_backoff() {
+        let clock = SimClock::new();
+        let mut sensor = Flaky::new(3);
+        let reading = measure(
+            &mut sensor,
+            &mut delay(&clock),
+            &policy(Backoff::Exponential, 4),
+        )
+        .unwrap();
+
+        assert_eq!(reading.attempts, 4);
+        assert_eq!(sensor.attempts_ms, [0, 105, 310, 715]);
+    }
+
+    #[test]
+    fn gives_up_after_the_last_attempt() {
+        let clock = SimClock::new();
+        let mut sensor = Flaky::new(u16::MAX);
+        let result = measure(&mut sensor, &mut delay(&clock), &policy(Backoff::Fixed, 3));
+
+        assert!(matches!(result, Err((Error::NoResponse, 3))), "{result:?}");
+        assert_eq!(sensor.attempts_ms.len(), 3);
+        // No backoff is waited out after the last attempt
+        assert_eq!(clock.now_us() / 1_000, 215);
+    }
+
+    #[test]
+    fn gives_up_when_the_next_attempt_would_overrun_the_budget() {
+        for (budget_ms, attempts) in [(2_200, 3), (3_015, 4)] {
+            let clock = SimClock::new();
+            let mut sensor = Flaky::new(u16::MAX);
+            let policy = RetryPolicy {
+                delay_ms: 1_000,
+                budget_ms,
+                ..policy(Backoff::Fixed, 10)
+            };
+            let result = measure(&mut sensor, &mut delay(&clock), &policy);
+
+            // Attempts start 1005ms apart, each is over once the 5ms read is done
+            assert!(
+                matches!(result, Err((_, n)) if n == attempts),
+                "{budget_ms}: {result:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn counts_the_sensors_minimum_interval_against_the_budget() {
+        let clock = SimClock::new();
+        let mut sensor = Flaky::new(u16::MAX).with_min_interval(Duration::from_secs(2));
+        let policy = RetryPolicy {
+            budget_ms: 3_000,
+            ..policy(Backoff::Fixed, 10)
+        };
+        let result = measure(&mut sensor, &mut delay(&clock), &policy);
+
+        // The third attempt could only start 4s in, past the budget
+        assert!(matches!(result, Err((_, 2))), "{result:?}");
+        assert_eq!(sensor.attempts_ms, [0, 2_000]);
+    }
+
+    #[test]
+    fn waits_out_the_minimum_interval_between_attempts() {
+        let clock = SimClock::new();
+        let mut sensor = Flaky::new(1).with_min_interval(Duration::from_secs(2));
+        // A read that just finished on the previous tick
+        sensor.last_us = Some(0);
+        clock.advance_us(500_000);
+
+        let reading = measure(&mut sensor, &mut delay(&clock), &policy(Backoff::Fixed, 3)).unwrap();
+        assert_eq!(reading.attempts, 2);
+        assert_eq!(sensor.attempts_ms, [2_000, 4_000]);
+    }
+
+    #[test]
+    fn retries_within_the_minimum_interval_when_told_to() {
+        let clock = SimClock::new();
+        let mut sensor = Flaky::new(2).with_min_interval(Duration::from_secs(2));
+        let policy = RetryPolicy {
+            respect_min_interval: false,
+            ..policy(Backoff::Fixed, 3)
+        };
+
+        let reading = measure(&mut sensor, &mut delay(&clock), &policy).unwrap();
+        assert_eq!(reading.attempts, 3);
+        assert_eq!(sensor.attempts_ms, [0, 105, 210]);
     }
 }
@@ -0,0 +1,287 @@
+//! Rust driver for the ams TSL2591 ambient light sensor, generic over `embedded-hal` I2C buses.
+//!
+//! The sensor integrates a full spectrum and an infrared channel, and the illuminance is
+//! derived from both with the coefficients of the manufacturer's sample code. Every reading
+//! powers the sensor up for a single 100ms integration. The gain follows the light: it is
+//! lowered while a channel saturates and raised while the counts are too low to be precise,
+//! and kept for the next reading.
+//!
+#![deny(unsafe_code)]
+
+use embedded_hal::blocking::delay::{DelayMs, DelayUs};
+
+use super::{
+    bus::{BusError, I2cBus},
+    device::{Device, Measurement},
+    pin::Clock,
+    quantity::Quantity,
+    Error, Result,
+};
+
+/// The only address the sensor answers on
+pub const DEFAULT_ADDRESS: u8 = 0x29;
+
+/// Measurement range in lux
+pub const ILLUMINANCE_RANGE: (f32, f32) = (0.0, 88_000.0);
+
+/// Command bit selecting a register for a normal transaction
+pub(super) const COMMAND: u8 = 0xa0;
+pub(super) const REG_ENABLE: u8 = 0x00;
+pub(super) const REG_CONFIG: u8 = 0x01;
+pub(super) const REG_ID: u8 = 0x12;
+pub(super) const REG_STATUS: u8 = 0x13;
+pub(super) const REG_DATA: u8 = 0x14;
+
+pub(super) const CHIP_ID: u8 = 0x50;
+/// Enable bits powering the oscillator and the light sensing
+pub(super) const ENABLE_ON: u8 = 0x03;
+/// Status bit set once an integration completed
+pub(super) const STATUS_VALID: u8 = 0x01;
+/// Bits of the config register selecting the gain
+#[cfg(feature = "sim")]
+pub(super) const GAIN_MASK: u8 = 0x30;
+
+/// Config bits and multiplier of each gain, from low to maximum
+pub(super) const GAINS: [(u8, f32); 4] = [(0x00, 1.0), (0x10, 25.0), (0x20, 428.0), (0x30, 9876.0)];
+/// Index of the medium gain, which suits indoor light
+const MEDIUM_GAIN: usize = 1;
+
+/// Length of each integration (in milliseconds), selected by leaving the time bits cleared
+pub(super) const INTEGRATION_MS: u16 = 100;
+/// Count at which the channels saturate with 100ms integrations
+pub(super) const MAX_COUNT: u16 = 36863;
+/// Counts per lux at unity gain and 1ms integration are the reciprocal of this
+pub(super) const LUX_COEFFICIENT: f32 = 408.0;
+/// Full spectrum count below which the gain is raised
+const LOW_COUNT: u16 = 200;
+/// How often the status is polled after the integration time has passed
+const STATUS_POLLS: u16 = 10;
+
+/// Derives the illuminance in lux from the counts of both channels at the given gain.
+pub fn lux(full: u16, infrared: u16, gain: f32) -> f32 {
+    // The full spectrum includes the infrared, so more infrared is noise in the dark
+    if infrared >= full {
+        return 0.0;
+    }
+    let (full, infrared) = (f32::from(full), f32::from(infrared));
+    let counts_per_lux = f32::from(INTEGRATION_MS) * gain / LUX_COEFFICIENT;
+    (full - infrared) * (1.0 - infrared / full) / counts_per_lux
+}
+
+/// A TSL2591 device.
+pub struct Tsl2591<B> {
+    /// The bus the sensor is connected to.
+    bus: B,
+    /// The address of the sensor on the bus.
+    address: u8,
+    /// Index of the gain in [`GAINS`] used by the next integration.
+    gain: usize,
+    /// Whether the chip id was checked.
+    identified: bool,
+}
+
+impl<B> Tsl2591<B> {
+    /// Creates a device at `address` on the given bus, starting at medium gain.
+    pub fn new(bus: B, address: u8) -> Self {
+        Tsl2591 {
+            bus,
+            address,
+            gain: MEDIUM_GAIN,
+            identified: false,
+        }
+    }
+}
+
+impl<B> Tsl2591<B>
+where
+    B: I2cBus,
+    Error: From<BusError<B>>,
+{
+    /// Integrates until the gain suits the light and converts the counts.
+    pub fn perform_measurement<D: DelayMs<u16>>(&mut self, delay: &mut D) -> Result<Measurement> {
+        if !self.identified {
+            let id = self.read::<1>(REG_ID)?[0];
+            if id != CHIP_ID {
+                return Err(Error::UnknownChip { id });
+            }
+            self.identified = true;
+        }
+
+        let mut illuminance = None;
+        for _ in 0..GAINS.len() {
+            let (full, infrared) = self.integrate(delay)?;
+            if full >= MAX_COUNT || infrared >= MAX_COUNT {
+                if self.gain == 0 {
+                    return Err(Error::Saturated);
+                }
+                self.gain -= 1;
+                continue;
+            }
+
+            illuminance = Some(lux(full, infrared, GAINS[self.gain].1));
+            if full < LOW_COUNT && self.gain < GAINS.len() - 1 {
+                self.gain += 1;
+                continue;
+            }
+            break;
+        }
+
+        Ok(Measurement::new().with(Quantity::Illuminance, illuminance.ok_or(Error::Saturated)?))
+    }
+
+    /// Runs a single integration at the current gain, returning the full spectrum and infrared counts.
+    fn integrate<D: DelayMs<u16>>(&mut self, delay: &mut D) -> Result<(u16, u16)> {
+        // The gain only applies to integrations started after it is written
+        self.write(REG_CONFIG, GAINS[self.gain].0)?;
+        self.write(REG_ENABLE, ENABLE_ON)?;
+        delay.delay_ms(INTEGRATION_MS);
+        let result = self.wait_for_integration(delay).and_then(|()| {
+            let data = self.read::<4>(REG_DATA)?;
+            Ok((
+                u16::from_le_bytes([data[0], data[1]]),
+                u16::from_le_bytes([data[2], data[3]]),
+            ))
+        });
+        // Powers down between readings
+        self.write(REG_ENABLE, 0)?;
+        result
+    }
+
+    fn wait_for_integration<D: DelayMs<u16>>(&mut self, delay: &mut D) -> Result<()> {
+        for _ in 0..STATUS_POLLS {
+            if self.read::<1>(REG_STATUS)?[0] & STATUS_VALID != 0 {
+                return Ok(());
+            }
+            delay.delay_ms(10);
+        }
+        Err(Error::NotReady)
+    }
+
+    fn read<const N: usize>(&mut self, register: u8) -> Result<[u8; N]> {
+        let mut buffer = [0; N];
+        self.bus
+            .write_read(self.address, &[COMMAND | register], &mut buffer)?;
+        Ok(buffer)
+    }
+
+    fn write(&mut self, register: u8, value: u8) -> Result<()> {
+        self.bus.write(self.address, &[COMMAND | register, value])?;
+        Ok(())
+    }
+}
+
+impl<B> Device for Tsl2591<B>
+where
+    B: I2cBus,
+    Error: From<BusError<B>>,
+{
+    fn perform_measurement<D: DelayUs<u16> + DelayMs<u16> + Clock>(
+        &mut self,
+        delay: &mut D,
+    ) -> Result<Measurement> {
+        self.perform_measurement(delay)
+    }
+}
+
+#[cfg(all(test, feature = "sim"))]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::humidity::{
+        sim::SimClock,
+        sim_i2c::{SimBus, SimTsl2591, Spy},
+    };
+
+    /// The commands written to a sensor.
+    type Writes = Arc<Mutex<Vec<Vec<u8>>>>;
+
+    /// A sensor under the illuminance set through the returned handle, with the log of its writes.
+    fn sensor() -> (Tsl2591<SimBus>, Arc<Mutex<f32>>, Writes) {
+        let light = Arc::new(Mutex::new(0.0));
+        let sample = {
+            let light = light.clone();
+            move || Measurement::new().with(Quantity::Illuminance, *light.lock().unwrap())
+        };
+        let (spy, writes) = Spy::new(SimTsl2591::new(sample));
+        let bus = SimBus::new().with_device(DEFAULT_ADDRESS, spy);
+        (Tsl2591::new(bus, DEFAULT_ADDRESS), light, writes)
+    }
+
+    /// The gain bits written before each integration, emptying the log.
+    fn gains(writes: &Mutex<Vec<Vec<u8>>>) -> Vec<u8> {
+        writes
+            .lock()
+            .unwrap()
+            .drain(..)
+            .filter_map(|write| match *write {
+                [register, gain] if register == COMMAND | REG_CONFIG => Some(gain),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn assert_lux(result: Result<Measurement>, lux: f32) {
+        let illuminance = result.unwrap().get(Quantity::Illuminance).unwrap();
+        assert!(
+            (illuminance - lux).abs() <= lux * 0.01,
+            "{illuminance} != {lux}"
+        );
+    }
+
+    #[test]
+    fn derives_lux_from_both_channels() {
+        assert_eq!(lux(0, 0, 25.0), 0.0);
+        // 100ms at gain 25 counts 6.127 per lux, the infrared share takes off (1 - 0.25)²
+        assert!((lux(1000, 250, 25.0) - 91.8).abs() < 0.01);
+        assert!((lux(1000, 250, 1.0) - 2295.0).abs() < 0.1);
+        // Infrared only
+        assert_eq!(lux(100, 100, 25.0), 0.0);
+        assert_eq!(lux(100, 200, 25.0), 0.0);
+    }
+
+    #[test]
+    fn reads_at_medium_gain() {
+        let (mut tsl2591, light, writes) = sensor();
+        *light.lock().unwrap() = 300.0;
+
+        assert_lux(
+            tsl2591.perform_measurement(&mut SimClock::new().delay()),
+            300.0,
+        );
+        assert_eq!(gains(&writes), [GAINS[MEDIUM_GAIN].0]);
+    }
+
+    #[test]
+    fn raises_the_gain_in_dim_light() {
+        let (mut tsl2591, light, writes) = sensor();
+        *light.lock().unwrap() = 0.5;
+
+        assert_lux(
+            tsl2591.perform_measurement(&mut SimClock::new().delay()),
+            0.5,
+        );
+        assert_eq!(gains(&writes), [0x10, 0x20, 0x30]);
+    }
+
+    #[test]
+    fn lowers_the_gain_while_saturated_and_keeps_it() {
+        let (mut tsl2591, light, writes) = sensor();
+        let mut delay = SimClock::new().delay();
+        *light.lock().unwrap() = 20_000.0;
+
+        assert_lux(tsl2591.perform_measurement(&mut delay), 20_000.0);
+        assert_eq!(gains(&writes), [0x10, 0x00]);
+        assert_lux(tsl2591.perform_measurement(&mut delay), 20_000.0);
+        assert_eq!(gains(&writes), [0x00]);
+    }
+
+    #[test]
+    fn reports_saturation_at_the_lowest_gain() {
+        let (mut tsl2591, light, _) = sensor();
+        *light.lock().unwrap() = 100_000.0;
+
+        let result = tsl2591.perform_measurement(&mut SimClock::new().delay());
+        assert!(matches!(result, Err(Error::Saturated)), "{result:?}");
+    }
+}
@@ -0,0 +1,414 @@
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+
+use serde::{Deserialize, Serialize};
+
+use super::{Error, Quantity, Reading};
+
+/// What happens to readings that fail validation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    /// Keep the reading but mark it as suspect.
+    Tag,
+    /// Discard the reading and report it as an error.
+    Drop,
+}
+
+/// Why a reading was considered implausible.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Suspicion {
+    /// A value is outside what the sensor model can measure.
+    OutOfRange,
+    /// A value changed faster than the climate plausibly can.
+    Spike,
+}
+
+/// Limits that readings have to satisfy before they are recorded.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, clap::Args)]
+#[serde(default)]
+#[command(about = None, long_about = None, next_help_heading = "Validation")]
+pub struct ValidationPolicy {
+    /// What happens to implausible readings
+    #[arg(long = "validation-action", value_enum, default_value_t = Action::Drop)]
+    pub action: Action,
+    /// Fastest plausible temperature change, in degrees Celsius per minute
+    #[arg(long = "validation-max-temperature-rate", default_value_t = 2.0)]
+    pub max_temperature_rate: f32,
+    /// Fastest plausible humidity change, in percent per minute
+    #[arg(long = "validation-max-humidity-rate", default_value_t = 10.0)]
+    pub max_humidity_rate: f32,
+    /// Temperature change always allowed between readings to absorb sensor noise
+    #[arg(long = "validation-temperature-tolerance", default_value_t = 1.0)]
+    pub temperature_tolerance: f32,
+    /// Humidity change always allowed between readings to absorb sensor noise
+    #[arg(long = "validation-humidity-tolerance", default_value_t = 5.0)]
+    pub humidity_tolerance: f32,
+    /// Spikes in a row after which the new values are trusted, 0 never trusts them
+    #[arg(long = "validation-trust-after", default_value_t = 3)]
+    pub trust_after: u16,
+}
+
+impl Default for ValidationPolicy {
+    fn default() -> Self {
+        ValidationPolicy {
+            action: Action::Drop,
+            max_temperature_rate: 2.0,
+            max_humidity_rate: 10.0,
+            temperature_tolerance: 1.0,
+            humidity_tolerance: 5.0,
+            trust_after: 3,
+        }
+    }
+}
+
+/// How many readings failed validation.
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct ValidationCounts {
+    /// Readings that were tagged or dropped.
+    pub implausible: u64,
+    /// Readings that were dropped.
+    pub dropped: u64,
+}
+
+/// Counters shared between a [`Validator`] and whoever reports on it.
+#[derive(Debug, Default)]
+pub struct ValidationStats {
+    implausible: AtomicU64,
+    dropped: AtomicU64,
+}
+
+impl ValidationStats {
+    pub fn counts(&self) -> ValidationCounts {
+        ValidationCounts {
+            implausible: self.implausible.load(Ordering::Relaxed),
+            dropped: self.dropped.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Checks readings against the sensor's range and the recent history before they are recorded.
+pub struct Validator {
+    policy: ValidationPolicy,
+    /// The range of each quantity the sensor can measure.
+    ranges: [Option<(f32, f32)>; Quantity::ALL.len()],
+    /// The last reading accepted as plausible.
+    baseline: Option<Reading>,
+    /// The last reading while there is no baseline yet.
+    candidate: Option<Reading>,
+    /// Spikes in a row since the baseline.
+    spikes: u16,
+    stats: Arc<ValidationStats>,
+}
+
+impl Validator {
+    /// Creates a validator that only checks for spikes, the ranges are added for the quantities
+    /// the sensor measures.
+    pub fn new(policy: ValidationPolicy) -> Self {
+        Validator {
+            policy,
+            ranges: [None; Quantity::ALL.len()],
+            baseline: None,
+            candidate: None,
+            spikes: 0,
+            stats: Arc::default(),
+        }
+    }
+
+    /// Checks values of `quantity` against the sensor's range.
+    pub fn with_range(mut self, quantity: Quantity, range: (f32, f32)) -> Self {
+        self.ranges[quantity.index()] = Some(range);
+        self
+    }
+
+    pub fn stats(&self) -> Arc<ValidationStats> {
+        self.stats.clone()
+    }
+
+    /// Returns the reading, tagged if it is implausible, or the reason it was dropped.
+    pub fn check(&mut self, reading: Reading) -> Result<Reading, Error> {
+        if let Err(error) = self.in_range(&reading) {
+            return self.reject(reading, error, Suspicion::OutOfRange);
+        }
+
+        let Some(baseline) = self.baseline else {
+            // Nothing vouches for the first reading yet, so it only becomes the baseline
+            // once the next one agrees with it
+            match self.candidate.replace(reading) {
+                Some(candidate) if self.changes(&candidate, &reading).is_ok() => {
+                    self.accept(reading);
+                }
+                _ => {}
+            }
+            return Ok(reading);
+        };
+
+        match self.changes(&baseline, &reading) {
+            Ok(()) => Ok(self.accept(reading)),
+            Err(error) => {
+                // A change that persists is real, e.g. a heater switching on
+                self.spikes += 1;
+                if self.policy.trust_after > 0 && self.spikes >= self.policy.trust_after {
+                    return Ok(self.accept(reading));
+                }
+                self.reject(reading, error, Suspicion::Spike)
+            }
+        }
+    }
+
+    fn accept(&mut self, reading: Reading) -> Reading {
+        self.baseline = Some(reading);
+        self.spikes = 0;
+        reading
+    }
+
+    fn reject(
+        &self,
+        mut reading: Reading,
+        error: Error,
+        suspicion: Suspicion,
+    ) -> Result<Reading, Error> {
+        self.stats.implausible.fetch_add(1, Ordering::Relaxed);
+        match self.policy.action {
+            Action::Tag => {
+                reading.suspect = Some(suspicion);
+                Ok(reading)
+            }
+            Action::Drop => {
+                self.stats.dropped.fetch_add(1, Ordering::Relaxed);
+                Err(error)
+            }
+        }
+    }
+
+    fn in_range(&self, reading: &Reading) -> Result<(), Error> {
+        for value in reading.result.values() {
+            if let Some(range) = self.ranges[value.quantity.index()] {
+                check_range(value.quantity, value.value, range)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks the change from `previous` to `reading` against the plausible rates.
+    fn changes(&self, previous: &Reading, reading: &Reading) -> Result<(), Error> {
+        // Watering, venting or lights switching change the other quantities within minutes,
+        // so only temperature and humidity are checked for spikes
+        let minutes = (reading.time - previous.time).num_milliseconds().max(0) as f32 / 60_000.0;
+        let measurement = reading.result;
+        if let (Some(temperature), Some(previous)) = (
+            measurement.get(Quantity::Temperature),
+            previous.result.get(Quantity::Temperature),
+        ) {
+            check_change(
+                Quantity::Temperature,
+                temperature - previous,
+                self.policy.temperature_tolerance + self.policy.max_temperature_rate * minutes,
+            )?;
+        }
+        if let (Some(humidity), Some(previous)) = (
+            measurement.get(Quantity::Humidity),
+            previous.result.get(Quantity::Humidity),
+        ) {
+            check_change(
+                Quantity::Humidity,
+                humidity - previous,
+                self.policy.humidity_tolerance + self.policy.max_humidity_rate * minutes,
+            )?;
+        }
+        Ok(())
+    }
+}
+
+fn check_range(quantity: Quantity, value: f32, (min, max): (f32, f32)) -> Result<(), Error> {
+    // DHT sensors that lose the bus read humidity as 0%, which air never is
+    if quantity == Quantity::Humidity && value == 0.0 {
+        Err(Error::Dropout { quantity })
+    } else if (min..=max).contains(&value) {
+        Ok(())
+    } else {
+        Err(Error::OutOfRange {
+            quantity,
+            value,
+            min,
+            max,
+        })
+    }
+}
+
+fn check_change(quantity: Quantity, change: f32, max_change: f32) -> Result<(), Error> {
+    if change.abs() <= max_change {
+        Ok(())
+    } else {
+        Err(Error::Spike {
+            quantity,
+            change,
+            max_change,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{DateTime, Duration, Utc};
+
+    use super::*;
+    use crate::humidity::Measurement;
+
+    fn reading(minutes: i64, temperature: f32, humidity: f32) -> Reading {
+        Reading {
+            result: Measurement::new()
+                .with(Quantity::Temperature, temperature)
+                .with(Quantity::Humidity, humidity),
+            time: DateTime::<Utc>::UNIX_EPOCH + Duration::minutes(minutes),
+            attempts: 1,
+            suspect: None,
+        }
+    }
+
+    fn validator(action: Action) -> Validator {
+        Validator::new(ValidationPolicy {
+            action,
+            ..ValidationPolicy::default()
+        })
+        .with_range(Quantity::Temperature, (-40.0, 80.0))
+        .with_range(Quantity::Humidity, (0.0, 100.0))
+    }
+
+    /// A validator whose baseline is 20°C and 50% at minute 1.
+    fn settled(action: Action) -> Validator {
+        let mut validator = validator(action);
+        validator.check(reading(0, 20.0, 50.0)).unwrap();
+        validator.check(reading(1, 20.0, 50.0)).unwrap();
+        validator
+    }
+
+    #[test]
+    fn drops_values_outside_the_range() {
+        let mut validator = validator(Action::Drop);
+        assert!(validator.check(reading(0, 20.0, 100.0)).is_ok());
+        assert!(matches!(
+            validator.check(reading(1, 81.0, 50.0)),
+            Err(Error::OutOfRange {
+                quantity: Quantity::Temperature,
+                ..
+            })
+        ));
+        assert!(matches!(
+            validator.check(reading(2, 20.0, 100.5)),
+            Err(Error::OutOfRange {
+                quantity: Quantity::Humidity,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn drops_humidity_dropouts() {
+        let mut validator = settled(Action::Drop);
+        assert!(matches!(
+            validator.check(reading(2, 20.0, 0.0)),
+            Err(Error::Dropout {
+                quantity: Quantity::Humidity
+            })
+        ));
+        // Not a dropout, but still far from the baseline
+        assert!(matches!(
+            validator.check(reading(3, 20.0, 0.1)),
+            Err(Error::Spike { .. })
+        ));
+    }
+
+    #[test]
+    fn tags_implausible_readings_when_told_to() {
+        let mut validator = settled(Action::Tag);
+        let out_of_range = validator.check(reading(2, 20.0, 0.0)).unwrap();
+        assert_eq!(out_of_range.suspect, Some(Suspicion::OutOfRange));
+        let spike = validator.check(reading(3, 30.0, 50.0)).unwrap();
+        assert_eq!(spike.suspect, Some(Suspicion::Spike));
+        let plausible = validator.check(reading(4, 20.5, 52.0)).unwrap();
+        assert_eq!(plausible.suspect, None);
+    }
+
+    #[test]
+    fn allows_changes_growing_with_the_time_between_readings() {
+        let mut validator = settled(Action::Drop);
+        // 1°C tolerance and 2°C per minute
+        assert!(matches!(
+            validator.check(reading(2, 23.1, 50.0)),
+            Err(Error::Spike {
+                quantity: Quantity::Temperature,
+                ..
+            })
+        ));
+        assert!(validator.check(reading(3, 24.9, 50.0)).is_ok());
+        // 5% tolerance and 10% per minute, from the last accepted reading
+        assert!(matches!(
+            validator.check(reading(4, 24.9, 65.1)),
+            Err(Error::Spike {
+                quantity: Quantity::Humidity,
+                ..
+            })
+        ));
+        assert!(validator.check(reading(5, 24.9, 74.9)).is_ok());
+    }
+
+    #[test]
+    fn does_not_measure_spikes_from_an_unconfirmed_first_reading() {
+        let mut validator = validator(Action::Tag);
+        let readings = [
+            reading(0, 20.0, 5.0),
+            reading(1, 20.0, 50.0),
+            reading(2, 20.0, 51.0),
+        ];
+        for reading in readings {
+            assert_eq!(validator.check(reading).unwrap().suspect, None);
+        }
+        // The last two agreed, so spikes are now measured from them
+        let spike = validator.check(reading(3, 20.0, 80.0)).unwrap();
+        assert_eq!(spike.suspect, Some(Suspicion::Spike));
+    }
+
+    #[test]
+    fn trusts_a_change_that_persists() {
+        let mut validator = settled(Action::Drop);
+        assert!(validator.check(reading(2, 30.0, 50.0)).is_err());
+        assert!(validator.check(reading(2, 30.0, 50.0)).is_err());
+        assert!(validator.check(reading(2, 30.0, 50.0)).is_ok());
+        assert!(validator.check(reading(3, 30.0, 50.0)).is_ok());
+    }
+
+    #[test]
+    fn never_trusts_spikes_without_trust_after() {
+        let mut validator = Validator::new(ValidationPolicy {
+            trust_after: 0,
+            ..ValidationPolicy::default()
+        });
+        validator.check(reading(0, 20.0, 50.0)).unwrap();
+        validator.check(reading(1, 20.0, 50.0)).unwrap();
+        for minute in 2..10 {
+            assert!(validator.check(reading(minute, 40.0, 50.0)).is_err());
+        }
+    }
+
+    #[test]
+    fn counts_implausible_and_dropped_readings() {
+        for (action, dropped) in [(Action::Drop, 2), (Action::Tag, 0)] {
+            let mut validator = settled(action);
+            let stats = validator.stats();
+            let _ = validator.check(reading(2, 20.0, 0.0));
+            let _ = validator.check(reading(3, 30.0, 50.0));
+            let _ = validator.check(reading(4, 20.0, 50.0));
+
+            let counts = stats.counts();
+            assert_eq!(
+                (counts.implausible, counts.dropped),
+                (2, dropped),
+                "{action:?}"
+            );
+        }
+    }
+}
@@ -1,74 +1,126 @@
 mod circular;
+mod config;
+mod gpio;
 mod humidity;
 mod relay;
 mod sensor_data;
+mod sensors;
+#[cfg(feature = "sim")]
+mod sim;
 
 use anyhow::Result;
 use axum::{
-    extract::{Path, State},
+    extract::{Path, Query, State},
     http::{Method, StatusCode},
-    routing::get,
+    routing::{get, post},
     Json, Router,
 };
-use circular::Circular;
-use humidity::Update;
-use relay::{Relay, RelayBoard};
-use rppal::gpio::Gpio;
+use clap::Parser;
+use embedded_hal::digital::v2::OutputPin;
+use relay::RelayBoard;
 use sensor_data::SensorData;
+use sensors::{CalibrationError, Registry};
+use serde::{Deserialize, Serialize};
 use std::{env, future::IntoFuture, sync::Arc};
 use tokio::{
     sync::RwLock,
-    time::{interval, sleep, Duration},
+    time::{sleep, Duration},
 };
 use tower_http::cors::{Any, CorsLayer};
 
-type HumidityState = Arc<RwLock<Circular<humidity::Reading, 10>>>;
+type SensorsState = Arc<Registry>;
 type RelayState = Arc<RwLock<RelayBoard<3>>>;
 
+/// How long a diagnostic read may take, including waiting out the sensor's sampling interval
+const DIAGNOSTICS_DEADLINE: Duration = Duration::from_secs(5);
+
 // Pins
-const GPIO_HUMIDITY: u8 = 23;
 const GPIO_RELAY_1: u8 = 17;
 const GPIO_RELAY_2: u8 = 27;
 const GPIO_RELAY_3: u8 = 22;
 
-impl Update for Circular<humidity::Reading, 10> {
-    fn update(&mut self, reading: humidity::Reading) {
-        self.add(reading);
+mod options {
+    #[derive(Debug, clap::Parser)]
+    #[command()]
+    pub struct Args {
+        /// JSON file listing the sensors, defaults to a single DHT22 on pin 23
+        #[clap(long)]
+        pub config: Option<std::path::PathBuf>,
+        /// GPIO character device to use instead of the default backend, e.g. /dev/gpiochip0
+        #[cfg(feature = "gpio-cdev")]
+        #[clap(long)]
+        pub gpio_chip: Option<std::path::PathBuf>,
+        /// Run without hardware, using virtual sensors and in-memory relays
+        #[cfg(feature = "sim")]
+        #[clap(long, default_value = "false")]
+        pub simulate: bool,
+        /// JSON file describing the simulated environment
+        #[cfg(feature = "sim")]
+        #[clap(long, requires = "simulate")]
+        pub sim_model: Option<std::path::PathBuf>,
+        /// How the DHT response is sampled, `edges` needs a backend with edge events
+        #[clap(long, value_enum, default_value_t)]
+        pub dht_read_mode: crate::humidity::ReadMode,
+        #[command(flatten)]
+        pub retry_policy: crate::humidity::RetryPolicy,
+        #[command(flatten)]
+        pub validation_policy: crate::humidity::ValidationPolicy,
+        #[command(flatten)]
+        pub units: crate::humidity::Units,
     }
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
     println!("Running {}...", env::current_exe().unwrap().display());
+    let options = options::Args::parse();
+    let config = match &options.config {
+        Some(path) => config::Config::load(path)?,
+        None => config::Config::default(),
+    };
+    let backend = backend(&options, &config)?;
 
-    // humidity sensor setup
-    let humidity_tracker = humidity::Tracker::new(humidity::SensorType::Dht22, GPIO_HUMIDITY)?;
-    let humidity_state: HumidityState = Arc::new(RwLock::new(Circular::new()));
-    let update_task = humidity::start_tracking(
-        humidity_state.clone(),
-        humidity_tracker,
-        interval(Duration::from_secs(2)),
+    // sensor setup
+    let defaults = sensors::Defaults {
+        read_mode: options.dht_read_mode,
+        retry: options.retry_policy,
+        validation: options.validation_policy,
+    };
+    let sensors: SensorsState = Arc::new(
+        Registry::start(&backend, &config, options.config.as_deref(), &defaults)?
+            .with_units(options.units),
     );
 
-    let relays = Arc::new(RwLock::new(relay::RelayBoard::new([
-        GPIO_RELAY_1,
-        GPIO_RELAY_2,
-        GPIO_RELAY_3,
-    ])?));
+    let relays: RelayState = Arc::new(RwLock::new(RelayBoard::new(
+        &backend,
+        [GPIO_RELAY_1, GPIO_RELAY_2, GPIO_RELAY_3],
+    )?));
 
     let cors = CorsLayer::new()
-        // allow `GET` and `POST` when accessing the resource
-        .allow_methods([Method::GET, Method::POST])
+        // allow `GET`, `POST` and `DELETE` when accessing the resource
+        .allow_methods([Method::GET, Method::POST, Method::DELETE])
         // allow requests from any origin
         .allow_origin(Any);
 
     // build our application with a single route
     let app = Router::new()
         .route("/", get(|| async { "Hello, Grow!" }))
-        .route("/sensors", get(get_sensor_data))
-        .route("/humidity", get(get_humidity))
-        .route("/humidity/list", get(list_humidity))
-        .with_state(humidity_state)
+        .route("/sensors", get(list_sensors))
+        .route("/sensors/:id", get(get_sensor_data))
+        .route("/sensors/:id/humidity", get(get_humidity))
+        .route("/sensors/:id/humidity/list", get(list_humidity))
+        .route("/sensors/:id/diagnostics", get(get_diagnostics))
+        .route("/sensors/:id/validation", get(get_validation))
+        .route("/sensors/:id/heater", get(heat_sensor))
+        .route("/sensors/:id/light", get(get_light))
+        .route("/sensors/:id/calibration", get(get_calibration))
+        .route(
+            "/sensors/:id/calibration/:quantity",
+            post(capture_calibration_point).delete(reset_calibration),
+        )
+        .route("/humidity", get(get_first_humidity))
+        .route("/humidity/list", get(list_first_humidity))
+        .with_state(sensors)
         .route("/relay/:id/toggle", get(toggle_relay))
         .route("/relay/:id/on", get(relay_on))
         .route("/relay/:id/off", get(relay_off))
@@ -79,47 +131,316 @@ async fn main() -> Result<()> {
     // run our app with hyper, listening globally on port 3000
     let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await?;
 
-    let server = axum::serve(listener, app.into_make_service()).into_future();
-
-    let _ = tokio::join!(server, update_task);
+    axum::serve(listener, app.into_make_service())
+        .into_future()
+        .await?;
 
     Ok(())
 }
 
-async fn get_sensor_data(State(tracker): State<HumidityState>) -> Json<Option<SensorData>> {
-    let tracker = tracker.read().await;
+#[cfg_attr(not(feature = "sim"), allow(unused_variables))]
+fn backend(options: &options::Args, config: &config::Config) -> Result<gpio::Backend> {
+    #[cfg(feature = "sim")]
+    if options.simulate {
+        let model = match &options.sim_model {
+            Some(path) => sim::Model::load(path)?,
+            None => sim::Model::default(),
+        };
+        println!("Simulating hardware with {:?}", model);
+
+        let environment = sim::Environment::new(model)?;
+        for sensor in &config.sensors {
+            match sensor.connection()? {
+                humidity::Connection::Pin(pin) => environment.attach(pin, sensor.sensor_type),
+                humidity::Connection::I2c { bus, address } => {
+                    environment.attach_i2c(bus, address, sensor.sensor_type)
+                }
+                humidity::Connection::Iio { device } => {
+                    environment.attach_iio(device, sensor.sensor_type)
+                }
+                humidity::Connection::OneWire { device, .. } => environment.attach_w1(device),
+                // Every simulated serial port has an MH-Z19B on it
+                humidity::Connection::Serial { .. } => {}
+            }
+        }
+        return Ok(gpio::Backend::simulated(environment));
+    }
+
+    #[cfg(feature = "gpio-cdev")]
+    if let Some(chip) = &options.gpio_chip {
+        return Ok(gpio::Backend::cdev(chip)?);
+    }
+
+    Ok(gpio::Backend::hardware()?)
+}
+
+/// The units a request asks for, the server's defaults fill in the rest.
+#[derive(Debug, Deserialize)]
+struct UnitsQuery {
+    temperature: Option<humidity::TemperatureUnit>,
+    pressure: Option<humidity::PressureUnit>,
+}
 
-    match tracker.last() {
-        Some(entry) => Json(Some(SensorData::from(entry.result))),
-        None => Json(None),
+impl UnitsQuery {
+    fn or(self, defaults: humidity::Units) -> humidity::Units {
+        humidity::Units {
+            temperature: self.temperature.unwrap_or(defaults.temperature),
+            pressure: self.pressure.unwrap_or(defaults.pressure),
+        }
     }
 }
 
-async fn list_humidity(State(tracker): State<HumidityState>) -> String {
-    let tracker = tracker.read().await;
+/// A sensor in the aggregate listing.
+#[derive(Debug, Serialize)]
+struct SensorSummary {
+    id: String,
+    #[serde(rename = "type")]
+    sensor_type: humidity::SensorType,
+    connection: humidity::Connection,
+    interval_secs: u64,
+    /// Whether the sensor is still being read, `latest` is stale otherwise.
+    tracking: bool,
+    latest: Option<SensorData>,
+    /// The daily light integral so far today, for light sensors.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dli: Option<f32>,
+}
+
+async fn list_sensors(
+    Query(units): Query<UnitsQuery>,
+    State(sensors): State<SensorsState>,
+) -> Json<Vec<SensorSummary>> {
+    let units = units.or(sensors.units());
+    let mut summaries = Vec::new();
+    for sensor in sensors.iter() {
+        let state = sensor.state.read().await;
+        summaries.push(SensorSummary {
+            id: sensor.config.id.clone(),
+            sensor_type: sensor.config.sensor_type,
+            connection: sensor.connection.clone(),
+            interval_secs: sensor.config.interval_secs,
+            tracking: sensor.tracking(),
+            latest: state
+                .history
+                .last()
+                .map(|entry| SensorData::new(entry.result, units)),
+            dli: state.light.today().map(|today| today.dli),
+        });
+    }
+
+    Json(summaries)
+}
+
+async fn get_sensor_data(
+    Path(id): Path<String>,
+    Query(units): Query<UnitsQuery>,
+    State(sensors): State<SensorsState>,
+) -> Result<Json<Option<SensorData>>, (StatusCode, String)> {
+    let Some(sensor) = sensors.get(&id) else {
+        return Err((StatusCode::NOT_FOUND, format!("No sensor with id {id}")));
+    };
+    if !sensor.tracking() {
+        return Err((
+            StatusCode::SERVICE_UNAVAILABLE,
+            format!("Sensor {id} is no longer being read, see the server log"),
+        ));
+    }
+    let units = units.or(sensors.units());
+    let state = sensor.state.read().await;
+
+    match state.history.last() {
+        Some(entry) => Ok(Json(Some(SensorData::new(entry.result, units)))),
+        None => Ok(Json(None)),
+    }
+}
+
+async fn get_humidity(
+    Path(id): Path<String>,
+    Query(units): Query<UnitsQuery>,
+    State(sensors): State<SensorsState>,
+) -> Result<String, StatusCode> {
+    let sensor = sensors.get(&id).ok_or(StatusCode::NOT_FOUND)?;
+    Ok(describe_latest(sensor, units.or(sensors.units())).await)
+}
+
+async fn list_humidity(
+    Path(id): Path<String>,
+    Query(units): Query<UnitsQuery>,
+    State(sensors): State<SensorsState>,
+) -> Result<String, StatusCode> {
+    let sensor = sensors.get(&id).ok_or(StatusCode::NOT_FOUND)?;
+    Ok(describe_history(sensor, units.or(sensors.units())).await)
+}
+
+async fn get_first_humidity(
+    Query(units): Query<UnitsQuery>,
+    State(sensors): State<SensorsState>,
+) -> Result<String, StatusCode> {
+    let sensor = sensors.first().ok_or(StatusCode::NOT_FOUND)?;
+    Ok(describe_latest(sensor, units.or(sensors.units())).await)
+}
+
+async fn list_first_humidity(
+    Query(units): Query<UnitsQuery>,
+    State(sensors): State<SensorsState>,
+) -> Result<String, StatusCode> {
+    let sensor = sensors.first().ok_or(StatusCode::NOT_FOUND)?;
+    Ok(describe_history(sensor, units.or(sensors.units())).await)
+}
+
+async fn describe_history(sensor: &sensors::Sensor, units: humidity::Units) -> String {
+    let state = sensor.state.read().await;
     let mut result = String::new();
-    for entry in tracker.iter() {
+    for entry in state.history.iter() {
         result.push_str(&format!(
-            "Temperature: {}°C, Humidity: {}%, Time: {}\n",
-            entry.result.temperature, entry.result.humidity, entry.time
+            "{}, Time: {}, Attempts: {}{}\n",
+            SensorData::new(entry.result, units),
+            entry.time,
+            entry.attempts,
+            match entry.suspect {
+                Some(suspicion) => format!(", Suspect: {:?}", suspicion),
+                None => String::new(),
+            }
         ));
     }
 
     result
 }
 
-async fn get_humidity(State(tracker): State<HumidityState>) -> String {
-    let tracker = tracker.read().await;
+async fn describe_latest(sensor: &sensors::Sensor, units: humidity::Units) -> String {
+    let state = sensor.state.read().await;
 
-    match tracker.last() {
+    match state.history.last() {
         Some(entry) => format!(
-            "Temperature: {}°C, Humidity: {}%, Time: {}",
-            entry.result.temperature, entry.result.humidity, entry.time
+            "{}, Time: {}",
+            SensorData::new(entry.result, units),
+            entry.time
         ),
         None => "No data".to_owned(),
     }
 }
 
+async fn get_diagnostics(
+    Path(id): Path<String>,
+    State(sensors): State<SensorsState>,
+) -> Result<Json<humidity::Diagnostics>, (StatusCode, String)> {
+    let Some(sensor) = sensors.get(&id) else {
+        return Err((StatusCode::NOT_FOUND, format!("No sensor with id {id}")));
+    };
+
+    match sensor.handle.diagnose(DIAGNOSTICS_DEADLINE).await {
+        Ok(diagnostics) => Ok(Json(diagnostics)),
+        Err(e) => Err(sensor_error_response(e)),
+    }
+}
+
+async fn heat_sensor(
+    Path(id): Path<String>,
+    State(sensors): State<SensorsState>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let Some(sensor) = sensors.get(&id) else {
+        return Err((StatusCode::NOT_FOUND, format!("No sensor with id {id}")));
+    };
+
+    match sensor.handle.heat(humidity::HEATER_DEADLINE).await {
+        Ok(()) => Ok(StatusCode::OK),
+        Err(e) => Err(sensor_error_response(e)),
+    }
+}
+
+/// Picks the status for a request the sensor's thread could not serve.
+fn sensor_error_response(e: humidity::SensorError) -> (StatusCode, String) {
+    let status = match e.error {
+        humidity::Error::Deadline(_) => StatusCode::GATEWAY_TIMEOUT,
+        humidity::Error::TooFrequent { .. } => StatusCode::TOO_MANY_REQUESTS,
+        humidity::Error::Unsupported(_) => StatusCode::NOT_IMPLEMENTED,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    };
+    (status, e.to_string())
+}
+
+/// The light a sensor received today and yesterday.
+#[derive(Debug, Serialize)]
+struct LightSummary {
+    today: Option<humidity::DailyLight>,
+    yesterday: Option<humidity::DailyLight>,
+}
+
+async fn get_light(
+    Path(id): Path<String>,
+    State(sensors): State<SensorsState>,
+) -> Result<Json<LightSummary>, StatusCode> {
+    let sensor = sensors.get(&id).ok_or(StatusCode::NOT_FOUND)?;
+    let state = sensor.state.read().await;
+    Ok(Json(LightSummary {
+        today: state.light.today(),
+        yesterday: state.light.yesterday(),
+    }))
+}
+
+/// A value a sensor is exposed to while capturing a calibration point.
+#[derive(Debug, Deserialize)]
+struct CalibrationReference {
+    reference: f32,
+}
+
+async fn get_calibration(
+    Path(id): Path<String>,
+    State(sensors): State<SensorsState>,
+) -> Result<Json<sensors::Calibration>, StatusCode> {
+    let sensor = sensors.get(&id).ok_or(StatusCode::NOT_FOUND)?;
+    let calibration = sensor.calibration.lock().await;
+    Ok(Json(calibration.clone()))
+}
+
+async fn capture_calibration_point(
+    Path((id, quantity)): Path<(String, humidity::Quantity)>,
+    State(sensors): State<SensorsState>,
+    Json(body): Json<CalibrationReference>,
+) -> Result<Json<sensors::Calibration>, (StatusCode, String)> {
+    let Some(sensor) = sensors.get(&id) else {
+        return Err((StatusCode::NOT_FOUND, format!("No sensor with id {id}")));
+    };
+
+    sensors
+        .capture_point(sensor, quantity, body.reference)
+        .await
+        .map(Json)
+        .map_err(calibration_error_response)
+}
+
+async fn reset_calibration(
+    Path((id, quantity)): Path<(String, humidity::Quantity)>,
+    State(sensors): State<SensorsState>,
+) -> Result<Json<sensors::Calibration>, (StatusCode, String)> {
+    let Some(sensor) = sensors.get(&id) else {
+        return Err((StatusCode::NOT_FOUND, format!("No sensor with id {id}")));
+    };
+
+    sensors
+        .reset_calibration(sensor, quantity)
+        .await
+        .map(Json)
+        .map_err(calibration_error_response)
+}
+
+fn calibration_error_response(e: CalibrationError) -> (StatusCode, String) {
+    match e {
+        CalibrationError::Fit(_) => (StatusCode::UNPROCESSABLE_ENTITY, e.to_string()),
+        CalibrationError::NoReadings { .. } => (StatusCode::CONFLICT, e.to_string()),
+        CalibrationError::Sensor(e) => sensor_error_response(e),
+        CalibrationError::Save(_) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+    }
+}
+
+async fn get_validation(
+    Path(id): Path<String>,
+    State(sensors): State<SensorsState>,
+) -> Result<Json<humidity::ValidationCounts>, StatusCode> {
+    let sensor = sensors.get(&id).ok_or(StatusCode::NOT_FOUND)?;
+    Ok(Json(sensor.handle.validation()))
+}
+
 async fn toggle_relay(Path(id): Path<usize>, State(relays): State<RelayState>) -> StatusCode {
     let mut relays = relays.write().await;
     let relay = match relays.get_mut(id) {
@@ -127,9 +448,10 @@ async fn toggle_relay(Path(id): Path<usize>, State(relays): State<RelayState>) -
         None => return StatusCode::NOT_FOUND,
     };
 
-    relay.toggle();
-
-    StatusCode::OK
+    match relay.toggle() {
+        Ok(()) => StatusCode::OK,
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    }
 }
 async fn relay_on(Path(id): Path<usize>, State(relays): State<RelayState>) -> StatusCode {
     let mut relays = relays.write().await;
@@ -138,9 +460,10 @@ async fn relay_on(Path(id): Path<usize>, State(relays): State<RelayState>) -> St
         None => return StatusCode::NOT_FOUND,
     };
 
-    relay.on();
-
-    StatusCode::OK
+    match relay.on() {
+        Ok(()) => StatusCode::OK,
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    }
 }
 
 async fn relay_off(Path(id): Path<usize>, State(relays): State<RelayState>) -> StatusCode {
@@ -150,29 +473,99 @@ async fn relay_off(Path(id): Path<usize>, State(relays): State<RelayState>) -> S
         None => return StatusCode::NOT_FOUND,
     };
 
-    relay.off();
-
-    StatusCode::OK
+    match relay.off() {
+        Ok(()) => StatusCode::OK,
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    }
 }
 
-async fn flash_led() -> StatusCode {
+#[allow(dead_code)]
+async fn flash_led(State(backend): State<gpio::Backend>) -> StatusCode {
     tokio::spawn(async move {
-        let _ = blink_led(5).await;
+        let _ = blink_led(&backend, 5).await;
     });
     StatusCode::OK
 }
 
+#[allow(dead_code)]
 const GPIO_LED: u8 = 23;
-async fn blink_led(times: usize) -> Result<()> {
-    let gpio = Gpio::new()?;
-    let mut pin = gpio.get(GPIO_LED)?.into_output();
+#[allow(dead_code)]
+async fn blink_led(backend: &gpio::Backend, times: usize) -> Result<()> {
+    let mut pin = backend.output(GPIO_LED)?;
 
     for _ in 0..times {
-        pin.set_high();
+        pin.set_high()?;
         sleep(Duration::from_millis(500)).await;
-        pin.set_low();
+        pin.set_low()?;
         sleep(Duration::from_millis(500)).await;
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use humidity::{PressureUnit, TemperatureUnit, Units};
+
+    fn query(uri: &str) -> Result<UnitsQuery, axum::extract::rejection::QueryRejection> {
+        Query::<UnitsQuery>::try_from_uri(&uri.parse().unwrap()).map(|Query(units)| units)
+    }
+
+    #[test]
+    fn reads_the_units_a_request_asks_for() {
+        let units = query("/sensors?temperature=kelvin&pressure=inhg").unwrap();
+        assert_eq!(
+            units.or(Units::default()),
+            Units {
+                temperature: TemperatureUnit::Kelvin,
+                pressure: PressureUnit::InchOfMercury,
+            }
+        );
+    }
+
+    #[test]
+    fn fills_in_missing_units_from_the_defaults() {
+        let defaults = Units {
+            temperature: TemperatureUnit::Fahrenheit,
+            pressure: PressureUnit::Kilopascal,
+        };
+        assert_eq!(query("/sensors").unwrap().or(defaults), defaults);
+        assert_eq!(
+            query("/sensors?pressure=hpa").unwrap().or(defaults),
+            Units {
+                temperature: TemperatureUnit::Fahrenheit,
+                pressure: PressureUnit::Hectopascal,
+            }
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_units_in_a_request() {
+        assert!(query("/sensors?temperature=rankine").is_err());
+        assert!(query("/sensors?pressure=bar").is_err());
+    }
+
+    #[test]
+    fn reads_the_default_units_from_the_command_line() {
+        let args = options::Args::try_parse_from(["pi"]).unwrap();
+        assert_eq!(args.units, Units::default());
+
+        let args = options::Args::try_parse_from([
+            "pi",
+            "--temperature-unit",
+            "kelvin",
+            "--pressure-unit",
+            "inhg",
+        ])
+        .unwrap();
+        assert_eq!(
+            args.units,
+            Units {
+                temperature: TemperatureUnit::Kelvin,
+                pressure: PressureUnit::InchOfMercury,
+            }
+        );
+        assert!(options::Args::try_parse_from(["pi", "--temperature-unit", "rankine"]).is_err());
+    }
+}
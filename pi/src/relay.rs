@@ -1,7 +1,7 @@
-use std::array;
-
 use anyhow::Result;
-use rppal::gpio::{Gpio, OutputPin};
+use embedded_hal::digital::v2::{OutputPin as _, StatefulOutputPin};
+
+use crate::gpio::{Backend, OutputPin};
 
 #[derive(Debug)]
 pub struct Relay {
@@ -10,28 +10,29 @@ pub struct Relay {
 }
 
 impl Relay {
-    pub fn new(gpio_pin: u8) -> Result<Self> {
-        let gpio = Gpio::new()?;
-        let pin = gpio.get(gpio_pin)?.into_output();
-        let on = pin.is_set_high();
+    pub fn new(backend: &Backend, gpio_pin: u8) -> Result<Self> {
+        let pin = backend.output(gpio_pin)?;
+        let on = pin.is_set_high()?;
         Ok(Relay { pin, on })
     }
 
-    pub fn on(&mut self) {
-        self.pin.set_high();
-        self.on = self.pin.is_set_high();
+    pub fn on(&mut self) -> Result<()> {
+        self.pin.set_high()?;
+        self.on = self.pin.is_set_high()?;
+        Ok(())
     }
 
-    pub fn off(&mut self) {
-        self.pin.set_low();
-        self.on = self.pin.is_set_high();
+    pub fn off(&mut self) -> Result<()> {
+        self.pin.set_low()?;
+        self.on = self.pin.is_set_high()?;
+        Ok(())
     }
 
-    pub fn toggle(&mut self) {
+    pub fn toggle(&mut self) -> Result<()> {
         if self.on {
-            self.off();
+            self.off()
         } else {
-            self.on();
+            self.on()
         }
     }
 }
@@ -42,10 +43,10 @@ pub struct RelayBoard<const N: usize> {
 }
 
 impl<const N: usize> RelayBoard<N> {
-    pub fn new(pin_nums: [u8; N]) -> Result<Self> {
+    pub fn new(backend: &Backend, pin_nums: [u8; N]) -> Result<Self> {
         let relays = pin_nums
             .into_iter()
-            .map(Relay::new)
+            .map(|pin| Relay::new(backend, pin))
             .collect::<Result<Vec<Relay>>>()?;
 
         let relays = match relays.try_into() {
@@ -1,17 +1,29 @@
+use core::fmt;
+
 use crate::humidity;
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct SensorData {
-    temperature: f32,
-    humidity: f32,
+    /// Every quantity the sensor measured, with its unit.
+    values: Vec<humidity::Value>,
 }
 
 impl SensorData {
-    pub fn from(measurement: humidity::Measurement) -> Self {
+    /// The values of a measurement, converted to `units`.
+    pub fn new(measurement: humidity::Measurement, units: humidity::Units) -> Self {
         Self {
-            temperature: measurement.temperature,
-            humidity: measurement.humidity,
+            values: measurement
+                .values()
+                .map(|value| units.convert(value))
+                .collect(),
         }
     }
 }
+
+impl fmt::Display for SensorData {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let values: Vec<_> = self.values.iter().map(|value| value.to_string()).collect();
+        write!(f, "{}", values.join(", "))
+    }
+}
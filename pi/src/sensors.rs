@@ -0,0 +1,417 @@
+//! The configured sensors, each tracked on its own thread with its own history and light integral.
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use anyhow::{bail, Result};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use tokio::{
+    sync::{Mutex, RwLock},
+    task::JoinHandle,
+    time::{interval, Duration},
+};
+
+use crate::{
+    circular::Circular,
+    config::{Config, SensorConfig},
+    gpio,
+    humidity::{
+        self, CalibrationPoint, Connection, Corrections, FitError, LightIntegral, Quantity,
+        ReadMode, Reading, RetryPolicy, SensorError, Tracker, TrackerHandle, Units, Update,
+        ValidationPolicy,
+    },
+};
+
+/// How long the sensor's thread may take to pick up new corrections
+const CALIBRATE_DEADLINE: Duration = Duration::from_secs(5);
+/// Two points give the slope, older ones are dropped
+const MAX_CALIBRATION_POINTS: usize = 2;
+
+/// What is kept of a sensor's readings.
+pub struct SensorState {
+    /// The latest readings.
+    pub history: Circular<Reading, 10>,
+    /// The PPFD of every reading summed up per day, for light sensors.
+    pub light: LightIntegral,
+}
+
+impl Update for SensorState {
+    fn update(&mut self, reading: Reading) {
+        if let Some(ppfd) = reading.result.get(Quantity::Ppfd) {
+            self.light.add(reading.time, ppfd);
+        }
+        self.history.add(reading);
+    }
+}
+
+/// Settings for sensors that don't override them in the config.
+#[derive(Debug, Clone, Copy)]
+pub struct Defaults {
+    pub read_mode: ReadMode,
+    pub retry: RetryPolicy,
+    pub validation: ValidationPolicy,
+}
+
+/// The corrections a sensor's readings get and the points they were fitted to.
+#[derive(Debug, Clone, Serialize)]
+pub struct Calibration {
+    pub corrections: Corrections,
+    /// The points captured since the server started, by quantity.
+    pub points: BTreeMap<Quantity, Vec<CalibrationPoint>>,
+    /// When the sensor's thread picked up the current corrections.
+    #[serde(skip)]
+    applied_at: DateTime<Utc>,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum CalibrationError {
+    #[error(transparent)]
+    Fit(#[from] FitError),
+    #[error("No valid {quantity} readings since the last calibration, wait for the next reading")]
+    NoReadings { quantity: Quantity },
+    #[error(transparent)]
+    Sensor(#[from] SensorError),
+    #[error("The calibration is applied but could not be saved: {0:#}")]
+    Save(anyhow::Error),
+}
+
+/// A sensor that is being tracked.
+pub struct Sensor {
+    pub config: SensorConfig,
+    pub connection: Connection,
+    pub state: Arc<RwLock<SensorState>>,
+    pub handle: TrackerHandle,
+    pub calibration: Mutex<Calibration>,
+    /// The task reading the sensor, which only ends if its reader thread died.
+    task: JoinHandle<()>,
+}
+
+impl Sensor {
+    /// Whether the sensor is still being read, its history goes stale otherwise.
+    pub fn tracking(&self) -> bool {
+        !self.task.is_finished()
+    }
+}
+
+/// All tracked sensors, looked up by id.
+#[derive(Default)]
+pub struct Registry {
+    sensors: Vec<Sensor>,
+    /// The config the sensors were started with, kept to save calibrations to.
+    config: Mutex<Config>,
+    /// Where calibrations are saved, they only last until a restart without one.
+    config_path: Option<PathBuf>,
+    /// The units values are shown in when a request doesn't pick any.
+    units: Units,
+}
+
+impl Registry {
+    /// Starts tracking every sensor in `config`, saving calibrations to `config_path`.
+    pub fn start(
+        backend: &gpio::Backend,
+        config: &Config,
+        config_path: Option<&Path>,
+        defaults: &Defaults,
+    ) -> Result<Self> {
+        let mut registry = Registry {
+            sensors: Vec::new(),
+            config: Mutex::new(config.clone()),
+            config_path: config_path.map(Path::to_owned),
+            units: Units::default(),
+        };
+        for sensor in &config.sensors {
+            if registry.get(&sensor.id).is_some() {
+                bail!("Sensor id {} is used more than once", sensor.id);
+            }
+            if sensor.interval_secs == 0 {
+                bail!("Sensor {} needs a non-zero interval", sensor.id);
+            }
+
+            let soil = sensor.soil.unwrap_or_default();
+            if soil.dry == soil.wet {
+                bail!("Sensor {} needs different dry and wet voltages", sensor.id);
+            }
+            if let Err(e) = humidity::check_corrections(&sensor.calibration) {
+                bail!("Sensor {} has an invalid calibration: {e}", sensor.id);
+            }
+
+            let connection = sensor.connection()?;
+            let retry = sensor.retry.unwrap_or(defaults.retry);
+            let mut tracker = Tracker::new(
+                &sensor.id,
+                backend,
+                sensor.sensor_type,
+                connection.clone(),
+                sensor.read_mode.unwrap_or(defaults.read_mode),
+            )?
+            .with_retry_policy(retry)
+            .with_validation_policy(sensor.validation.unwrap_or(defaults.validation))
+            .with_sht_settings(sensor.sht.unwrap_or_default())
+            .with_adc_settings(sensor.adc.unwrap_or_default())
+            .with_soil_calibration(soil)
+            .with_corrections(sensor.calibration.clone());
+            if let Some(humidity) = sensor.condensation_humidity {
+                tracker = tracker.with_condensation_humidity(humidity);
+            }
+            if let Some(enabled) = sensor.self_calibration {
+                tracker = tracker.with_self_calibration(enabled);
+            }
+            if let Some(spectrum) = sensor.spectrum {
+                tracker = tracker.with_spectrum(spectrum);
+            }
+            if let Some(offset) = sensor.leaf_temperature_offset {
+                tracker = tracker.with_leaf_temperature_offset(offset);
+            }
+
+            let state = Arc::new(RwLock::new(SensorState {
+                history: Circular::new(),
+                light: LightIntegral::default(),
+            }));
+            let (task, handle) = humidity::start_tracking(
+                state.clone(),
+                tracker,
+                interval(sensor.interval()),
+                retry.budget() + Duration::from_secs(1),
+            );
+
+            registry.sensors.push(Sensor {
+                config: sensor.clone(),
+                connection,
+                state,
+                handle,
+                calibration: Mutex::new(Calibration {
+                    corrections: sensor.calibration.clone(),
+                    points: BTreeMap::new(),
+                    applied_at: Utc::now(),
+                }),
+                task,
+            });
+        }
+
+        Ok(registry)
+    }
+
+    /// Shows values in `units` unless a request asks for others.
+    pub fn with_units(mut self, units: Units) -> Self {
+        self.units = units;
+        self
+    }
+
+    pub fn units(&self) -> Units {
+        self.units
+    }
+
+    pub fn get(&self, id: &str) -> Option<&Sensor> {
+        self.sensors.iter().find(|sensor| sensor.config.id == id)
+    }
+
+    /// The first configured sensor, served by the routes that predate named sensors.
+    pub fn first(&self) -> Option<&Sensor> {
+        self.sensors.first()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Sensor> {
+        self.sensors.iter()
+    }
+
+    /// Records that `quantity` is at `reference` right now, e.g. 75% humidity over a salt
+    /// solution, and corrects the sensor's readings to match.
+    ///
+    /// The raw value is the average of the valid readings taken since the corrections last
+    /// changed, so the sensor should have settled at the reference before those.
+    pub async fn capture_point(
+        &self,
+        sensor: &Sensor,
+        quantity: Quantity,
+        reference: f32,
+    ) -> Result<Calibration, CalibrationError> {
+        if quantity.is_derived() {
+            return Err(FitError::Derived { quantity }.into());
+        }
+        let mut calibration = sensor.calibration.lock().await;
+        let correction = calibration
+            .corrections
+            .get(&quantity)
+            .copied()
+            .unwrap_or_default();
+
+        let values: Vec<f32> = {
+            let state = sensor.state.read().await;
+            state
+                .history
+                .iter()
+                .filter(|reading| {
+                    reading.time > calibration.applied_at && reading.suspect.is_none()
+                })
+                .filter_map(|reading| reading.result.get(quantity))
+                .collect()
+        };
+        if values.is_empty() {
+            return Err(CalibrationError::NoReadings { quantity });
+        }
+        let raw = correction.invert(values.iter().sum::<f32>() / values.len() as f32);
+
+        let mut points = calibration
+            .points
+            .get(&quantity)
+            .cloned()
+            .unwrap_or_default();
+        points.push(CalibrationPoint { raw, reference });
+        if points.len() > MAX_CALIBRATION_POINTS {
+            points.remove(0);
+        }
+        let mut corrections = calibration.corrections.clone();
+        corrections.insert(quantity, correction.fit(&points)?);
+
+        self.apply(sensor, &mut calibration, corrections).await?;
+        calibration.points.insert(quantity, points);
+        self.save(sensor, &calibration.corrections).await?;
+        Ok(calibration.clone())
+    }
+
+    /// Drops the correction of `quantity` and the points it was fitted to.
+    pub async fn reset_calibration(
+        &self,
+        sensor: &Sensor,
+        quantity: Quantity,
+    ) -> Result<Calibration, CalibrationError> {
+        let mut calibration = sensor.calibration.lock().await;
+        let mut corrections = calibration.corrections.clone();
+        corrections.remove(&quantity);
+
+        self.apply(sensor, &mut calibration, corrections).await?;
+        calibration.points.remove(&quantity);
+        self.save(sensor, &calibration.corrections).await?;
+        Ok(calibration.clone())
+    }
+
+    /// Hands new corrections to the sensor's thread, changing nothing here if that fails.
+    async fn apply(
+        &self,
+        sensor: &Sensor,
+        calibration: &mut Calibration,
+        corrections: Corrections,
+    ) -> Result<(), CalibrationError> {
+        sensor
+            .handle
+            .calibrate(corrections.clone(), CALIBRATE_DEADLINE)
+            .await?;
+        calibration.corrections = corrections;
+        calibration.applied_at = Utc::now();
+        Ok(())
+    }
+
+    /// Saves the corrections with the sensor's config, if there is a config file.
+    async fn save(
+        &self,
+        sensor: &Sensor,
+        corrections: &Corrections,
+    ) -> Result<(), CalibrationError> {
+        let Some(path) = &self.config_path else {
+            return Ok(());
+        };
+        let mut config = self.config.lock().await;
+        if let Some(saved) = config
+            .sensors
+            .iter_mut()
+            .find(|saved| saved.id == sensor.config.id)
+        {
+            saved.calibration = corrections.clone();
+        }
+        config.save(path).map_err(CalibrationError::Save)
+    }
+}
+
+#[cfg(all(test, feature = "sim"))]
+mod tests {
+    use super::*;
+    use crate::sim::{Environment, Model};
+
+    fn start(config: &Config) -> Result<Registry> {
+        let backend = gpio::Backend::simulated(Environment::new(Model::default()).unwrap());
+        let defaults = Defaults {
+            read_mode: ReadMode::Polling,
+            retry: RetryPolicy::default(),
+            validation: ValidationPolicy::default(),
+        };
+        Registry::start(&backend, config, None, &defaults)
+    }
+
+    #[test]
+    fn rejects_a_zero_interval() {
+        let mut config = Config::default();
+        config.sensors[0].interval_secs = 0;
+
+        let error = start(&config).err().unwrap();
+        assert_eq!(
+            error.to_string(),
+            "Sensor humidity needs a non-zero interval"
+        );
+    }
+
+    /// Replaces the sensor's history with a reading taken now, of a raw `humidity` corrected
+    /// the way the sensor's thread would.
+    async fn settle(sensor: &Sensor, humidity: f32) {
+        let correction = sensor
+            .calibration
+            .lock()
+            .await
+            .corrections
+            .get(&Quantity::Humidity)
+            .copied()
+            .unwrap_or_default();
+        let mut state = sensor.state.write().await;
+        state.history = Circular::new();
+        state.history.add(Reading {
+            result: humidity::Measurement::new()
+                .with(Quantity::Humidity, correction.apply(humidity)),
+            time: Utc::now(),
+            attempts: 1,
+            suspect: None,
+        });
+    }
+
+    #[tokio::test]
+    async fn keeps_the_last_calibration_points() {
+        let mut config = Config::default();
+        config.sensors[0].interval_secs = 3600;
+        let registry = start(&config).unwrap();
+        let sensor = registry.first().unwrap();
+        // Wait for the first reading, so it can't land among the ones set here
+        for _ in 0..100 {
+            if sensor.state.read().await.history.last().is_some() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+
+        settle(sensor, 35.0).await;
+        registry
+            .capture_point(sensor, Quantity::Humidity, 33.0)
+            .await
+            .unwrap();
+        settle(sensor, 72.0).await;
+        registry
+            .capture_point(sensor, Quantity::Humidity, 75.0)
+            .await
+            .unwrap();
+        settle(sensor, 80.0).await;
+        let calibration = registry
+            .capture_point(sensor, Quantity::Humidity, 85.0)
+            .await
+            .unwrap();
+
+        // The first point is dropped, the correction goes through the other two
+        let points = &calibration.points[&Quantity::Humidity];
+        assert_eq!(points.len(), MAX_CALIBRATION_POINTS);
+        assert!((points[0].raw - 72.0).abs() < 1e-3);
+        assert!((points[1].raw - 80.0).abs() < 1e-3);
+        let correction = calibration.corrections[&Quantity::Humidity];
+        assert!((correction.apply(72.0) - 75.0).abs() < 1e-3);
+        assert!((correction.apply(80.0) - 85.0).abs() < 1e-3);
+    }
+}
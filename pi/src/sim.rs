@@ -0,0 +1,538 @@
+//! Virtual grow environment backing the `--simulate` mode.
+//!
+//! Output pins are kept in memory and the climate drifts towards ambient conditions,
+//! pushed around by whichever relays are currently on. Data lines answer like a DHT
+//! sensor sampling the climate, using a [`SimLine`] timed by a virtual clock of its own, and
+//! so do the IIO devices of the kernel's `dht11` driver. I2C buses carry a BME280, SHT, SCD4x,
+//! BH1750, TSL2591 or an ADS1115 with soil moisture probes at each address a sensor was
+//! attached to, DS18B20 probes on the 1-Wire bus measure the water temperature, and every
+//! serial port has an MH-Z19B measuring the CO2 concentration.
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    fs, io,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::Instant,
+};
+
+use anyhow::Result;
+use rand::Rng;
+use serde::Deserialize;
+
+use crate::humidity::{
+    sim::{Fault, Responder, Response, SimClock, SimDelay, SimLine},
+    sim_i2c::{Registers, SimAds1115, SimBh1750, SimBme280, SimBus, SimScd4x, SimSht, SimTsl2591},
+    sim_iio::{self, SimIio},
+    sim_uart::SimMhz19,
+    sim_w1::SimW1,
+    Measurement, Quantity, SensorType, SoilCalibration,
+};
+
+/// Length of the DHT11 start signal the simulated line waits for (in microseconds)
+const DHT11_START_US: u64 = 18_000;
+
+/// Parameters of the simulated environment, loadable from a JSON file.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Model {
+    /// Starting temperature in degrees Celsius.
+    pub temperature: f32,
+    /// Starting relative humidity in percent.
+    pub humidity: f32,
+    /// Air pressure in hectopascal.
+    pub pressure: f32,
+    /// Temperature of the reservoir and root zone in degrees Celsius.
+    pub water_temperature: f32,
+    /// Soil moisture in percent, as read by probes with the default calibration.
+    pub soil_moisture: f32,
+    /// CO2 concentration in parts per million.
+    pub co2: f32,
+    /// Illuminance in lux, as under grow lights that are always on.
+    pub illuminance: f32,
+    /// Temperature the environment settles to with all relays off.
+    pub ambient_temperature: f32,
+    /// Humidity the environment settles to with all relays off.
+    pub ambient_humidity: f32,
+    /// Fraction of the difference to ambient conditions lost per minute.
+    pub drift: f32,
+    /// Maximum deviation added to each temperature sample.
+    pub temperature_noise: f32,
+    /// Maximum deviation added to each humidity sample.
+    pub humidity_noise: f32,
+    /// Maximum deviation added to each pressure sample.
+    pub pressure_noise: f32,
+    /// Maximum deviation added to each CO2 sample.
+    pub co2_noise: f32,
+    /// Maximum deviation added to each illuminance sample.
+    pub illuminance_noise: f32,
+    /// Probability of each kind of fault occurring on a sensor read.
+    pub faults: FaultRates,
+    /// Effect of the relays connected to each pin while they are on.
+    pub relays: Vec<RelayEffect>,
+    /// `i2cdump` output of a real BME280, whose calibration the simulated ones use.
+    pub bme280_dump: Option<PathBuf>,
+}
+
+impl Default for Model {
+    fn default() -> Self {
+        Model {
+            temperature: 22.0,
+            humidity: 50.0,
+            pressure: 1013.25,
+            water_temperature: 19.5,
+            soil_moisture: 35.0,
+            co2: 800.0,
+            illuminance: 20_000.0,
+            ambient_temperature: 22.0,
+            ambient_humidity: 50.0,
+            drift: 0.05,
+            temperature_noise: 0.1,
+            humidity_noise: 0.3,
+            pressure_noise: 0.1,
+            co2_noise: 20.0,
+            illuminance_noise: 200.0,
+            faults: FaultRates {
+                corrupt_bit: 0.05,
+                dropped_edge: 0.02,
+                ..Default::default()
+            },
+            relays: vec![
+                // humidifier
+                RelayEffect {
+                    pin: 17,
+                    temperature: 0.0,
+                    humidity: 3.0,
+                },
+                // heater
+                RelayEffect {
+                    pin: 27,
+                    temperature: 0.5,
+                    humidity: -0.5,
+                },
+                // exhaust fan
+                RelayEffect {
+                    pin: 22,
+                    temperature: -0.3,
+                    humidity: -1.5,
+                },
+            ],
+            bme280_dump: None,
+        }
+    }
+}
+
+impl Model {
+    /// Reads a model from a JSON file, missing fields use the defaults.
+    pub fn load(path: &Path) -> Result<Self> {
+        Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+    }
+}
+
+/// How a relay changes the climate while it is on.
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(default)]
+pub struct RelayEffect {
+    /// The GPIO pin switching the relay.
+    pub pin: u8,
+    /// Temperature change in degrees Celsius per minute.
+    pub temperature: f32,
+    /// Humidity change in percent per minute.
+    pub humidity: f32,
+}
+
+/// Probabilities of the sensor faults injected into simulated reads.
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(default)]
+pub struct FaultRates {
+    /// A single bit of the frame is flipped, failing the checksum.
+    pub corrupt_bit: f64,
+    /// An edge is missed, merging two pulses.
+    pub dropped_edge: f64,
+    /// The sensor stops part way through the frame.
+    pub truncated: f64,
+    /// The sensor does not answer at all, as if unplugged.
+    pub no_response: f64,
+    /// The data line is held low.
+    pub stuck_low: f64,
+}
+
+impl FaultRates {
+    fn pick<R: Rng>(&self, rng: &mut R) -> Option<Fault> {
+        let faults = [
+            (self.corrupt_bit, Fault::CorruptBit(rng.gen_range(0..40))),
+            (self.dropped_edge, Fault::DroppedEdge(rng.gen_range(0..82))),
+            (self.truncated, Fault::Truncated(rng.gen_range(0..40))),
+            (self.no_response, Fault::NoResponse),
+            (self.stuck_low, Fault::StuckLow),
+        ];
+        faults
+            .into_iter()
+            .find(|(rate, _)| rng.gen_bool(rate.clamp(0.0, 1.0)))
+            .map(|(_, fault)| fault)
+    }
+}
+
+/// The conditions the relays act on.
+#[derive(Debug, Clone, Copy)]
+struct Climate {
+    temperature: f32,
+    humidity: f32,
+}
+
+struct State {
+    model: Model,
+    climate: Climate,
+    outputs: HashMap<u8, Arc<AtomicBool>>,
+    sensors: HashMap<u8, SensorType>,
+    /// Sensors bound to the kernel's `dht11` driver, by IIO device.
+    iio_sensors: HashMap<PathBuf, SensorType>,
+    /// Sensors by I2C bus and address.
+    i2c_sensors: HashMap<(u8, u8), SensorType>,
+    /// Ids of the probes on the 1-Wire bus.
+    w1_probes: BTreeSet<String>,
+    /// Registers loaded from the model's BME280 dump.
+    bme280_registers: Option<Registers>,
+    /// The clocks timing the data lines, by pin, so reads on other pins can't stretch pulses.
+    clocks: HashMap<u8, SimClock>,
+    updated: Instant,
+}
+
+impl State {
+    fn step(&mut self) {
+        let now = Instant::now();
+        let minutes = now.duration_since(self.updated).as_secs_f32() / 60.0;
+        self.updated = now;
+
+        let mut temperature = self.climate.temperature;
+        let mut humidity = self.climate.humidity;
+        let drift = (self.model.drift * minutes).min(1.0);
+        temperature += (self.model.ambient_temperature - temperature) * drift;
+        humidity += (self.model.ambient_humidity - humidity) * drift;
+
+        for effect in &self.model.relays {
+            let on = self
+                .outputs
+                .get(&effect.pin)
+                .is_some_and(|state| state.load(Ordering::SeqCst));
+            if on {
+                temperature += effect.temperature * minutes;
+                humidity += effect.humidity * minutes;
+            }
+        }
+
+        self.climate = Climate {
+            temperature,
+            humidity: humidity.clamp(0.0, 100.0),
+        };
+    }
+}
+
+/// Shared handle to the simulated environment.
+#[derive(Clone)]
+pub struct Environment(Arc<Mutex<State>>);
+
+impl Environment {
+    pub fn new(model: Model) -> Result<Self> {
+        let bme280_registers = match &model.bme280_dump {
+            Some(path) => Some(Registers::from_dump(&fs::read_to_string(path)?)?),
+            None => None,
+        };
+        let climate = Climate {
+            temperature: model.temperature,
+            humidity: model.humidity,
+        };
+        Ok(Environment(Arc::new(Mutex::new(State {
+            model,
+            climate,
+            outputs: HashMap::new(),
+            sensors: HashMap::new(),
+            iio_sensors: HashMap::new(),
+            i2c_sensors: HashMap::new(),
+            w1_probes: BTreeSet::new(),
+            bme280_registers,
+            clocks: HashMap::new(),
+            updated: Instant::now(),
+        }))))
+    }
+
+    /// Places a sensor of the given type on `pin`, pins default to a DHT22.
+    pub fn attach(&self, pin: u8, sensor_type: SensorType) {
+        self.0.lock().unwrap().sensors.insert(pin, sensor_type);
+    }
+
+    /// Places a sensor of the given type at `address` on I2C `bus`.
+    pub fn attach_i2c(&self, bus: u8, address: u8, sensor_type: SensorType) {
+        let mut state = self.0.lock().unwrap();
+        state.i2c_sensors.insert((bus, address), sensor_type);
+    }
+
+    /// Binds a sensor of the given type to the IIO `device` of the kernel's `dht11` driver.
+    pub fn attach_iio(&self, device: PathBuf, sensor_type: SensorType) {
+        self.0
+            .lock()
+            .unwrap()
+            .iio_sensors
+            .insert(device, sensor_type);
+    }
+
+    /// Places a DS18B20 probe with the given id on the 1-Wire bus.
+    pub fn attach_w1(&self, device: String) {
+        self.0.lock().unwrap().w1_probes.insert(device);
+    }
+
+    /// Returns the in-memory state of output `pin`, which the climate reacts to.
+    pub fn output(&self, pin: u8) -> Arc<AtomicBool> {
+        let mut state = self.0.lock().unwrap();
+        state.outputs.entry(pin).or_default().clone()
+    }
+
+    /// Returns a data line connected to the sensor on `pin`.
+    pub fn line(&self, pin: u8) -> SimLine {
+        let mut state = self.0.lock().unwrap();
+        let sensor_type = state
+            .sensors
+            .get(&pin)
+            .copied()
+            .unwrap_or(SensorType::Dht22);
+        let probe = Probe {
+            environment: self.clone(),
+            sensor_type,
+        };
+        let clock = state.clocks.entry(pin).or_default().clone();
+        let line = SimLine::new(clock, probe);
+        match sensor_type {
+            SensorType::Dht11 => line.with_min_start_us(DHT11_START_US),
+            _ => line,
+        }
+    }
+
+    /// Returns I2C `bus` with the sensors attached to it.
+    pub fn i2c(&self, bus: u8) -> SimBus {
+        let state = self.0.lock().unwrap();
+        let mut sim_bus = SimBus::new();
+        for (&(sensor_bus, address), &sensor_type) in &state.i2c_sensors {
+            if sensor_bus != bus {
+                continue;
+            }
+            let environment = self.clone();
+            let sample = move || environment.sample();
+            sim_bus = match sensor_type {
+                SensorType::SoilMoisture => {
+                    let environment = self.clone();
+                    sim_bus.with_device(
+                        address,
+                        SimAds1115::new(move |_| environment.sample_soil_voltage()),
+                    )
+                }
+                SensorType::Scd4x => sim_bus.with_device(address, SimScd4x::new(sample)),
+                SensorType::Bh1750 => sim_bus.with_device(address, SimBh1750::new(sample)),
+                SensorType::Tsl2591 => sim_bus.with_device(address, SimTsl2591::new(sample)),
+                SensorType::Bme280 => match &state.bme280_registers {
+                    Some(registers) => sim_bus.with_device(
+                        address,
+                        SimBme280::with_registers(registers.clone(), sample),
+                    ),
+                    None => sim_bus.with_device(address, SimBme280::new(sample)),
+                },
+                sensor_type => match sensor_type.sht_family() {
+                    Some(family) => sim_bus.with_device(address, SimSht::new(family, sample)),
+                    // Single-wire sensors don't answer on the bus
+                    None => sim_bus,
+                },
+            };
+        }
+        sim_bus
+    }
+
+    /// Returns the IIO `device` the driver registered for a sensor, devices default to a DHT22.
+    pub fn iio(&self, device: &Path) -> SimIio {
+        let sensor_type = self
+            .0
+            .lock()
+            .unwrap()
+            .iio_sensors
+            .get(device)
+            .copied()
+            .unwrap_or(SensorType::Dht22);
+        // The DHT11 reports whole numbers, the others tenths
+        let resolution = match sensor_type {
+            SensorType::Dht11 => 1.0,
+            _ => 0.1,
+        };
+
+        let environment = self.clone();
+        SimIio::new(move || -> io::Result<Measurement> {
+            if let Some(fault) = environment.fault() {
+                return Err(sim_iio::driver_error(fault));
+            }
+            let measurement = environment.sample();
+            let quantize = |value: f32| (value / resolution).round() * resolution;
+            let mut quantized = Measurement::new();
+            for quantity in [Quantity::Temperature, Quantity::Humidity] {
+                quantized.set(quantity, measurement.get(quantity).map(quantize));
+            }
+            Ok(quantized)
+        })
+    }
+
+    /// Returns the 1-Wire bus with the probes attached to it.
+    pub fn one_wire(&self) -> SimW1 {
+        let state = self.0.lock().unwrap();
+        let mut bus = SimW1::new();
+        for device in &state.w1_probes {
+            let environment = self.clone();
+            bus = bus.with_probe(device.clone(), move || environment.sample_water());
+        }
+        bus
+    }
+
+    /// Returns a serial port with an MH-Z19B on it.
+    pub fn serial(&self) -> SimMhz19 {
+        let environment = self.clone();
+        let faults = self.clone();
+        SimMhz19::new(move || environment.sample().get(Quantity::Co2).unwrap_or_default())
+            .with_faults(move || faults.fault())
+    }
+
+    /// A delay advancing the virtual clock that times the data line on `pin`.
+    pub fn line_delay(&self, pin: u8) -> SimDelay {
+        let mut state = self.0.lock().unwrap();
+        state.clocks.entry(pin).or_default().delay()
+    }
+
+    /// A delay advancing a virtual clock of its own, for sensors without a data line.
+    pub fn delay(&self) -> SimDelay {
+        SimClock::new().delay()
+    }
+
+    /// Advances the climate to the current time and returns a noisy sample of it.
+    pub fn sample(&self) -> Measurement {
+        let mut state = self.0.lock().unwrap();
+        state.step();
+
+        let mut rng = rand::thread_rng();
+        let temperature_noise = state.model.temperature_noise;
+        let humidity_noise = state.model.humidity_noise;
+        let pressure_noise = state.model.pressure_noise;
+        let co2_noise = state.model.co2_noise;
+        let illuminance_noise = state.model.illuminance_noise;
+        Measurement::new()
+            .with(
+                Quantity::Temperature,
+                state.climate.temperature + rng.gen_range(-temperature_noise..=temperature_noise),
+            )
+            .with(
+                Quantity::Humidity,
+                (state.climate.humidity + rng.gen_range(-humidity_noise..=humidity_noise))
+                    .clamp(0.0, 100.0),
+            )
+            .with(
+                Quantity::Pressure,
+                state.model.pressure + rng.gen_range(-pressure_noise..=pressure_noise),
+            )
+            .with(
+                Quantity::Co2,
+                state.model.co2 + rng.gen_range(-co2_noise..=co2_noise),
+            )
+            .with(
+                Quantity::Illuminance,
+                (state.model.illuminance + rng.gen_range(-illuminance_noise..=illuminance_noise))
+                    .max(0.0),
+            )
+    }
+
+    /// Returns a noisy sample of the water temperature, which the relays don't affect.
+    pub fn sample_water(&self) -> f32 {
+        let model = &self.0.lock().unwrap().model;
+        let noise = model.temperature_noise;
+        model.water_temperature + rand::thread_rng().gen_range(-noise..=noise)
+    }
+
+    /// Returns the output of a soil moisture probe, every channel carries one in the same soil.
+    pub fn sample_soil_voltage(&self) -> f32 {
+        let model = &self.0.lock().unwrap().model;
+        let noise = model.humidity_noise;
+        let moisture = model.soil_moisture + rand::thread_rng().gen_range(-noise..=noise);
+        let SoilCalibration { dry, wet } = SoilCalibration::default();
+        dry - moisture / 100.0 * (dry - wet)
+    }
+
+    fn fault(&self) -> Option<Fault> {
+        let faults = self.0.lock().unwrap().model.faults;
+        faults.pick(&mut rand::thread_rng())
+    }
+}
+
+/// A virtual DHT sensor placed in the [`Environment`].
+struct Probe {
+    environment: Environment,
+    sensor_type: SensorType,
+}
+
+impl Responder for Probe {
+    fn respond(&mut self) -> Option<Response> {
+        let measurement = self.environment.sample();
+        let response = match self.sensor_type {
+            SensorType::Dht11 => Response::dht11(measurement),
+            _ => Response::dht22(measurement),
+        };
+
+        match self.environment.fault() {
+            Some(fault) => Some(response.with_fault(fault)),
+            None => Some(response),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+    use crate::{
+        gpio::Backend,
+        humidity::{Connection, ReadMode, RetryPolicy, Tracker},
+    };
+
+    #[test]
+    fn reads_data_lines_concurrently() {
+        let model = Model {
+            faults: FaultRates::default(),
+            ..Model::default()
+        };
+        let backend = Backend::simulated(Environment::new(model).unwrap());
+
+        let readers: Vec<_> = [4, 5]
+            .into_iter()
+            .map(|pin| {
+                let mut tracker = Tracker::new(
+                    format!("pin{pin}"),
+                    &backend,
+                    SensorType::Dht22,
+                    Connection::Pin(pin),
+                    ReadMode::Polling,
+                )
+                .unwrap()
+                // A read disturbed by the other line would be hidden by a retry
+                .with_retry_policy(RetryPolicy {
+                    max_attempts: 1,
+                    ..RetryPolicy::default()
+                });
+                thread::spawn(move || {
+                    for _ in 0..200 {
+                        if let Err(e) = tracker.read() {
+                            panic!("{e}");
+                        }
+                    }
+                })
+            })
+            .collect();
+        for reader in readers {
+            reader.join().unwrap();
+        }
+    }
+}